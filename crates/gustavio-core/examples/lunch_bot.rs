@@ -0,0 +1,87 @@
+//! Lunch poll bot: shows up as a regular peer and counts votes.
+//!
+//!     cargo run -p gustavio-core --example lunch_bot -- [username] [tcp_port]
+//!
+//! Commands (in a DM or in a group the bot was added to):
+//!   !almoco <lugar>   vote for a place
+//!   !placar           show the current tally
+//!   !zerar            start a new poll
+
+use gustavio_core::bot::{self, Bot, BotConfig, BotMessage};
+use gustavio_core::db::Database;
+use gustavio_core::node::NodeConfig;
+
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct LunchPoll {
+    /// voter peer_id -> place
+    votes: HashMap<String, String>,
+}
+
+impl LunchPoll {
+    fn handle(&mut self, msg: &BotMessage) -> Option<String> {
+        let text = msg.content.trim();
+        if let Some(place) = text.strip_prefix("!almoco") {
+            let place = place.trim().to_lowercase();
+            if place.is_empty() {
+                return Some("uso: !almoco <lugar>".into());
+            }
+            self.votes.insert(msg.from_id.clone(), place.clone());
+            return Some(format!("{} votou em {place}", msg.from_name));
+        }
+        match text {
+            "!placar" => Some(self.tally()),
+            "!zerar" => {
+                self.votes.clear();
+                Some("votação zerada".into())
+            }
+            _ => None,
+        }
+    }
+
+    fn tally(&self) -> String {
+        if self.votes.is_empty() {
+            return "nenhum voto ainda".into();
+        }
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for place in self.votes.values() {
+            *counts.entry(place).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts
+            .iter()
+            .map(|(place, n)| format!("{place}: {n}"))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+impl Bot for LunchPoll {
+    fn on_direct_message(&mut self, msg: &BotMessage) -> Option<String> {
+        self.handle(msg)
+    }
+
+    fn on_group_message(&mut self, msg: &BotMessage) -> Option<String> {
+        self.handle(msg)
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let username = args.next().unwrap_or_else(|| "almoco-bot".into());
+    let tcp_port = args.next().and_then(|p| p.parse().ok()).unwrap_or(9998);
+
+    let config = BotConfig {
+        username,
+        node: NodeConfig {
+            db_path: Database::data_dir().join("bots").join("lunch_bot.db"),
+            tcp_port,
+            ..NodeConfig::default()
+        },
+    };
+
+    bot::run(LunchPoll::default(), config).await;
+}
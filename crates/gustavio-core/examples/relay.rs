@@ -0,0 +1,29 @@
+//! Headless relay bridging LAN segments that don't hear each other's
+//! announces. Run one in each segment, pointed at the relays of the others:
+//!
+//!     cargo run -p gustavio-core --example relay -- <username> <host:port>...
+//!
+//! Peers on each side then show up on the other, and chat messages and their
+//! acks are passed across.
+
+use gustavio_core::db::Database;
+use gustavio_core::node::NodeConfig;
+use gustavio_core::relay::{self, RelayConfig};
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let username = args.next().unwrap_or_else(|| "relay".into());
+    let links = args.collect();
+
+    let config = RelayConfig {
+        username,
+        links,
+        node: NodeConfig {
+            db_path: Database::data_dir().join("relays").join("relay.db"),
+            ..NodeConfig::default()
+        },
+    };
+
+    relay::run(config).await;
+}
@@ -0,0 +1,240 @@
+//! Automatic away, for apps that turn it on with `NodeConfig::auto_away`.
+//! The app reports the user's keystrokes and clicks with
+//! `Command::Activity`; once none has come for the configured time our
+//! presence turns to away, and the next one turns it back to online. Away
+//! set this way isn't saved, and away, busy or do-not-disturb picked by
+//! hand is left alone. While we're away, by hand or not, the first DM from
+//! each peer is answered with the auto-reply, if one is set.
+
+use crate::db::MessageRow;
+use crate::events::{Event, Events};
+use crate::outbox::Outbox;
+use crate::profile;
+use crate::protocol::{Presence, TcpMessage, MAX_HOPS};
+use crate::state::SharedState;
+use crate::storage::Storage;
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Idle time before we turn away, unless set otherwise.
+pub const DEFAULT_AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// The handle network code reports DMs through. Kept in
+/// [`SharedState::away`].
+pub struct Away {
+    signals: mpsc::UnboundedSender<Signal>,
+    /// Set while we're away because the user is idle
+    idle_away: Arc<AtomicBool>,
+}
+
+enum Signal {
+    /// The user did something
+    Active,
+    /// A DM came in from this peer
+    Message(String),
+    Settings(Settings),
+}
+
+/// When to turn away and what to answer meanwhile.
+#[derive(Debug, Clone)]
+struct Settings {
+    /// `None` to never turn away by ourselves
+    after: Option<Duration>,
+    /// Empty for no auto-reply
+    reply: String,
+}
+
+impl Away {
+    pub fn active(&self) {
+        let _ = self.signals.send(Signal::Active);
+    }
+
+    pub fn message_from(&self, peer_id: &str) {
+        let _ = self.signals.send(Signal::Message(peer_id.to_string()));
+    }
+
+    /// Whether our presence is away because we turned it so while the user
+    /// was idle, rather than by their choice.
+    pub fn is_idle_away(&self) -> bool {
+        self.idle_away.load(Ordering::SeqCst)
+    }
+}
+
+async fn load(db: &Storage) -> Settings {
+    let after = match db.get_config("away_after").await {
+        Some(secs) => secs
+            .parse()
+            .ok()
+            .filter(|s| *s > 0)
+            .map(Duration::from_secs),
+        None => Some(DEFAULT_AWAY_AFTER),
+    };
+    Settings {
+        after,
+        reply: db.get_config("away_reply").await.unwrap_or_default(),
+    }
+}
+
+/// Tell the UI the settings, as saved.
+pub async fn send_settings(db: &Storage, events: &Events) {
+    let settings = load(db).await;
+    events.emit(Event::AutoAway {
+        after_secs: settings.after.map_or(0, |a| a.as_secs()),
+        reply: settings.reply,
+    });
+}
+
+/// Turn away after `after_secs` idle, 0 for never, and answer DMs meanwhile
+/// with `reply`, empty for not at all.
+pub async fn configure(
+    after_secs: u64,
+    reply: String,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) {
+    let reply = reply.trim().to_string();
+    db.set_config("away_after", &after_secs.to_string());
+    db.set_config("away_reply", &reply);
+    if let Some(away) = state.away.get() {
+        let _ = away.signals.send(Signal::Settings(Settings {
+            after: (after_secs > 0).then(|| Duration::from_secs(after_secs)),
+            reply: reply.clone(),
+        }));
+    }
+    events.emit(Event::AutoAway { after_secs, reply });
+}
+
+/// Start answering DMs while we're away through `outbox`, and with `auto`
+/// set, watching for idleness too. The returned task runs until aborted.
+pub async fn start(
+    peer_id: String,
+    auto: bool,
+    outbox: Outbox,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+) -> JoinHandle<()> {
+    let (signals, rx) = mpsc::unbounded_channel();
+    let idle_away = Arc::new(AtomicBool::new(false));
+    let _ = state.away.set(Away {
+        signals,
+        idle_away: idle_away.clone(),
+    });
+    let settings = load(&db).await;
+    let ctx = Context {
+        peer_id,
+        auto,
+        idle_away,
+        outbox,
+        state,
+        db,
+        events,
+    };
+    tokio::spawn(run(settings, rx, ctx))
+}
+
+/// What the task sends and shows with.
+#[derive(Clone)]
+struct Context {
+    peer_id: String,
+    /// Turn away when idle
+    auto: bool,
+    /// We turned away, so we turn back
+    idle_away: Arc<AtomicBool>,
+    outbox: Outbox,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+}
+
+async fn run(mut settings: Settings, mut rx: mpsc::UnboundedReceiver<Signal>, ctx: Context) {
+    let mut last_active = Instant::now();
+    // Past the idle time; cleared by the next activity
+    let mut idle = false;
+    // Peers answered since we last were around
+    let mut replied = HashSet::new();
+    loop {
+        let deadline = settings
+            .after
+            .filter(|_| ctx.auto && !idle)
+            .map(|a| last_active + a);
+        let signal = tokio::select! {
+            signal = rx.recv() => signal,
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                idle = true;
+                let ours = set_presence(Presence::Online, Presence::Away, &ctx).await;
+                ctx.idle_away.store(ours, Ordering::SeqCst);
+                continue;
+            }
+        };
+        match signal {
+            None => break,
+            Some(Signal::Active) => {
+                last_active = Instant::now();
+                idle = false;
+                replied.clear();
+                if ctx.idle_away.swap(false, Ordering::SeqCst) {
+                    set_presence(Presence::Away, Presence::Online, &ctx).await;
+                }
+            }
+            Some(Signal::Message(from)) => {
+                if ctx.state.profile.lock().await.presence != Presence::Away {
+                    replied.clear();
+                } else if !settings.reply.is_empty() && replied.insert(from.clone()) {
+                    let (ctx, text) = (ctx.clone(), settings.reply.clone());
+                    tokio::spawn(async move { reply(&from, &text, &ctx).await });
+                }
+            }
+            Some(Signal::Settings(new)) => settings = new,
+        }
+    }
+}
+
+/// Switch our presence from `from` to `to`, for this run only; false if it
+/// wasn't `from`.
+async fn set_presence(from: Presence, to: Presence, ctx: &Context) -> bool {
+    let mut profile = ctx.state.profile.lock().await.clone();
+    if profile.presence != from {
+        return false;
+    }
+    profile.presence = to;
+    profile::apply(profile, &ctx.state, &ctx.events).await;
+    true
+}
+
+async fn reply(to: &str, content: &str, ctx: &Context) {
+    let username = ctx.state.username.lock().await.clone();
+    let mut row = MessageRow {
+        id: uuid::Uuid::new_v4().to_string(),
+        conversation_id: to.to_string(),
+        from_id: ctx.peer_id.clone(),
+        from_name: username,
+        content: content.to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        is_group: false,
+        status: String::new(),
+    };
+    let msg = TcpMessage::DirectMessage {
+        id: row.id.clone(),
+        from_id: row.from_id.clone(),
+        from_name: row.from_name.clone(),
+        content: row.content.clone(),
+        timestamp: row.timestamp.clone(),
+        to: Some(to.to_string()),
+        hops: MAX_HOPS,
+    };
+    let sent = ctx.outbox.send(to, msg).await;
+    row.status = match sent.await {
+        Ok(Ok(())) => "sent".into(),
+        _ => "failed".into(),
+    };
+    ctx.db.insert_message(row.clone());
+    ctx.events.emit(Event::MessageSent(row));
+}
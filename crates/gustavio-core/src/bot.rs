@@ -0,0 +1,119 @@
+use crate::events::Event;
+use crate::node::{self, Command, NodeConfig};
+
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// A chat message delivered to a bot, from a DM or from a group it belongs to.
+#[derive(Debug, Clone)]
+pub struct BotMessage {
+    pub id: String,
+    pub from_id: String,
+    pub from_name: String,
+    /// Set when the message was sent to a group
+    pub group_id: Option<String>,
+    pub content: String,
+    pub timestamp: String,
+}
+
+/// Message handlers for a bot. Returning `Some(text)` replies in the same
+/// conversation: a DM back to the sender, or a message to the whole group.
+pub trait Bot: Send + 'static {
+    /// Called once networking is up. Keep the handle to send messages on your own.
+    fn on_start(&mut self, _handle: BotHandle) {}
+
+    fn on_direct_message(&mut self, _msg: &BotMessage) -> Option<String> {
+        None
+    }
+
+    fn on_group_message(&mut self, _msg: &BotMessage) -> Option<String> {
+        None
+    }
+}
+
+pub struct BotConfig {
+    /// Name shown in everyone's peer list
+    pub username: String,
+    /// Database path and ports; the TCP port must differ from other
+    /// instances on the same machine
+    pub node: NodeConfig,
+}
+
+/// Sends messages as the bot, through the same retry path as the desktop app.
+#[derive(Clone)]
+pub struct BotHandle {
+    peer_id: String,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl BotHandle {
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    pub fn send_direct(&self, peer_id: &str, content: &str) {
+        let _ = self.commands.send(Command::SendMessage {
+            peer_id: peer_id.to_string(),
+            content: content.to_string(),
+        });
+    }
+
+    pub fn send_group(&self, group_id: &str, content: &str) {
+        let _ = self.commands.send(Command::SendGroupMessage {
+            group_id: group_id.to_string(),
+            content: content.to_string(),
+        });
+    }
+}
+
+/// Run a bot as a normal peer: announce it over discovery, accept connections
+/// and call its handlers for every incoming chat message.
+pub async fn run<B: Bot>(mut bot: B, config: BotConfig) {
+    let (ev_tx, mut ev_rx) = mpsc::unbounded_channel::<Event>();
+    let commands = node::spawn(config.node, Arc::new(ev_tx)).commands;
+    let _ = commands.send(Command::SetUsername {
+        username: config.username,
+    });
+
+    let mut handle: Option<BotHandle> = None;
+    while let Some(event) = ev_rx.recv().await {
+        match event {
+            Event::ConfigLoaded {
+                peer_id,
+                username: Some(_),
+            } if handle.is_none() => {
+                let h = BotHandle {
+                    peer_id,
+                    commands: commands.clone(),
+                };
+                bot.on_start(h.clone());
+                handle = Some(h);
+            }
+            Event::MessageReceived(row) => {
+                let Some(h) = &handle else { continue };
+                let msg = BotMessage {
+                    id: row.id,
+                    from_id: row.from_id,
+                    from_name: row.from_name,
+                    group_id: row.is_group.then(|| row.conversation_id.clone()),
+                    content: row.content,
+                    timestamp: row.timestamp,
+                };
+                match &msg.group_id {
+                    Some(group_id) => {
+                        if let Some(reply) = bot.on_group_message(&msg) {
+                            h.send_group(group_id, &reply);
+                        }
+                    }
+                    None => {
+                        if let Some(reply) = bot.on_direct_message(&msg) {
+                            h.send_direct(&msg.from_id, &reply);
+                        }
+                    }
+                }
+            }
+            Event::Error(e) => eprintln!("Bot: {e}"),
+            _ => {}
+        }
+    }
+}
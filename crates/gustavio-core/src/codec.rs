@@ -0,0 +1,75 @@
+//! Wire format for peer connections: each frame is a 4-byte big-endian
+//! length followed by that many bytes of JSON-encoded `TcpMessage`.
+
+use crate::protocol::TcpMessage;
+
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest frame body we send or accept.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Encode `msg` as one frame and flush it.
+pub async fn write_frame<W>(writer: &mut W, msg: &TcpMessage) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let body =
+        serde_json::to_vec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if body.len() > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("frame of {} bytes exceeds {MAX_FRAME_SIZE}", body.len()),
+        ));
+    }
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+/// Read one frame body of at most `max_size` bytes. `Ok(None)` means the
+/// peer closed the connection. Oversized frames are an `InvalidData` error:
+/// the stream can't be trusted after one.
+pub async fn read_frame<R>(reader: &mut R, max_size: usize) -> io::Result<Option<Vec<u8>>>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if header[0] == b'{' {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "peer speaks the old newline-delimited protocol",
+        ));
+    }
+    let len = u32::from_be_bytes(header) as usize;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes exceeds {max_size}"),
+        ));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+/// Parse a frame body. Types added by newer peers come back as
+/// `TcpMessage::Unknown`; only malformed JSON is an error.
+pub fn decode(body: &[u8]) -> serde_json::Result<TcpMessage> {
+    serde_json::from_slice(body)
+}
+
+/// The `type` tag of a frame body, for logging ones we can't handle.
+pub fn message_type(body: &[u8]) -> String {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("type").and_then(|t| t.as_str()).map(str::to_string))
+        .unwrap_or_else(|| "?".into())
+}
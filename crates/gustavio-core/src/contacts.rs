@@ -0,0 +1,126 @@
+//! Peers we've met, kept after they go offline. A peer is saved as a
+//! contact when we first discover it and on its `Hello`, and stamped again
+//! when it leaves, so it can still be listed with when it was last seen.
+//! Direct messages to a contact that is offline are stored as `queued` and
+//! sent once it shows up again.
+
+use crate::db::MessageRow;
+use crate::discovery;
+use crate::events::{Event, Events};
+use crate::outbox::Outbox;
+use crate::protocol::{TcpMessage, MAX_HOPS};
+use crate::state::{PeerInfo, SharedState};
+use crate::storage::Storage;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Status of a message waiting for its recipient to come online.
+pub const QUEUED: &str = "queued";
+
+/// How often queued messages are checked against the peers online.
+const QUEUE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// True while we can hear of `peer_id` or hold a session with it.
+pub async fn online(peer_id: &str, state: &SharedState) -> bool {
+    state.peers.lock().await.contains_key(peer_id)
+        || state.connections.lock().await.contains_key(peer_id)
+}
+
+/// Save `peer_id` as a contact, seen now.
+pub async fn seen(peer_id: &str, username: &str, ip: &str, db: &Storage, events: &Events) {
+    db.upsert_peer(peer_id, username, ip);
+    send_contacts(db, events).await;
+}
+
+/// Stamp `peers`, which just went away, as last seen now.
+pub async fn left(peers: &[PeerInfo], db: &Storage, events: &Events) {
+    if peers.is_empty() {
+        return;
+    }
+    for p in peers {
+        db.upsert_peer(&p.peer_id, &p.username, p.ip());
+    }
+    send_contacts(db, events).await;
+}
+
+/// `peer_id` told us it now goes by `username`. Updates where we keep its
+/// name, and tells the UI unless its announces got there first.
+pub async fn rename(
+    peer_id: &str,
+    username: &str,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) {
+    let swap = |name: &mut String| {
+        (name != username).then(|| std::mem::replace(name, username.to_string()))
+    };
+    let in_session = match state.sessions.lock().await.get_mut(peer_id) {
+        Some(session) => swap(&mut session.username),
+        None => None,
+    };
+    let old = match state.peers.lock().await.get_mut(peer_id) {
+        Some(p) => swap(&mut p.username),
+        None => in_session,
+    };
+    let Some(old) = old else {
+        return;
+    };
+    db.rename_peer(peer_id, username);
+    events.emit(Event::PeerRenamed {
+        peer_id: peer_id.to_string(),
+        old,
+        new: username.to_string(),
+    });
+    discovery::send_peer_list(state, events).await;
+    send_contacts(db, events).await;
+}
+
+pub async fn send_contacts(db: &Storage, events: &Events) {
+    events.emit(Event::Contacts(db.get_contacts().await));
+}
+
+/// Send queued messages as their recipients come back. A send that fails
+/// leaves its message queued for the next round.
+pub async fn deliver_queued(outbox: Outbox, state: Arc<SharedState>, db: Storage, events: Events) {
+    let sending = Arc::new(Mutex::new(HashSet::new()));
+    loop {
+        tokio::time::sleep(QUEUE_INTERVAL).await;
+        let queued = db.get_queued_messages().await;
+        for row in queued {
+            if !online(&row.conversation_id, &state).await
+                || !sending.lock().await.insert(row.id.clone())
+            {
+                continue;
+            }
+            let sent = outbox.send(&row.conversation_id, message(&row)).await;
+            let (db, events, sending) = (db.clone(), events.clone(), sending.clone());
+            tokio::spawn(async move {
+                if let Ok(Ok(())) = sent.await {
+                    db.update_message_status(&row.id, "sent");
+                    events.emit(Event::MessageAck {
+                        message_id: row.id.clone(),
+                        status: "sent".into(),
+                    });
+                }
+                sending.lock().await.remove(&row.id);
+            });
+        }
+    }
+}
+
+/// The frame for a queued message.
+fn message(row: &MessageRow) -> TcpMessage {
+    TcpMessage::DirectMessage {
+        id: row.id.clone(),
+        from_id: row.from_id.clone(),
+        from_name: row.from_name.clone(),
+        content: row.content.clone(),
+        timestamp: row.timestamp.clone(),
+        to: Some(row.conversation_id.clone()),
+        hops: MAX_HOPS,
+    }
+}
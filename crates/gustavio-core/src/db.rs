@@ -0,0 +1,464 @@
+use crate::profile::Profile;
+use crate::workspace::Workspace;
+
+use rusqlite::{params, Connection};
+use std::path::{Path, PathBuf};
+
+pub struct Database {
+    conn: Connection,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MessageRow {
+    pub id: String,
+    pub conversation_id: String,
+    pub from_id: String,
+    pub from_name: String,
+    pub content: String,
+    pub timestamp: String,
+    pub is_group: bool,
+    pub status: String,
+}
+
+/// A peer we've met, online or not.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ContactRow {
+    pub peer_id: String,
+    pub username: String,
+    pub last_ip: String,
+    /// RFC 3339; when we last heard from the peer or saw it leave
+    pub last_seen: String,
+    /// From the last profile the peer sent
+    pub status: String,
+    pub presence: String,
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GroupRow {
+    pub group_id: String,
+    pub name: String,
+    pub creator_id: String,
+}
+
+impl Database {
+    /// Open (or create) the database at `path`, creating parent directories.
+    pub fn open_at(path: &Path) -> rusqlite::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let db = Self { conn };
+        db.init_schema()?;
+        Ok(db)
+    }
+
+    /// Per-user directory where gustavio keeps its data.
+    pub fn data_dir() -> PathBuf {
+        #[cfg(target_os = "windows")]
+        {
+            let base = std::env::var("APPDATA").unwrap_or_else(|_| ".".into());
+            PathBuf::from(base).join("gustavio")
+        }
+        #[cfg(target_os = "macos")]
+        {
+            let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+                .join("gustavio")
+        }
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        {
+            let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
+            PathBuf::from(home)
+                .join(".local")
+                .join("share")
+                .join("gustavio")
+        }
+    }
+
+    fn init_schema(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS config (
+                key   TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS peers (
+                peer_id   TEXT PRIMARY KEY,
+                username  TEXT NOT NULL,
+                last_ip   TEXT,
+                last_seen TEXT,
+                status    TEXT NOT NULL DEFAULT '',
+                presence  TEXT NOT NULL DEFAULT 'online',
+                avatar    TEXT
+            );
+            CREATE TABLE IF NOT EXISTS static_peers (
+                address TEXT PRIMARY KEY
+            );
+            CREATE TABLE IF NOT EXISTS workspaces (
+                name   TEXT PRIMARY KEY,
+                secret TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS messages (
+                id              TEXT PRIMARY KEY,
+                conversation_id TEXT NOT NULL,
+                from_id         TEXT NOT NULL,
+                from_name       TEXT NOT NULL,
+                content         TEXT NOT NULL,
+                timestamp       TEXT NOT NULL,
+                is_group        INTEGER NOT NULL DEFAULT 0,
+                status          TEXT NOT NULL DEFAULT 'sent'
+            );
+            CREATE INDEX IF NOT EXISTS idx_messages_conv
+                ON messages(conversation_id, timestamp);
+            CREATE TABLE IF NOT EXISTS groups (
+                group_id   TEXT PRIMARY KEY,
+                name       TEXT NOT NULL,
+                creator_id TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS group_members (
+                group_id TEXT NOT NULL,
+                peer_id  TEXT NOT NULL,
+                PRIMARY KEY (group_id, peer_id)
+            );
+            ",
+        )?;
+        // Columns added since the first release
+        self.add_column("peers", "status", "TEXT NOT NULL DEFAULT ''")?;
+        self.add_column("peers", "presence", "TEXT NOT NULL DEFAULT 'online'")?;
+        self.add_column("peers", "avatar", "TEXT")
+    }
+
+    /// Add `column` to `table` unless a database from an older version
+    /// already has it.
+    fn add_column(&self, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({table})"))?;
+        let has = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|r| r.ok())
+            .any(|name| name == column);
+        if !has {
+            self.conn
+                .execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))?;
+        }
+        Ok(())
+    }
+
+    // ── Config ───────────────────────────────────────────────
+
+    pub fn get_config(&self, key: &str) -> Option<String> {
+        self.conn
+            .query_row(
+                "SELECT value FROM config WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
+    pub fn set_config(&self, key: &str, value: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO config (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = ?2",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Our own peer id, generated on first run.
+    pub fn get_or_create_peer_id(&self) -> String {
+        match self.get_config("peer_id") {
+            Some(id) => id,
+            None => {
+                let id = uuid::Uuid::new_v4().to_string();
+                self.set_config("peer_id", &id).unwrap();
+                id
+            }
+        }
+    }
+
+    // ── Messages ─────────────────────────────────────────────
+
+    pub fn insert_message(&self, msg: &MessageRow) -> rusqlite::Result<()> {
+        Self::insert_with(&self.conn, msg)
+    }
+
+    /// Insert several messages in a single transaction, each on its own
+    /// savepoint so one that fails is left out alone. Returns the ones that
+    /// failed, by id.
+    pub fn insert_messages(
+        &self,
+        msgs: &[MessageRow],
+    ) -> rusqlite::Result<Vec<(String, rusqlite::Error)>> {
+        let mut tx = self.conn.unchecked_transaction()?;
+        let mut failed = Vec::new();
+        for msg in msgs {
+            let one = tx.savepoint()?;
+            match Self::insert_with(&one, msg) {
+                Ok(()) => one.commit()?,
+                // Dropping the savepoint rolls it back
+                Err(e) => failed.push((msg.id.clone(), e)),
+            }
+        }
+        tx.commit()?;
+        Ok(failed)
+    }
+
+    fn insert_with(conn: &Connection, msg: &MessageRow) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT OR IGNORE INTO messages
+             (id, conversation_id, from_id, from_name, content, timestamp, is_group, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                msg.id,
+                msg.conversation_id,
+                msg.from_id,
+                msg.from_name,
+                msg.content,
+                msg.timestamp,
+                msg.is_group as i32,
+                msg.status,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_history(&self, conversation_id: &str, limit: i64) -> Vec<MessageRow> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, conversation_id, from_id, from_name, content, timestamp, is_group, status
+                 FROM messages
+                 WHERE conversation_id = ?1
+                 ORDER BY timestamp ASC
+                 LIMIT ?2",
+            )
+            .unwrap();
+        stmt.query_map(params![conversation_id, limit], Self::message_row)
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    /// Direct messages waiting for their recipient to come online, oldest
+    /// first.
+    pub fn get_queued_messages(&self) -> Vec<MessageRow> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, conversation_id, from_id, from_name, content, timestamp, is_group, status
+                 FROM messages
+                 WHERE status = 'queued' AND is_group = 0
+                 ORDER BY timestamp ASC",
+            )
+            .unwrap();
+        stmt.query_map([], Self::message_row)
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    fn message_row(row: &rusqlite::Row) -> rusqlite::Result<MessageRow> {
+        Ok(MessageRow {
+            id: row.get(0)?,
+            conversation_id: row.get(1)?,
+            from_id: row.get(2)?,
+            from_name: row.get(3)?,
+            content: row.get(4)?,
+            timestamp: row.get(5)?,
+            is_group: row.get::<_, i32>(6)? != 0,
+            status: row.get(7)?,
+        })
+    }
+
+    pub fn update_message_status(&self, id: &str, status: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET status = ?2 WHERE id = ?1",
+            params![id, status],
+        )?;
+        Ok(())
+    }
+
+    // ── Peers ────────────────────────────────────────────────
+
+    pub fn upsert_peer(&self, peer_id: &str, username: &str, ip: &str) -> rusqlite::Result<()> {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.conn.execute(
+            "INSERT INTO peers (peer_id, username, last_ip, last_seen) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(peer_id) DO UPDATE SET username=?2,
+                 last_ip=COALESCE(NULLIF(?3, ''), last_ip), last_seen=?4",
+            params![peer_id, username, ip, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn rename_peer(&self, peer_id: &str, username: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE peers SET username = ?2 WHERE peer_id = ?1",
+            params![peer_id, username],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_peer_profile(&self, peer_id: &str, profile: &Profile) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE peers SET status = ?2, presence = ?3, avatar = ?4 WHERE peer_id = ?1",
+            params![
+                peer_id,
+                profile.status,
+                profile.presence.as_str(),
+                profile.avatar
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_contacts(&self) -> Vec<ContactRow> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT peer_id, username, COALESCE(last_ip, ''), COALESCE(last_seen, ''),
+                        status, presence, avatar
+                 FROM peers ORDER BY username",
+            )
+            .unwrap();
+        stmt.query_map([], |row| {
+            Ok(ContactRow {
+                peer_id: row.get(0)?,
+                username: row.get(1)?,
+                last_ip: row.get(2)?,
+                last_seen: row.get(3)?,
+                status: row.get(4)?,
+                presence: row.get(5)?,
+                avatar: row.get(6)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    /// Remember a peer added by `host:port`.
+    pub fn add_static_peer(&self, address: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO static_peers (address) VALUES (?1)",
+            params![address],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_static_peer(&self, address: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "DELETE FROM static_peers WHERE address = ?1",
+            params![address],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_static_peers(&self) -> Vec<String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT address FROM static_peers ORDER BY address")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    // ── Workspaces ───────────────────────────────────────────
+
+    /// Join a workspace, or change the secret of one we're in.
+    pub fn add_workspace(&self, name: &str, secret: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO workspaces (name, secret) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET secret=?2",
+            params![name, secret],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_workspace(&self, name: &str) -> rusqlite::Result<()> {
+        self.conn
+            .execute("DELETE FROM workspaces WHERE name = ?1", params![name])?;
+        Ok(())
+    }
+
+    pub fn get_workspaces(&self) -> Vec<Workspace> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, secret FROM workspaces ORDER BY name")
+            .unwrap();
+        stmt.query_map([], |row| {
+            Ok(Workspace {
+                name: row.get(0)?,
+                secret: row.get(1)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    // ── Groups ───────────────────────────────────────────────
+
+    pub fn create_group(
+        &self,
+        group_id: &str,
+        name: &str,
+        creator_id: &str,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO groups (group_id, name, creator_id) VALUES (?1, ?2, ?3)",
+            params![group_id, name, creator_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_group_member(&self, group_id: &str, peer_id: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO group_members (group_id, peer_id) VALUES (?1, ?2)",
+            params![group_id, peer_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_group_member(&self, group_id: &str, peer_id: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "DELETE FROM group_members WHERE group_id = ?1 AND peer_id = ?2",
+            params![group_id, peer_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_groups(&self) -> Vec<GroupRow> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT group_id, name, creator_id FROM groups")
+            .unwrap();
+        stmt.query_map([], |row| {
+            Ok(GroupRow {
+                group_id: row.get(0)?,
+                name: row.get(1)?,
+                creator_id: row.get(2)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    pub fn get_group_members(&self, group_id: &str) -> Vec<String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT peer_id FROM group_members WHERE group_id = ?1")
+            .unwrap();
+        stmt.query_map(params![group_id], |row| row.get(0))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+}
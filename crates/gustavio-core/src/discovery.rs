@@ -0,0 +1,538 @@
+use crate::contacts;
+use crate::events::{Event, Events, PeerSummary};
+use crate::mdns::{self, Mdns};
+use crate::netif::Interface;
+use crate::protocol::{Presence, UdpPacket};
+use crate::state::{PeerInfo, SharedState};
+use crate::storage::Storage;
+use crate::transport::DatagramSocket;
+use crate::workspace;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+pub const DISCOVERY_PORT: u16 = 5555;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(3);
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+const GOSSIP_INTERVAL: Duration = Duration::from_secs(5);
+/// Most addresses we keep for one peer; announces are untrusted input.
+const MAX_ADDRS: usize = 8;
+/// Administratively scoped group, private to the organisation's network.
+pub const MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 77, 77);
+/// Link-local group, reaching IPv6-only segments.
+pub const MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x7777);
+
+/// Where we announce ourselves and how quickly silent peers are dropped.
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    /// UDP port we listen on for announces
+    pub port: u16,
+    /// Where our announces are sent. Multicast groups among them are joined
+    /// on every interface; by default our IPv4 and IPv6 groups
+    pub announce_to: Vec<SocketAddr>,
+    /// Router hops our multicast announces may cross; 1 stays on the LAN
+    pub multicast_ttl: u32,
+    /// Also announce to the directed broadcast address of each IPv4
+    /// interface, which reaches subnets the limited broadcast doesn't
+    pub subnet_broadcast: bool,
+    /// Also advertise and browse `_gustavio._tcp` over mDNS, where the
+    /// transport allows it
+    pub mdns: bool,
+    /// How often connected peers are sent our peer table and routes
+    pub gossip_interval: Duration,
+    pub announce_interval: Duration,
+    /// A peer not heard from for this long is removed
+    pub peer_timeout: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            port: DISCOVERY_PORT,
+            announce_to: vec![
+                SocketAddrV4::new(MULTICAST_V4, DISCOVERY_PORT).into(),
+                SocketAddrV6::new(MULTICAST_V6, DISCOVERY_PORT, 0, 0).into(),
+            ],
+            multicast_ttl: 1,
+            subnet_broadcast: true,
+            mdns: true,
+            gossip_interval: GOSSIP_INTERVAL,
+            announce_interval: ANNOUNCE_INTERVAL,
+            peer_timeout: PEER_TIMEOUT,
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    /// Announce on the IPv4 limited broadcast address only, for networks
+    /// where multicast doesn't get through.
+    pub fn broadcast() -> Self {
+        Self {
+            announce_to: vec![broadcast_target(DISCOVERY_PORT)],
+            ..Self::default()
+        }
+    }
+}
+
+fn broadcast_target(port: u16) -> SocketAddr {
+    SocketAddrV4::new(Ipv4Addr::BROADCAST, port).into()
+}
+
+/// Running discovery tasks, kept so we can say goodbye on the way out.
+pub struct Discovery {
+    peer_id: String,
+    socket: Arc<dyn DatagramSocket>,
+    state: Arc<SharedState>,
+    /// As of the last announce round
+    targets: Arc<Mutex<Vec<SocketAddr>>>,
+    broadcast_port: Option<u16>,
+    mdns: Option<Mdns>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Discovery {
+    /// Advertise under `username` on mDNS too; our announces pick it up
+    /// from the shared state by themselves.
+    pub fn rename(&mut self, username: &str) {
+        if let Some(mdns) = &mut self.mdns {
+            mdns.rename(username);
+        }
+    }
+
+    /// Stop announcing and tell peers we're leaving, so they drop us now
+    /// instead of after `peer_timeout`.
+    pub async fn goodbye(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Some(mdns) = self.mdns {
+            mdns.stop().await;
+        }
+        let pkt = UdpPacket::Goodbye {
+            peer_id: self.peer_id,
+        };
+        let ours = self.state.workspaces.lock().await.clone();
+        let ifaces = self.state.transport.interfaces();
+        let targets = self.targets.lock().unwrap().clone();
+        let round = round_targets(&targets, self.broadcast_port, &ifaces);
+        for pkt in workspace::seal(&ours, pkt) {
+            let data = serde_json::to_vec(&pkt).unwrap();
+            for target in &round {
+                let _ = self.socket.send_to(&data, *target).await;
+            }
+        }
+    }
+}
+
+/// Start the UDP discovery system: announce ourselves + listen for others.
+pub async fn run(
+    peer_id: String,
+    tcp_port: u16,
+    config: DiscoveryConfig,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+) -> Discovery {
+    // Create a socket that can broadcast and join multicast groups
+    let socket = state
+        .transport
+        .bind_datagram(config.port)
+        .await
+        .expect("Failed to create UDP socket");
+
+    let announce_to = Arc::new(Mutex::new(join_groups(&*socket, &config, &[])));
+
+    // Spawn the announce loop
+    let s = socket.clone();
+    let pid = peer_id.clone();
+    let targets = announce_to.clone();
+    let broadcast_port = config.subnet_broadcast.then_some(config.port);
+    let interval = config.announce_interval;
+    let cfg = config.clone();
+    let st = state.clone();
+    let announcer = tokio::spawn(async move {
+        loop {
+            // Interfaces come and go (laptops roam), so look every round,
+            // and follow them with our multicast groups
+            let ifaces = st.transport.interfaces();
+            let was = targets.lock().unwrap().clone();
+            let now = join_groups(&*s, &cfg, &was);
+            *targets.lock().unwrap() = now.clone();
+            let ours = st.workspaces.lock().await.clone();
+            let announce = UdpPacket::Announce {
+                peer_id: pid.clone(),
+                // Read every round, so a rename goes out with the next one
+                username: st.username.lock().await.clone(),
+                tcp_port,
+                addrs: reachable_addrs(&ifaces),
+                via: None,
+                presence: st.profile.lock().await.presence,
+            };
+            let mut pkts = workspace::seal(&ours, announce);
+            if let Some(relay) = st.relay.get() {
+                pkts.extend(relay.announces(&ours, &st).await);
+            }
+            let round = round_targets(&now, broadcast_port, &ifaces);
+            for pkt in pkts {
+                let data = serde_json::to_vec(&pkt).unwrap();
+                for target in &round {
+                    let _ = s.send_to(&data, *target).await;
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+
+    // Spawn the listener loop
+    let s = socket.clone();
+    let my_id = peer_id.clone();
+    let st = state.clone();
+    let d = db.clone();
+    let ev = events.clone();
+    let listener = tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (len, addr) = match s.recv_from(&mut buf).await {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let Ok(pkt) = serde_json::from_slice::<UdpPacket>(&buf[..len]) else {
+                continue;
+            };
+            let ours = st.workspaces.lock().await.clone();
+            let Some((pkt, ws)) = workspace::open(&ours, pkt) else {
+                continue;
+            };
+            let ws: Vec<String> = ws.into_iter().collect();
+            match pkt {
+                UdpPacket::Announce {
+                    peer_id,
+                    username,
+                    tcp_port,
+                    addrs,
+                    via: Some(via),
+                    presence,
+                } => {
+                    // Relays learn other segments over their links, not
+                    // from each other's announces
+                    if peer_id == my_id || via == my_id || st.relay.get().is_some() {
+                        continue;
+                    }
+                    let mut peers = st.peers.lock().await;
+                    // Hearing the peer itself beats hearing of it
+                    if peers.get(&peer_id).is_some_and(|p| !p.indirect) {
+                        continue;
+                    }
+                    let known = peers.get(&peer_id);
+                    let old_name = known.map(|p| p.username.clone());
+                    let addrs = merge_addrs(known.map_or(&[][..], |p| &p.addrs[..]), addrs);
+                    let workspaces = joined(known, &ws);
+                    let info = PeerInfo {
+                        peer_id: peer_id.clone(),
+                        username,
+                        addrs,
+                        tcp_port,
+                        last_seen: Instant::now(),
+                        indirect: true,
+                        via: Some(via),
+                        workspaces,
+                    };
+                    peers.insert(peer_id, info.clone());
+                    drop(peers);
+                    heard(&info.peer_id, presence, &st).await;
+                    note(&info, old_name, &d, &ev).await;
+                    send_peer_list(&st, &ev).await;
+                }
+                UdpPacket::Announce {
+                    peer_id,
+                    username,
+                    tcp_port,
+                    addrs,
+                    via: None,
+                    presence,
+                } => {
+                    if peer_id == my_id {
+                        continue;
+                    }
+                    let mut peers = st.peers.lock().await;
+                    let known = peers.get(&peer_id);
+                    let old_name = known.map(|p| p.username.clone());
+                    let fresh = std::iter::once(host_of(addr)).chain(addrs);
+                    let addrs = merge_addrs(known.map_or(&[][..], |p| &p.addrs[..]), fresh);
+                    let workspaces = joined(known, &ws);
+                    let info = PeerInfo {
+                        peer_id: peer_id.clone(),
+                        username,
+                        addrs,
+                        tcp_port,
+                        last_seen: Instant::now(),
+                        indirect: false,
+                        via: None,
+                        workspaces,
+                    };
+                    peers.insert(peer_id, info.clone());
+                    drop(peers);
+                    heard(&info.peer_id, presence, &st).await;
+                    note(&info, old_name, &d, &ev).await;
+                    send_peer_list(&st, &ev).await;
+                }
+                UdpPacket::Goodbye { peer_id } => {
+                    let mut peers = st.peers.lock().await;
+                    // Gone from the workspace it signed for; from the
+                    // table once it's left all we share
+                    let mut gone = Vec::new();
+                    if let Some(p) = peers.get_mut(&peer_id) {
+                        p.workspaces.retain(|w| !ws.contains(w));
+                        if p.workspaces.is_empty() {
+                            gone.extend(peers.remove(&peer_id));
+                        }
+                    }
+                    drop(peers);
+                    contacts::left(&gone, &d, &ev).await;
+                    send_peer_list(&st, &ev).await;
+                }
+                UdpPacket::Signed { .. } => {}
+            }
+        }
+    });
+
+    let mdns = if config.mdns && state.transport.supports_mdns() {
+        mdns::run(
+            peer_id.clone(),
+            state.username.lock().await.clone(),
+            tcp_port,
+            config.announce_interval,
+            state.clone(),
+            db.clone(),
+            events.clone(),
+        )
+    } else {
+        None
+    };
+
+    // Spawn the cleanup loop (remove stale peers)
+    let st = state.clone();
+    let ev = events.clone();
+    let timeout = config.peer_timeout;
+    let cleanup = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(timeout / 2).await;
+            let mut peers = st.peers.lock().await;
+            let stale: Vec<String> = peers
+                .values()
+                .filter(|p| p.last_seen.elapsed() >= timeout)
+                .map(|p| p.peer_id.clone())
+                .collect();
+            let gone: Vec<PeerInfo> = stale.iter().filter_map(|id| peers.remove(id)).collect();
+            drop(peers);
+            if !gone.is_empty() {
+                contacts::left(&gone, &db, &ev).await;
+                send_peer_list(&st, &ev).await;
+            }
+        }
+    });
+
+    Discovery {
+        peer_id,
+        socket,
+        state,
+        targets: announce_to,
+        broadcast_port,
+        mdns,
+        tasks: vec![announcer, listener, cleanup],
+    }
+}
+
+/// Note the presence a peer announced.
+async fn heard(peer_id: &str, presence: Presence, state: &SharedState) {
+    let mut profiles = state.profiles.lock().await;
+    profiles.entry(peer_id.to_string()).or_default().presence = presence;
+}
+
+/// Save a peer we hadn't heard of, or that goes by a new name, as a
+/// contact; `old_name` is what we knew it as.
+pub(crate) async fn note(info: &PeerInfo, old_name: Option<String>, db: &Storage, events: &Events) {
+    if old_name.as_deref() == Some(info.username.as_str()) {
+        return;
+    }
+    contacts::seen(&info.peer_id, &info.username, info.ip(), db, events).await;
+    if let Some(old) = old_name {
+        events.emit(Event::PeerRenamed {
+            peer_id: info.peer_id.clone(),
+            old,
+            new: info.username.clone(),
+        });
+    }
+}
+
+/// The workspaces of `known` plus the one a packet about it was signed for.
+fn joined(known: Option<&PeerInfo>, signed_for: &[String]) -> Vec<String> {
+    let mut workspaces = known.map_or_else(Vec::new, |p| p.workspaces.clone());
+    workspace::merge(&mut workspaces, signed_for);
+    workspaces
+}
+
+/// The address to reach a peer at, given where its announce came from.
+/// IPv6 link-local sources keep their interface as `%scope`.
+fn host_of(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => format!("{}%{}", v6.ip(), v6.scope_id()),
+        _ => addr.ip().to_string(),
+    }
+}
+
+/// Our addresses worth announcing, the likeliest to reach us first and no
+/// more than peers keep. IPv6 link-local ones are left out: their scope
+/// means nothing on the receiving host, which sees our source anyway.
+fn reachable_addrs(ifaces: &[Interface]) -> Vec<String> {
+    let mut ifaces: Vec<&Interface> = ifaces
+        .iter()
+        .filter(|i| !matches!(i.addr, IpAddr::V6(v6) if v6.is_unicast_link_local()))
+        .collect();
+    ifaces.sort_by_key(|i| reach_rank(i));
+    ifaces
+        .into_iter()
+        .take(MAX_ADDRS)
+        .map(|i| i.addr.to_string())
+        .collect()
+}
+
+/// Name prefixes of the interfaces containers, VMs and VPNs add, which
+/// other hosts on the LAN mostly can't reach.
+const VIRTUAL_IFACES: &[&str] = &[
+    "docker",
+    "br-",
+    "veth",
+    "virbr",
+    "vboxnet",
+    "vmnet",
+    "lxc",
+    "lxd",
+    "cni",
+    "podman",
+    "tun",
+    "tap",
+    "wg",
+    "utun",
+    "zt",
+    "tailscale",
+];
+
+/// Lower for addresses peers are likelier to reach: IPv4 on a LAN with a
+/// broadcast address, then other IPv4 and IPv6, then virtual interfaces.
+fn reach_rank(iface: &Interface) -> u8 {
+    if VIRTUAL_IFACES.iter().any(|p| iface.name.starts_with(p)) {
+        2
+    } else if iface.broadcast.is_some() {
+        0
+    } else {
+        1
+    }
+}
+
+/// The configured targets plus, with a `broadcast_port`, each interface's
+/// subnet broadcast address.
+fn round_targets(
+    targets: &[SocketAddr],
+    broadcast_port: Option<u16>,
+    ifaces: &[Interface],
+) -> Vec<SocketAddr> {
+    let mut all = targets.to_vec();
+    if let Some(port) = broadcast_port {
+        for bcast in ifaces.iter().filter_map(|i| i.broadcast) {
+            let target = SocketAddrV4::new(bcast, port).into();
+            if !all.contains(&target) {
+                all.push(target);
+            }
+        }
+    }
+    all
+}
+
+/// A peer's candidate addresses after hearing it's at `fresh`, best first:
+/// the ones we already had keep their order (the front one last worked),
+/// then the new ones. Addresses it no longer announces are dropped.
+pub(crate) fn merge_addrs(
+    known: &[String],
+    fresh: impl IntoIterator<Item = String>,
+) -> Vec<String> {
+    let fresh: Vec<String> = fresh.into_iter().take(MAX_ADDRS).collect();
+    let mut addrs: Vec<String> = known
+        .iter()
+        .filter(|a| fresh.contains(a))
+        .cloned()
+        .collect();
+    for addr in fresh {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs
+}
+
+/// Join the multicast groups among our targets on the interfaces up now and
+/// return the targets we can announce to. With groups configured but none
+/// joinable (no interface takes multicast) we fall back to broadcast. `was`
+/// is what the last round returned, so only changes are logged.
+fn join_groups(
+    socket: &dyn DatagramSocket,
+    config: &DiscoveryConfig,
+    was: &[SocketAddr],
+) -> Vec<SocketAddr> {
+    let mut targets = Vec::new();
+    let (mut groups, mut joined) = (0, 0);
+    for target in &config.announce_to {
+        if !target.ip().is_multicast() {
+            targets.push(*target);
+            continue;
+        }
+        groups += 1;
+        match socket.join_multicast(target.ip(), config.multicast_ttl) {
+            Ok(()) => {
+                if !was.is_empty() && !was.contains(target) {
+                    eprintln!("Joined multicast group {}", target.ip());
+                }
+                joined += 1;
+                targets.push(*target);
+            }
+            Err(e) if was.is_empty() || was.contains(target) => {
+                eprintln!("Can't join multicast group {}: {e}", target.ip())
+            }
+            Err(_) => {}
+        }
+    }
+    if groups > 0 && joined == 0 {
+        let broadcast = broadcast_target(config.port);
+        if !was.contains(&broadcast) {
+            eprintln!("No multicast group joined; falling back to broadcast");
+        }
+        targets.push(broadcast);
+    }
+    targets
+}
+
+pub async fn send_peer_list(state: &SharedState, events: &Events) {
+    let peers = state.peers.lock().await;
+    let profiles = state.profiles.lock().await;
+    let list: Vec<PeerSummary> = peers
+        .values()
+        .map(|p| {
+            let profile = profiles.get(&p.peer_id).cloned().unwrap_or_default();
+            PeerSummary {
+                peer_id: p.peer_id.clone(),
+                username: p.username.clone(),
+                ip: p.ip().to_string(),
+                indirect: p.indirect,
+                workspaces: p.workspaces.clone(),
+                presence: profile.presence,
+                status: profile.status,
+            }
+        })
+        .collect();
+    drop(profiles);
+    drop(peers);
+    events.emit(Event::PeerList(list));
+}
@@ -0,0 +1,124 @@
+use crate::db::{ContactRow, GroupRow, MessageRow};
+use crate::profile::Profile;
+use crate::protocol::Presence;
+
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PeerSummary {
+    pub peer_id: String,
+    pub username: String,
+    pub ip: String,
+    /// Learnt through another peer, not heard directly
+    pub indirect: bool,
+    /// Workspaces the peer is listed under; empty while we're in none
+    pub workspaces: Vec<String>,
+    pub presence: Presence,
+    /// Empty until the peer sends its profile
+    pub status: String,
+}
+
+/// A peer added by address, and whether it answered our last probe.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StaticPeer {
+    /// `host:port` as the user typed it
+    pub address: String,
+    /// Learnt from the `Hello` of the first probe that got through
+    pub peer_id: Option<String>,
+    pub username: Option<String>,
+    #[serde(flatten)]
+    pub reachability: Reachability,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Reachability {
+    /// Not probed yet
+    Probing,
+    Reachable,
+    Unreachable {
+        error: String,
+    },
+}
+
+/// Where our session with a peer stands.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum LinkState {
+    Connecting,
+    Connected,
+    /// The last attempt failed; the next one starts after `retry_in_ms`
+    BackingOff {
+        retry_in_ms: u64,
+    },
+    Disconnected,
+}
+
+/// Everything the core reports to its consumer (desktop UI, bot, test).
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Identity loaded at startup or after the username was set
+    ConfigLoaded {
+        peer_id: String,
+        username: Option<String>,
+    },
+    /// Current set of discovered peers
+    PeerList(Vec<PeerSummary>),
+    /// Every peer we've met, for listing the offline ones too
+    Contacts(Vec<ContactRow>),
+    /// Our profile, on startup and once changed
+    MyProfile(Profile),
+    /// Our auto-away settings; `after_secs` is 0 when it's off and `reply`
+    /// empty when there's none
+    AutoAway {
+        after_secs: u64,
+        reply: String,
+    },
+    /// A peer now goes by another name
+    PeerRenamed {
+        peer_id: String,
+        old: String,
+        new: String,
+    },
+    /// Names of the workspaces we're in
+    Workspaces(Vec<String>),
+    /// Code for an invite we issued, to hand to whoever it's for
+    InviteCreated(String),
+    /// The peers added by address and how their probes went
+    StaticPeers(Vec<StaticPeer>),
+    /// A chat message arrived from another peer
+    MessageReceived(MessageRow),
+    /// We sent a message (status is "sent" or "failed")
+    MessageSent(MessageRow),
+    /// The recipient acknowledged one of our messages
+    MessageAck {
+        message_id: String,
+        status: String,
+    },
+    History(Vec<MessageRow>),
+    GroupList(Vec<GroupRow>),
+    /// A group we created locally is ready
+    GroupCreated(String),
+    /// Our session with a peer changed state
+    Link {
+        peer_id: String,
+        state: LinkState,
+    },
+    Error(String),
+    /// The node finished shutting down; nothing follows this
+    Stopped,
+}
+
+/// Receives core events. Implementations must not block.
+pub trait EventSink: Send + Sync + 'static {
+    fn emit(&self, event: Event);
+}
+
+impl EventSink for mpsc::UnboundedSender<Event> {
+    fn emit(&self, event: Event) {
+        let _ = self.send(event);
+    }
+}
+
+pub type Events = Arc<dyn EventSink>;
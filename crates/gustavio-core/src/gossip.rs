@@ -0,0 +1,205 @@
+//! Peer table gossip. Connected peers send each other their tables now and
+//! again, so when broadcast only half works a peer whose announces never
+//! reach us is still learnt through someone who hears it. Such entries are
+//! marked indirect until we reach the peer ourselves, which we keep trying.
+
+use crate::discovery::{self, merge_addrs};
+use crate::events::Events;
+use crate::network;
+use crate::protocol::{KnownPeer, TcpMessage, CAP_GOSSIP, CAP_ROUTE};
+use crate::reconnect::Reconnector;
+use crate::state::{PeerInfo, SharedState};
+use crate::storage::Storage;
+use crate::workspace;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Most entries we send or accept in one table.
+const MAX_ENTRIES: usize = 256;
+
+/// Every `interval`: keep connected peers listed, send our table to the
+/// ones that gossip, and dial the indirect peers we aren't connected to.
+pub async fn run(links: Reconnector, interval: Duration) {
+    let mut dials = JoinSet::new();
+    let mut dialing = HashSet::new();
+    loop {
+        tokio::time::sleep(interval).await;
+        while let Some(Ok(peer_id)) = dials.try_join_next() {
+            dialing.remove(&peer_id);
+        }
+
+        let state = links.state();
+        let connected: Vec<String> = state.connections.lock().await.keys().cloned().collect();
+        // (gossips, workspaces) of each connected peer
+        let sessions: HashMap<String, (bool, Vec<String>)> = {
+            let sessions = state.sessions.lock().await;
+            connected
+                .iter()
+                .filter_map(|id| sessions.get(id).map(|s| (id, s)))
+                .map(|(id, s)| {
+                    let gossips = s.capabilities.iter().any(|c| c == CAP_GOSSIP);
+                    (id.clone(), (gossips, s.workspaces.clone()))
+                })
+                .collect()
+        };
+        // A live session is as good as an announce
+        let (table, unreached) = {
+            let mut peers = state.peers.lock().await;
+            for peer_id in &connected {
+                if let Some(p) = peers.get_mut(peer_id) {
+                    p.last_seen = Instant::now();
+                    p.indirect = false;
+                    p.via = None;
+                    if let Some((_, workspaces)) = sessions.get(peer_id) {
+                        workspace::merge(&mut p.workspaces, workspaces);
+                    }
+                }
+            }
+            // Peers we reach through another are that peer's to tell of
+            let table: Vec<KnownPeer> = peers
+                .values()
+                .filter(|p| p.via.is_none())
+                .take(MAX_ENTRIES)
+                .map(entry)
+                .collect();
+            let unreached: Vec<String> = peers
+                .values()
+                .filter(|p| p.indirect && !p.addrs.is_empty())
+                .filter(|p| !connected.contains(&p.peer_id))
+                .map(|p| p.peer_id.clone())
+                .collect();
+            (table, unreached)
+        };
+
+        for (peer_id, (gossips, theirs)) in &sessions {
+            if !gossips {
+                continue;
+            }
+            // Only what the peer shares with us, tagged as such
+            let msg = TcpMessage::PeerTable {
+                peers: table
+                    .iter()
+                    .filter(|p| p.peer_id != *peer_id)
+                    .filter_map(|p| {
+                        let workspaces = workspace::narrow(&p.workspaces, theirs)?;
+                        Some(KnownPeer {
+                            workspaces,
+                            ..p.clone()
+                        })
+                    })
+                    .collect(),
+            };
+            let _ = network::send_to_peer(peer_id, &msg, state).await;
+        }
+
+        for peer_id in unreached {
+            if !dialing.insert(peer_id.clone()) {
+                continue;
+            }
+            let links = links.clone();
+            dials.spawn(async move {
+                if let Err(e) = links.connect(&peer_id).await {
+                    eprintln!("Indirect peer {peer_id} unreachable: {e}");
+                }
+                peer_id
+            });
+        }
+    }
+}
+
+fn entry(p: &PeerInfo) -> KnownPeer {
+    KnownPeer {
+        peer_id: p.peer_id.clone(),
+        username: p.username.clone(),
+        // Interface scopes mean nothing on another host
+        addrs: p
+            .addrs
+            .iter()
+            .filter(|a| !a.contains('%'))
+            .cloned()
+            .collect(),
+        tcp_port: p.tcp_port,
+        last_seen_ms: p.last_seen.elapsed().as_millis() as u64,
+        workspaces: p.workspaces.clone(),
+    }
+}
+
+/// Fold the table `from` sent us into ours. Peers we hear ourselves are left
+/// alone; the rest keep whichever sighting is newest. Sightings only ever
+/// age as they're passed on, so a peer that left can't be kept alive by
+/// gossip going round in circles. Peers learnt from a peer that routes are
+/// reached through it until we reach them ourselves. Entries for workspaces
+/// `from` doesn't share with us are dropped. New peers and new names are
+/// saved and shown as for announces.
+pub async fn merge(
+    from: &str,
+    table: &[KnownPeer],
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) {
+    let (via, theirs) = match state.sessions.lock().await.get(from) {
+        Some(s) => (
+            s.capabilities.iter().any(|c| c == CAP_ROUTE),
+            s.workspaces.clone(),
+        ),
+        None => return,
+    };
+    let via = via.then(|| from.to_string());
+    let now = Instant::now();
+    let mut changed = false;
+    // Peers to note as announces are, with the name we knew them by
+    let mut noted = Vec::new();
+    let mut peers = state.peers.lock().await;
+    for known in table.iter().take(MAX_ENTRIES) {
+        if known.peer_id == from {
+            continue;
+        }
+        let Some(workspaces) = workspace::narrow(&known.workspaces, &theirs) else {
+            continue;
+        };
+        let seen = now
+            .checked_sub(Duration::from_millis(known.last_seen_ms))
+            .unwrap_or(now);
+        match peers.get_mut(&known.peer_id) {
+            Some(p) if !p.indirect || p.last_seen >= seen => {}
+            Some(p) => {
+                let old = std::mem::replace(&mut p.username, known.username.clone());
+                p.addrs = merge_addrs(&p.addrs, known.addrs.iter().cloned());
+                p.tcp_port = known.tcp_port;
+                p.last_seen = seen;
+                p.via = via.clone();
+                workspace::merge(&mut p.workspaces, &workspaces);
+                if old != p.username {
+                    changed = true;
+                    noted.push((p.clone(), Some(old)));
+                }
+            }
+            None => {
+                let info = PeerInfo {
+                    peer_id: known.peer_id.clone(),
+                    username: known.username.clone(),
+                    addrs: merge_addrs(&[], known.addrs.iter().cloned()),
+                    tcp_port: known.tcp_port,
+                    last_seen: seen,
+                    indirect: true,
+                    via: via.clone(),
+                    workspaces,
+                };
+                peers.insert(info.peer_id.clone(), info.clone());
+                noted.push((info, None));
+                changed = true;
+            }
+        }
+    }
+    drop(peers);
+    for (info, old_name) in noted {
+        discovery::note(&info, old_name, db, events).await;
+    }
+    if changed {
+        discovery::send_peer_list(state, events).await;
+    }
+}
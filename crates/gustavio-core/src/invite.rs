@@ -0,0 +1,277 @@
+//! Invite codes, so workspace secrets and group memberships needn't be
+//! handed out by hand. A code names what it's for, carries a one-time token
+//! and says where its issuer listens. Redeeming it dials the issuer, at each
+//! of its addresses until one answers, and sends `Join` in place of `Hello`;
+//! the issuer spends the token and answers with the workspace's secret, or
+//! adds the joiner to the group and answers with its `GroupCreate`, after
+//! which the joiner tells the other members with `GroupMemberAdd`. Tokens
+//! only live in the issuer's memory and expire after [`INVITE_TTL`].
+
+use crate::db::GroupRow;
+use crate::events::{Event, Events};
+use crate::network;
+use crate::outbox::Outbox;
+use crate::protocol::TcpMessage;
+use crate::state::SharedState;
+use crate::storage::Storage;
+use crate::workspace::{self, Workspace};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long a code can be redeemed for.
+pub const INVITE_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Start of every code, naming its format.
+const PREFIX: &str = "gv1.";
+/// Most issuer addresses we try from one code; codes are pasted in from
+/// anywhere.
+const MAX_ISSUERS: usize = 8;
+
+/// What an invite lets its holder into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteTarget {
+    /// By name
+    Workspace(String),
+    /// By group id
+    Group(String),
+}
+
+/// The contents of an invite code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub target: InviteTarget,
+    pub token: String,
+    /// `host:port`s the issuer accepts connections on, best first
+    pub issuers: Vec<String>,
+}
+
+impl Invite {
+    /// The code to hand out: its fields, one per line and the issuer's
+    /// addresses last, in URL-safe base64.
+    pub fn encode(&self) -> String {
+        let (kind, id) = match &self.target {
+            InviteTarget::Workspace(name) => ("w", name),
+            InviteTarget::Group(group_id) => ("g", group_id),
+        };
+        let text = format!("{kind}\n{id}\n{}\n{}", self.token, self.issuers.join("\n"));
+        format!("{PREFIX}{}", URL_SAFE_NO_PAD.encode(text))
+    }
+
+    pub fn decode(code: &str) -> Result<Self, String> {
+        let bad = || "Not an invite code".to_string();
+        let body = code.trim().strip_prefix(PREFIX).ok_or_else(bad)?;
+        let text = URL_SAFE_NO_PAD.decode(body).map_err(|_| bad())?;
+        let text = String::from_utf8(text).map_err(|_| bad())?;
+        let fields: Vec<&str> = text.split('\n').collect();
+        let [kind, id, token, ref issuers @ ..] = fields[..] else {
+            return Err(bad());
+        };
+        if issuers.is_empty() {
+            return Err(bad());
+        }
+        let target = match kind {
+            "w" => InviteTarget::Workspace(id.to_string()),
+            "g" => InviteTarget::Group(id.to_string()),
+            _ => return Err(bad()),
+        };
+        Ok(Self {
+            target,
+            token: token.to_string(),
+            issuers: issuers
+                .iter()
+                .take(MAX_ISSUERS)
+                .map(|i| i.to_string())
+                .collect(),
+        })
+    }
+}
+
+/// Invites we issued that haven't been redeemed. Kept in
+/// [`SharedState::invites`].
+#[derive(Default)]
+pub struct Invites {
+    /// token -> (target, expiry)
+    pending: HashMap<String, (InviteTarget, Instant)>,
+}
+
+impl Invites {
+    /// A fresh token for `target`.
+    pub fn issue(&mut self, target: InviteTarget) -> String {
+        let now = Instant::now();
+        self.pending.retain(|_, (_, expiry)| *expiry > now);
+        let token = URL_SAFE_NO_PAD.encode(uuid::Uuid::new_v4().as_bytes());
+        self.pending
+            .insert(token.clone(), (target, now + INVITE_TTL));
+        token
+    }
+
+    /// Spend `token`, once.
+    pub fn take(&mut self, token: &str) -> Result<InviteTarget, String> {
+        match self.pending.remove(token) {
+            Some((target, expiry)) if expiry > Instant::now() => Ok(target),
+            Some(_) => Err("Invite expired".into()),
+            None => Err("Unknown or already used invite".into()),
+        }
+    }
+}
+
+/// Issue a code for `target`, pointing at each of our addresses and
+/// `tcp_port`.
+pub async fn create(
+    target: InviteTarget,
+    tcp_port: u16,
+    state: &SharedState,
+    db: &Storage,
+) -> Result<String, String> {
+    // Fields are a line each in the code
+    let (InviteTarget::Workspace(id) | InviteTarget::Group(id)) = &target;
+    if id.contains('\n') {
+        return Err(format!("Can't invite to {id:?}: it has a line break"));
+    }
+    match &target {
+        InviteTarget::Workspace(name) => {
+            if !state
+                .workspaces
+                .lock()
+                .await
+                .iter()
+                .any(|w| w.name == *name)
+            {
+                return Err(format!("Not in workspace {name}"));
+            }
+        }
+        InviteTarget::Group(group_id) => {
+            group(group_id, db).await?;
+        }
+    }
+    // IPv4 first; link-local IPv6 needs a scope the joiner can't know
+    let mut ips: Vec<IpAddr> = state
+        .transport
+        .interfaces()
+        .iter()
+        .map(|i| i.addr)
+        .filter(|ip| !matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local()))
+        .collect();
+    ips.sort_by_key(|ip| ip.is_ipv6());
+    if ips.is_empty() {
+        return Err("No address to invite to".into());
+    }
+
+    let token = state.invites.lock().await.issue(target.clone());
+    let invite = Invite {
+        target,
+        token,
+        issuers: ips
+            .into_iter()
+            .take(MAX_ISSUERS)
+            .map(|ip| SocketAddr::new(ip, tcp_port).to_string())
+            .collect(),
+    };
+    Ok(invite.encode())
+}
+
+async fn group(group_id: &str, db: &Storage) -> Result<GroupRow, String> {
+    db.get_groups()
+        .await
+        .into_iter()
+        .find(|g| g.group_id == group_id)
+        .ok_or_else(|| format!("No group {group_id}"))
+}
+
+/// The issuer's answer to `joiner` redeeming `token`. Joiners of a group
+/// are added to it here.
+pub async fn answer(token: &str, joiner: &str, state: &SharedState, db: &Storage) -> TcpMessage {
+    let target = state.invites.lock().await.take(token);
+    let granted = match target {
+        Ok(InviteTarget::Workspace(name)) => {
+            let ours = state.workspaces.lock().await;
+            match ours.iter().find(|w| w.name == name) {
+                Some(w) => Ok(TcpMessage::WorkspaceSecret {
+                    name: w.name.clone(),
+                    secret: w.secret.clone(),
+                }),
+                None => Err(format!("Issuer left workspace {name}")),
+            }
+        }
+        Ok(InviteTarget::Group(group_id)) => match group(&group_id, db).await {
+            Ok(g) => {
+                db.add_group_member(&group_id, joiner);
+                let mut members = db.get_group_members(&group_id).await;
+                if !members.iter().any(|m| m == joiner) {
+                    members.push(joiner.to_string());
+                }
+                Ok(TcpMessage::GroupCreate {
+                    group_id,
+                    name: g.name,
+                    creator_id: g.creator_id,
+                    members,
+                })
+            }
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    granted.unwrap_or_else(|reason| TcpMessage::JoinRefused { reason })
+}
+
+/// Redeem `code`: join its workspace, or its group and tell the other
+/// members we're in.
+pub async fn redeem(
+    code: &str,
+    me: &str,
+    username: &str,
+    outbox: &Outbox,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) -> Result<(), String> {
+    let invite = Invite::decode(code)?;
+    let (issuer, answer) = join(&invite, me, username, state).await?;
+    match answer {
+        TcpMessage::WorkspaceSecret { name, secret } => {
+            workspace::enter(Workspace { name, secret }, db, state, events).await;
+            Ok(())
+        }
+        TcpMessage::GroupCreate {
+            group_id,
+            name,
+            creator_id,
+            members,
+        } => {
+            db.create_group(&group_id, &name, &creator_id, &members);
+            events.emit(Event::GroupList(db.get_groups().await));
+            let added = TcpMessage::GroupMemberAdd {
+                group_id,
+                peer_id: me.to_string(),
+            };
+            for member in members.iter().filter(|m| *m != me && **m != issuer) {
+                drop(outbox.send(member, added.clone()).await);
+            }
+            Ok(())
+        }
+        TcpMessage::JoinRefused { reason } => Err(reason),
+        _ => Err("Unexpected answer to join".into()),
+    }
+}
+
+/// Send `Join` to the issuer at the first of its addresses that answers.
+async fn join(
+    invite: &Invite,
+    me: &str,
+    username: &str,
+    state: &SharedState,
+) -> Result<(String, TcpMessage), String> {
+    let mut last_err = String::from("No address to dial");
+    for addr in &invite.issuers {
+        match network::join(addr, &invite.token, me, username, state).await {
+            Ok(joined) => return Ok(joined),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
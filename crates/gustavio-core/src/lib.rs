@@ -0,0 +1,30 @@
+//! Networking and storage core of Gustavio: discovery, peer connections,
+//! history and groups. Consumers drive it with [`node::Command`]s and observe
+//! it through an [`events::EventSink`].
+
+pub mod away;
+pub mod bot;
+pub mod codec;
+pub mod contacts;
+pub mod db;
+pub mod discovery;
+pub mod events;
+pub mod gossip;
+pub mod invite;
+pub mod limits;
+pub mod mdns;
+pub mod netif;
+pub mod network;
+pub mod node;
+pub mod outbox;
+pub mod profile;
+pub mod protocol;
+pub mod reconnect;
+pub mod relay;
+pub mod routing;
+pub mod sim;
+pub mod state;
+pub mod static_peers;
+pub mod storage;
+pub mod transport;
+pub mod workspace;
@@ -0,0 +1,207 @@
+//! Protection against peers that hog or flood our TCP listener.
+
+use crate::codec::MAX_FRAME_SIZE;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Bounds on what a single remote machine may do to us.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Time a new connection gets to complete the `Hello` exchange
+    pub handshake_timeout: Duration,
+    /// Largest frame we accept; anything bigger drops the connection
+    pub max_frame_size: usize,
+    pub max_connections_per_ip: usize,
+    /// Sustained messages per second allowed on one connection for each
+    /// peer its frames come from, up to [`CONNECTION_SHARES`] times that in
+    /// all (see [`Rates`])
+    pub messages_per_second: u32,
+    /// Messages each may send in a burst above that rate
+    pub message_burst: u32,
+    /// How long an IP is refused after a violation
+    pub ban_duration: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            handshake_timeout: Duration::from_secs(5),
+            max_frame_size: MAX_FRAME_SIZE,
+            max_connections_per_ip: 8,
+            messages_per_second: 20,
+            message_burst: 50,
+            ban_duration: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Open connection counts and temporary bans, per remote IP.
+pub struct Gate {
+    limits: Limits,
+    open: HashMap<IpAddr, usize>,
+    banned: HashMap<IpAddr, Instant>,
+}
+
+impl Gate {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            open: HashMap::new(),
+            banned: HashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn is_banned(&mut self, ip: IpAddr) -> bool {
+        match self.banned.get(&ip) {
+            Some(until) if Instant::now() < *until => true,
+            Some(_) => {
+                self.banned.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Take a connection slot for `ip`. Going over the per-IP cap is a
+    /// violation and bans the address.
+    pub fn admit(&mut self, ip: IpAddr) -> Result<(), String> {
+        if self.is_banned(ip) {
+            return Err(format!("{ip} is banned"));
+        }
+        let open = self.open.entry(ip).or_insert(0);
+        if *open >= self.limits.max_connections_per_ip {
+            let reason = format!(
+                "more than {} connections",
+                self.limits.max_connections_per_ip
+            );
+            self.ban(ip, &reason);
+            return Err(format!("{ip} opened {reason}"));
+        }
+        *open += 1;
+        Ok(())
+    }
+
+    /// Give back a slot taken by `admit`.
+    pub fn release(&mut self, ip: IpAddr) {
+        if let Some(open) = self.open.get_mut(&ip) {
+            *open -= 1;
+            if *open == 0 {
+                self.open.remove(&ip);
+            }
+        }
+    }
+
+    pub fn ban(&mut self, ip: IpAddr, reason: &str) {
+        eprintln!(
+            "Banning {ip} for {}s: {reason}",
+            self.limits.ban_duration.as_secs()
+        );
+        self.banned
+            .insert(ip, Instant::now() + self.limits.ban_duration);
+    }
+}
+
+/// Peers' worth of messages one connection may carry in all, so a
+/// neighbour passing frames on for others isn't held to the rate of one,
+/// however many it claims to pass on for.
+pub const CONNECTION_SHARES: u32 = 4;
+/// Peers a connection's frames come from that we keep a bucket for; the
+/// one heard from least recently makes room for a new one.
+const MAX_ORIGINS: usize = 64;
+
+/// Token buckets for the messages of one connection: one for all of them,
+/// and within it one per peer its frames come from, each allowed the rate
+/// of a direct connection.
+pub struct Rates {
+    limits: Limits,
+    total: RateLimiter,
+    by_origin: HashMap<String, RateLimiter>,
+}
+
+impl Rates {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            limits: limits.clone(),
+            total: RateLimiter::scaled(limits, CONNECTION_SHARES),
+            by_origin: HashMap::new(),
+        }
+    }
+
+    /// Spend a token of the connection's and one of `origin`'s. Err says
+    /// which went over its rate.
+    pub fn allow(&mut self, origin: &str) -> Result<(), String> {
+        if !self.by_origin.contains_key(origin) {
+            if self.by_origin.len() >= MAX_ORIGINS {
+                let stalest = self
+                    .by_origin
+                    .iter()
+                    .min_by_key(|(_, r)| r.last)
+                    .map(|(o, _)| o.clone());
+                if let Some(stalest) = stalest {
+                    self.by_origin.remove(&stalest);
+                }
+            }
+            let fresh = RateLimiter::new(&self.limits);
+            self.by_origin.insert(origin.to_string(), fresh);
+        }
+        let per_second = self.limits.messages_per_second;
+        if !self
+            .by_origin
+            .get_mut(origin)
+            .is_some_and(RateLimiter::allow)
+        {
+            return Err(format!("more than {per_second} messages/s from {origin}"));
+        }
+        if !self.total.allow() {
+            let all = per_second * CONNECTION_SHARES;
+            return Err(format!("more than {all} messages/s in all"));
+        }
+        Ok(())
+    }
+}
+
+/// Token bucket for the messages of one peer, or of a whole connection.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &Limits) -> Self {
+        Self::scaled(limits, 1)
+    }
+
+    /// A bucket for `shares` peers' worth of messages.
+    fn scaled(limits: &Limits, shares: u32) -> Self {
+        let burst = (limits.message_burst.max(1) * shares) as f64;
+        Self {
+            rate: (limits.messages_per_second * shares) as f64,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Spend a token for one message; false once the peer is over its rate.
+    pub fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
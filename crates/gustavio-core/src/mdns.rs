@@ -0,0 +1,209 @@
+//! DNS-SD over mDNS, alongside our own UDP announces. We advertise
+//! `_gustavio._tcp.local` with our identity in TXT records and add the peers
+//! we browse to the same table, so networks that already carry mDNS find
+//! us and `avahi-browse -r _gustavio._tcp` can list us.
+
+use crate::discovery::{self, merge_addrs};
+use crate::events::Events;
+use crate::protocol::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::state::{PeerInfo, SharedState};
+use crate::storage::Storage;
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+pub const SERVICE_TYPE: &str = "_gustavio._tcp.local.";
+
+/// How long we wait for the daemon to send our goodbye record.
+const UNREGISTER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Our registered service and the browser feeding the peer table.
+pub struct Mdns {
+    daemon: ServiceDaemon,
+    fullname: String,
+    peer_id: String,
+    tcp_port: u16,
+    browser: JoinHandle<()>,
+}
+
+impl Mdns {
+    /// Advertise under `username` from now on. The instance name carries
+    /// it, so the old record is withdrawn and a new one registered.
+    pub fn rename(&mut self, username: &str) {
+        let Some(service) = service(&self.peer_id, username, self.tcp_port) else {
+            return;
+        };
+        let _ = self.daemon.unregister(&self.fullname);
+        self.fullname = service.get_fullname().to_string();
+        if let Err(e) = self.daemon.register(service) {
+            eprintln!("mDNS register failed: {e}");
+        }
+    }
+
+    /// Withdraw our service record and stop the daemon.
+    pub async fn stop(self) {
+        self.browser.abort();
+        if let Ok(done) = self.daemon.unregister(&self.fullname) {
+            let _ = tokio::time::timeout(UNREGISTER_TIMEOUT, done.recv_async()).await;
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Advertise ourselves and browse for other nodes. Peers that still hold a
+/// record are kept fresh every `refresh`; once the record goes they age out
+/// like any other peer. `None` if the daemon can't start.
+pub fn run(
+    peer_id: String,
+    username: String,
+    tcp_port: u16,
+    refresh: Duration,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+) -> Option<Mdns> {
+    let daemon = match ServiceDaemon::new() {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("mDNS unavailable: {e}");
+            return None;
+        }
+    };
+
+    let Some(service) = service(&peer_id, &username, tcp_port) else {
+        let _ = daemon.shutdown();
+        return None;
+    };
+    let fullname = service.get_fullname().to_string();
+    if let Err(e) = daemon.register(service) {
+        eprintln!("mDNS register failed: {e}");
+    }
+
+    let browse = match daemon.browse(SERVICE_TYPE) {
+        Ok(rx) => rx,
+        Err(e) => {
+            eprintln!("mDNS browse failed: {e}");
+            let _ = daemon.shutdown();
+            return None;
+        }
+    };
+    let me = peer_id.clone();
+    let browser = tokio::spawn(async move {
+        // Records we hold (fullname -> peer_id)
+        let mut found: HashMap<String, String> = HashMap::new();
+        let mut tick = tokio::time::interval(refresh);
+        loop {
+            tokio::select! {
+                event = browse.recv_async() => match event {
+                    Ok(ServiceEvent::ServiceResolved(info)) => {
+                        if let Some(id) = add_peer(&info, &me, &state, &db, &events).await {
+                            found.insert(info.get_fullname().to_string(), id);
+                            discovery::send_peer_list(&state, &events).await;
+                        }
+                    }
+                    Ok(ServiceEvent::ServiceRemoved(_, fullname)) => {
+                        found.remove(&fullname);
+                    }
+                    Ok(_) => {}
+                    Err(_) => return,
+                },
+                _ = tick.tick() => {
+                    let mut peers = state.peers.lock().await;
+                    for id in found.values() {
+                        if let Some(p) = peers.get_mut(id) {
+                            p.last_seen = Instant::now();
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    Some(Mdns {
+        daemon,
+        fullname,
+        peer_id,
+        tcp_port,
+        browser,
+    })
+}
+
+/// Our service record, with our identity in its TXT records.
+fn service(peer_id: &str, username: &str, tcp_port: u16) -> Option<ServiceInfo> {
+    let short = &peer_id[..peer_id.len().min(8)];
+    let instance = format!("{} ({short})", username.replace('.', "-"));
+    let host = format!("gustavio-{short}.local.");
+    let version = PROTOCOL_VERSION.to_string();
+    let txt = [
+        ("peer_id", peer_id),
+        ("username", username),
+        ("version", version.as_str()),
+    ];
+    match ServiceInfo::new(SERVICE_TYPE, &instance, &host, "", tcp_port, &txt[..]) {
+        Ok(s) => Some(s.enable_addr_auto()),
+        Err(e) => {
+            eprintln!("Bad mDNS service record: {e}");
+            None
+        }
+    }
+}
+
+/// Put a resolved record in the peer table and note it as announces are
+/// noted; returns its peer id unless it was ours or unusable. Records can't
+/// be signed, so in workspaces they're left to our own announces.
+async fn add_peer(
+    info: &ServiceInfo,
+    my_id: &str,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) -> Option<String> {
+    let peer_id = info.get_property_val_str("peer_id")?;
+    if peer_id == my_id || !state.workspaces.lock().await.is_empty() {
+        return None;
+    }
+    let version: u32 = info.get_property_val_str("version")?.parse().ok()?;
+    if version < MIN_PROTOCOL_VERSION {
+        return None;
+    }
+    let username = info.get_property_val_str("username").unwrap_or_default();
+
+    // IPv4 first; link-local IPv6 arrives without the scope it needs
+    let mut ips: Vec<IpAddr> = info
+        .get_addresses()
+        .iter()
+        .filter(|ip| !matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local()))
+        .copied()
+        .collect();
+    ips.sort_by_key(|ip| (ip.is_ipv6(), *ip));
+    if ips.is_empty() {
+        return None;
+    }
+
+    let mut peers = state.peers.lock().await;
+    let known = peers.get(peer_id);
+    let old_name = known.map(|p| p.username.clone());
+    let addrs = merge_addrs(
+        known.map_or(&[][..], |p| &p.addrs[..]),
+        ips.iter().map(IpAddr::to_string),
+    );
+    let info = PeerInfo {
+        peer_id: peer_id.to_string(),
+        username: username.to_string(),
+        addrs,
+        tcp_port: info.get_port(),
+        last_seen: Instant::now(),
+        indirect: false,
+        via: None,
+        workspaces: Vec::new(),
+    };
+    peers.insert(info.peer_id.clone(), info.clone());
+    drop(peers);
+    discovery::note(&info, old_name, db, events).await;
+    Some(info.peer_id)
+}
@@ -0,0 +1,113 @@
+//! The machine's network interfaces, for discovery traffic that has to go
+//! out on each of them rather than wherever the OS routes it.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// One address on one interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub addr: IpAddr,
+    /// OS interface index, used as the scope of IPv6 link-local traffic;
+    /// 0 (the OS default) where it can't be looked up
+    pub index: u32,
+    /// Subnet-directed broadcast address of an IPv4 interface, where the
+    /// netmask is known and the link has room for one
+    pub broadcast: Option<Ipv4Addr>,
+}
+
+/// Addresses of the interfaces that are up, loopback excluded.
+pub fn list() -> Vec<Interface> {
+    let netifas = match local_ip_address::list_afinet_netifas() {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("Listing network interfaces failed: {e}");
+            return Vec::new();
+        }
+    };
+    let masks = netmasks();
+    netifas
+        .into_iter()
+        .filter(|(_, addr)| !addr.is_loopback() && !addr.is_unspecified())
+        .map(|(name, addr)| Interface {
+            index: index_of(&name),
+            broadcast: match addr {
+                IpAddr::V4(v4) => masks.get(&v4).and_then(|m| broadcast_of(v4, *m)),
+                IpAddr::V6(_) => None,
+            },
+            name,
+            addr,
+        })
+        .collect()
+}
+
+/// `None` for /31 and /32 links (point-to-point, VPN tunnels), which have
+/// no broadcast address.
+pub fn broadcast_of(addr: Ipv4Addr, netmask: Ipv4Addr) -> Option<Ipv4Addr> {
+    let mask = u32::from(netmask);
+    if mask.count_ones() >= 31 {
+        return None;
+    }
+    Some(Ipv4Addr::from(u32::from(addr) | !mask))
+}
+
+#[cfg(unix)]
+fn index_of(name: &str) -> u32 {
+    let Ok(name) = std::ffi::CString::new(name) else {
+        return 0;
+    };
+    // SAFETY: `name` is a valid NUL-terminated string for the whole call
+    unsafe { libc::if_nametoindex(name.as_ptr()) }
+}
+
+#[cfg(not(unix))]
+fn index_of(_name: &str) -> u32 {
+    0
+}
+
+/// IPv4 netmasks by interface address, read with `getifaddrs`.
+#[cfg(unix)]
+fn netmasks() -> HashMap<Ipv4Addr, Ipv4Addr> {
+    let mut masks = HashMap::new();
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: on success getifaddrs points `head` at a list we free below
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return masks;
+    }
+    let mut cur = head;
+    while !cur.is_null() {
+        // SAFETY: `cur` is a node of the list getifaddrs returned
+        let ifa = unsafe { &*cur };
+        // SAFETY: both pointers come from getifaddrs and are checked for null
+        if let Some((addr, mask)) = unsafe { v4_pair(ifa.ifa_addr, ifa.ifa_netmask) } {
+            masks.insert(addr, mask);
+        }
+        cur = ifa.ifa_next;
+    }
+    // SAFETY: `head` came from getifaddrs and is freed once
+    unsafe { libc::freeifaddrs(head) };
+    masks
+}
+
+/// Some systems leave the netmask's family unset, so only the address's
+/// family is checked.
+#[cfg(unix)]
+unsafe fn v4_pair(
+    addr: *const libc::sockaddr,
+    mask: *const libc::sockaddr,
+) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    if addr.is_null() || mask.is_null() || (*addr).sa_family as i32 != libc::AF_INET {
+        return None;
+    }
+    let ip = |sa: *const libc::sockaddr| {
+        let sin = &*(sa as *const libc::sockaddr_in);
+        Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr))
+    };
+    Some((ip(addr), ip(mask)))
+}
+
+#[cfg(not(unix))]
+fn netmasks() -> HashMap<Ipv4Addr, Ipv4Addr> {
+    HashMap::new()
+}
@@ -0,0 +1,874 @@
+use crate::codec;
+use crate::contacts;
+use crate::db::MessageRow;
+use crate::discovery;
+use crate::events::{Event, Events, LinkState};
+use crate::gossip;
+use crate::invite;
+use crate::limits::{Limits, Rates};
+use crate::profile::{self, Profile};
+use crate::protocol::{
+    TcpMessage, CAP_HEARTBEAT, CAP_PROFILE, CAP_RELAY, MAX_HOPS, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
+use crate::routing;
+use crate::state::{is_preferred, PeerConnection, PeerSession, SharedState, SharedWriter};
+use crate::storage::Storage;
+use crate::transport::{BoxReader, Connection};
+use crate::workspace::{self, Proving, Workspace};
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::sync::Mutex as TokioMutex;
+
+/// How we notice a peer that vanished without closing the connection.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    /// Time between our `Ping`s
+    pub interval: Duration,
+    /// Silence after which the connection is torn down and the peer
+    /// shown offline; only applies to peers that advertise heartbeats
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            timeout: Duration::from_secs(6),
+        }
+    }
+}
+
+/// How long we wait for a write half to close before giving up on it.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long one address gets to accept before we try the peer's next one.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Why a connection's read loop stopped.
+enum Closed {
+    /// The peer hung up or the socket failed
+    Hangup,
+    /// Heartbeats stopped arriving
+    Silent,
+    /// The peer broke our limits
+    Violation(String),
+}
+
+/// What the peer opened a connection with.
+enum Greeting {
+    /// A `Hello`: the peer's id and the negotiated session
+    Session(String, PeerSession),
+    /// A `Join`, redeeming one of our invites
+    Join {
+        token: String,
+        peer_id: String,
+        username: String,
+    },
+}
+
+/// Start the TCP listener that accepts connections from peers.
+pub async fn run_listener(
+    my_peer_id: String,
+    tcp_port: u16,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+) {
+    let mut listener = match state.transport.listen(tcp_port).await {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("TCP listen error: {e}");
+            return;
+        }
+    };
+
+    loop {
+        let conn = match listener.accept().await {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        let ip = conn.remote_addr.ip();
+        if let Err(e) = state.gate.lock().await.admit(ip) {
+            eprintln!("Refusing connection: {e}");
+            continue;
+        }
+        let pid = my_peer_id.clone();
+        let st = state.clone();
+        let d = db.clone();
+        let ev = events.clone();
+        tokio::spawn(async move {
+            handle_connection(conn, pid, st.clone(), d, ev).await;
+            st.gate.lock().await.release(ip);
+        });
+    }
+}
+
+async fn handle_connection(
+    conn: Connection,
+    my_peer_id: String,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+) {
+    let ip = conn.remote_addr.ip();
+    let limits = state.gate.lock().await.limits().clone();
+    let mut reader = BufReader::new(conn.reader);
+    let writer: SharedWriter = Arc::new(TokioMutex::new(conn.writer));
+
+    let workspaces = state.workspaces.lock().await.clone();
+    let my_username = state.username.lock().await.clone();
+    let hello = our_hello(&my_peer_id, &my_username, &workspaces, &state);
+    let hello = handshake(&mut reader, &writer, &hello, false, &workspaces, &limits);
+    let greeting = match tokio::time::timeout(limits.handshake_timeout, hello).await {
+        Ok(Ok(greeting)) => greeting,
+        Ok(Err(e)) => {
+            eprintln!("Handshake with {} failed: {e}", conn.remote_addr);
+            return;
+        }
+        Err(_) => {
+            state.gate.lock().await.ban(ip, "no hello before timeout");
+            return;
+        }
+    };
+    let (remote_peer_id, session) = match greeting {
+        Greeting::Session(peer_id, session) => (peer_id, session),
+        Greeting::Join {
+            token,
+            peer_id,
+            username,
+        } => {
+            let answer = invite::answer(&token, &peer_id, &state, &db).await;
+            if let TcpMessage::JoinRefused { reason } = &answer {
+                eprintln!("Refused join from {username} ({peer_id}): {reason}");
+            }
+            let _ = write(&writer, &answer).await;
+            let _ = writer.lock().await.shutdown().await;
+            return;
+        }
+    };
+
+    let conn = PeerConnection {
+        writer: writer.clone(),
+        preferred: is_preferred(&remote_peer_id, &my_peer_id),
+    };
+    let heartbeat = session.capabilities.iter().any(|c| c == CAP_HEARTBEAT);
+    let profiles = session.capabilities.iter().any(|c| c == CAP_PROFILE);
+    let username = session.username.clone();
+    register(&remote_peer_id, conn, session, &state, &events).await;
+    contacts::seen(&remote_peer_id, &username, &ip.to_string(), &db, &events).await;
+    if profiles {
+        let _ = write(&writer, &profile::message(&state).await).await;
+    }
+
+    let closed = read_messages(
+        &mut reader,
+        &writer,
+        &remote_peer_id,
+        heartbeat,
+        &state,
+        &db,
+        &events,
+    )
+    .await;
+    finish(&remote_peer_id, &writer, ip, closed, &state, &db, &events).await;
+}
+
+/// Connect to a peer's TCP server and return the id of whoever answered.
+/// Registers the connection in state.connections and spawns a reader task.
+pub async fn connect_to_peer(
+    peer_ip: &str,
+    peer_tcp_port: u16,
+    my_peer_id: &str,
+    my_username: &str,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+) -> Result<String, String> {
+    let addr = peer_addr(peer_ip, peer_tcp_port)?;
+    let ip = addr.ip();
+    if state.gate.lock().await.is_banned(ip) {
+        return Err(format!("{ip} is banned"));
+    }
+    let limits = state.gate.lock().await.limits().clone();
+    let conn = tokio::time::timeout(CONNECT_TIMEOUT, state.transport.connect(addr))
+        .await
+        .map_err(|_| format!("TCP connect to {addr}: timed out"))?
+        .map_err(|e| format!("TCP connect to {addr}: {e}"))?;
+
+    let mut reader = BufReader::new(conn.reader);
+    let writer: SharedWriter = Arc::new(TokioMutex::new(conn.writer));
+    let workspaces = state.workspaces.lock().await.clone();
+    let hello = our_hello(my_peer_id, my_username, &workspaces, &state);
+    let hello = handshake(&mut reader, &writer, &hello, true, &workspaces, &limits);
+    let greeting = tokio::time::timeout(limits.handshake_timeout, hello)
+        .await
+        .map_err(|_| format!("No hello from {addr}"))??;
+    let Greeting::Session(remote_peer_id, session) = greeting else {
+        return Err(format!("{addr} sent a join instead of a hello"));
+    };
+    // Another of our own interfaces, e.g. a bridge both hosts number alike
+    if remote_peer_id == my_peer_id {
+        return Err(format!("{addr} is ourselves"));
+    }
+
+    let conn = PeerConnection {
+        writer: writer.clone(),
+        preferred: is_preferred(my_peer_id, &remote_peer_id),
+    };
+    let heartbeat = session.capabilities.iter().any(|c| c == CAP_HEARTBEAT);
+    let profiles = session.capabilities.iter().any(|c| c == CAP_PROFILE);
+    let username = session.username.clone();
+    register(&remote_peer_id, conn, session, &state, &events).await;
+    contacts::seen(&remote_peer_id, &username, &ip.to_string(), &db, &events).await;
+    if profiles {
+        let _ = write(&writer, &profile::message(&state).await).await;
+    }
+
+    // Spawn reader task
+    let peer_id = remote_peer_id.clone();
+    tokio::spawn(async move {
+        let closed = read_messages(
+            &mut reader,
+            &writer,
+            &remote_peer_id,
+            heartbeat,
+            &state,
+            &db,
+            &events,
+        )
+        .await;
+        finish(&remote_peer_id, &writer, ip, closed, &state, &db, &events).await;
+    });
+
+    Ok(peer_id)
+}
+
+/// Parse a peer's host (IPv4, IPv6, or scoped IPv6 like `fe80::1%2`) and port.
+fn peer_addr(host: &str, port: u16) -> Result<SocketAddr, String> {
+    let addr = if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    };
+    addr.parse()
+        .map_err(|e| format!("Bad peer address {host}: {e}"))
+}
+
+/// Our `Hello`; relays also say they re-announce, and nodes in workspaces
+/// send a nonce to be proved against.
+fn our_hello(
+    my_peer_id: &str,
+    my_username: &str,
+    workspaces: &[Workspace],
+    state: &SharedState,
+) -> TcpMessage {
+    let extra: &[&str] = match state.relay.get() {
+        Some(_) => &[CAP_RELAY],
+        None => &[],
+    };
+    TcpMessage::hello_with(my_peer_id, my_username, extra, workspace::nonce(workspaces))
+}
+
+/// Send `hello`, then check the peer speaks a version we understand. In
+/// workspaces, both sides then prove theirs and the peer must share one of
+/// ours; `dialed` says we opened the connection. A peer redeeming an invite
+/// answers with `Join` instead, which ends the handshake there.
+async fn handshake(
+    reader: &mut BufReader<BoxReader>,
+    writer: &SharedWriter,
+    hello: &TcpMessage,
+    dialed: bool,
+    workspaces: &[Workspace],
+    limits: &Limits,
+) -> Result<Greeting, String> {
+    let TcpMessage::Hello {
+        peer_id: me,
+        nonce: our_nonce,
+        ..
+    } = hello
+    else {
+        return Err("Not a hello".into());
+    };
+    write(writer, hello)
+        .await
+        .map_err(|e| format!("Send hello: {e}"))?;
+
+    let body = codec::read_frame(reader, limits.max_frame_size)
+        .await
+        .map_err(|e| format!("Read hello: {e}"))?
+        .ok_or("Closed before hello")?;
+    let (peer_id, username, version, capabilities, their_nonce) = match codec::decode(&body) {
+        Ok(TcpMessage::Hello {
+            peer_id,
+            username,
+            version,
+            capabilities,
+            nonce,
+        }) => (peer_id, username, version, capabilities, nonce),
+        Ok(TcpMessage::Join {
+            token,
+            peer_id,
+            username,
+        }) => {
+            return Ok(Greeting::Join {
+                token,
+                peer_id,
+                username,
+            })
+        }
+        _ => return Err("Bad hello".into()),
+    };
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(format!(
+            "Peer {peer_id} speaks protocol v{version}, need v{MIN_PROTOCOL_VERSION}+"
+        ));
+    }
+
+    let shared = match (our_nonce, their_nonce) {
+        (None, None) => Vec::new(),
+        (None, Some(_)) => return Err(format!("{peer_id} is in a workspace, we're in none")),
+        (Some(_), None) => return Err(format!("{peer_id} is in no workspace")),
+        (Some(ours), Some(theirs)) => {
+            let mine = Proving {
+                prover: me,
+                prover_nonce: ours,
+                verifier: &peer_id,
+                verifier_nonce: &theirs,
+                dialed,
+            };
+            let proof = TcpMessage::WorkspaceProof {
+                proofs: workspaces.iter().map(|w| w.prove(&mine)).collect(),
+            };
+            write(writer, &proof)
+                .await
+                .map_err(|e| format!("Send proof: {e}"))?;
+            let body = codec::read_frame(reader, limits.max_frame_size)
+                .await
+                .map_err(|e| format!("Read proof: {e}"))?
+                .ok_or("Closed before proof")?;
+            let Ok(TcpMessage::WorkspaceProof { proofs }) = codec::decode(&body) else {
+                return Err("Bad proof".into());
+            };
+            let peer = Proving {
+                prover: &peer_id,
+                prover_nonce: &theirs,
+                verifier: me,
+                verifier_nonce: ours,
+                dialed: !dialed,
+            };
+            let shared = workspace::check(workspaces, &peer, &proofs);
+            if shared.is_empty() {
+                return Err(format!("{peer_id} is in none of our workspaces"));
+            }
+            shared
+        }
+    };
+
+    let session = PeerSession {
+        username,
+        version: version.min(PROTOCOL_VERSION),
+        capabilities,
+        workspaces: shared,
+    };
+    Ok(Greeting::Session(peer_id, session))
+}
+
+/// Redeem invite `token` with the issuer at `addr` (`host:port`): read its
+/// `Hello`, send `Join` and return the issuer's id and its answer.
+pub async fn join(
+    addr: &str,
+    token: &str,
+    my_peer_id: &str,
+    my_username: &str,
+    state: &SharedState,
+) -> Result<(String, TcpMessage), String> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|e| format!("Bad issuer address {addr}: {e}"))?;
+    let limits = state.gate.lock().await.limits().clone();
+    let conn = tokio::time::timeout(CONNECT_TIMEOUT, state.transport.connect(addr))
+        .await
+        .map_err(|_| format!("TCP connect to {addr}: timed out"))?
+        .map_err(|e| format!("TCP connect to {addr}: {e}"))?;
+    let mut reader = BufReader::new(conn.reader);
+    let writer: SharedWriter = Arc::new(TokioMutex::new(conn.writer));
+
+    let exchange = async {
+        let issuer = match read(&mut reader, &limits).await? {
+            TcpMessage::Hello { peer_id, .. } => peer_id,
+            _ => return Err("Bad hello".to_string()),
+        };
+        let join = TcpMessage::Join {
+            token: token.to_string(),
+            peer_id: my_peer_id.to_string(),
+            username: my_username.to_string(),
+        };
+        write(&writer, &join)
+            .await
+            .map_err(|e| format!("Send join: {e}"))?;
+        Ok((issuer, read(&mut reader, &limits).await?))
+    };
+    let joined = tokio::time::timeout(limits.handshake_timeout, exchange)
+        .await
+        .map_err(|_| format!("No answer from {addr}"))?;
+    let _ = writer.lock().await.shutdown().await;
+    joined
+}
+
+async fn read(reader: &mut BufReader<BoxReader>, limits: &Limits) -> Result<TcpMessage, String> {
+    let body = codec::read_frame(reader, limits.max_frame_size)
+        .await
+        .map_err(|e| format!("Read: {e}"))?
+        .ok_or("Closed early")?;
+    codec::decode(&body).map_err(|e| format!("Bad message: {e}"))
+}
+
+async fn write(writer: &SharedWriter, msg: &TcpMessage) -> std::io::Result<()> {
+    let mut w = writer.lock().await;
+    codec::write_frame(&mut *w, msg).await
+}
+
+/// Make `conn` the session with `peer_id`, unless we already hold the
+/// preferred connection to it. The loser is shut down for writing: the other
+/// side makes the same call, closes its end too, and both readers drain
+/// whatever was already in flight before they stop.
+async fn register(
+    peer_id: &str,
+    conn: PeerConnection,
+    session: PeerSession,
+    state: &SharedState,
+    events: &Events,
+) {
+    let mut conns = state.connections.lock().await;
+    let loser = match conns.get(peer_id) {
+        Some(existing) if existing.preferred && !conn.preferred => conn.writer,
+        Some(existing) => {
+            let old = existing.writer.clone();
+            conns.insert(peer_id.to_string(), conn);
+            state
+                .sessions
+                .lock()
+                .await
+                .insert(peer_id.to_string(), session);
+            old
+        }
+        None => {
+            conns.insert(peer_id.to_string(), conn);
+            state
+                .sessions
+                .lock()
+                .await
+                .insert(peer_id.to_string(), session);
+            events.emit(Event::Link {
+                peer_id: peer_id.to_string(),
+                state: LinkState::Connected,
+            });
+            return;
+        }
+    };
+    drop(conns);
+    let _ = loser.lock().await.shutdown().await;
+}
+
+/// Handle frames until the connection ends. With `heartbeat` set we ping
+/// the peer and give up on it once nothing arrives for the heartbeat timeout.
+async fn read_messages(
+    reader: &mut BufReader<BoxReader>,
+    writer: &SharedWriter,
+    peer_id: &str,
+    heartbeat: bool,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) -> Closed {
+    let limits = state.gate.lock().await.limits().clone();
+    let mut rates = Rates::new(&limits);
+    let pinger = heartbeat.then(|| tokio::spawn(ping(writer.clone(), state.heartbeat.interval)));
+    let closed = loop {
+        let next = codec::read_frame(reader, limits.max_frame_size);
+        let frame = if heartbeat {
+            match tokio::time::timeout(state.heartbeat.timeout, next).await {
+                Ok(frame) => frame,
+                Err(_) => break Closed::Silent,
+            }
+        } else {
+            next.await
+        };
+        let body = match frame {
+            Ok(Some(body)) => body,
+            Ok(None) => break Closed::Hangup,
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                break Closed::Violation(e.to_string())
+            }
+            Err(_) => break Closed::Hangup,
+        };
+        let msg = codec::decode(&body);
+        let origin = match &msg {
+            Ok(msg) => origin(msg, peer_id, state).await,
+            Err(_) => peer_id.to_string(),
+        };
+        if let Err(e) = rates.allow(&origin) {
+            break Closed::Violation(e);
+        }
+        match msg {
+            Ok(TcpMessage::Unknown) => {
+                eprintln!(
+                    "Ignoring unknown message type {}",
+                    codec::message_type(&body)
+                );
+            }
+            Ok(msg) => process_incoming(&msg, peer_id, writer, state, db, events).await,
+            Err(e) => eprintln!("Ignoring malformed message: {e}"),
+        }
+    };
+    if let Some(pinger) = pinger {
+        pinger.abort();
+    }
+    closed
+}
+
+/// Whose share of the connection's rate a frame from `neighbour` counts
+/// against. Frames it passes on from a peer it routes for count against
+/// that peer, so relays and routers aren't cut off for the traffic of a
+/// few; the rest, and frames claiming to come from peers it doesn't reach,
+/// count against itself.
+async fn origin(msg: &TcpMessage, neighbour: &str, state: &SharedState) -> String {
+    let claimed = match msg {
+        TcpMessage::DirectMessage { from_id, .. } | TcpMessage::GroupMessage { from_id, .. } => {
+            Some(from_id)
+        }
+        TcpMessage::Ack { from_id, .. } => from_id.as_ref(),
+        _ => None,
+    };
+    match claimed {
+        Some(from) if from != neighbour && state.routes.lock().await.reaches(neighbour, from) => {
+            from.clone()
+        }
+        _ => neighbour.to_string(),
+    }
+}
+
+async fn ping(writer: SharedWriter, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let mut w = writer.lock().await;
+        if codec::write_frame(&mut *w, &TcpMessage::Ping)
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Clean up after a read loop: ban rule breakers, and show a peer that went
+/// silent as offline right away instead of waiting for discovery to time out.
+async fn finish(
+    peer_id: &str,
+    writer: &SharedWriter,
+    ip: IpAddr,
+    closed: Closed,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) {
+    match &closed {
+        Closed::Hangup => {}
+        Closed::Violation(reason) => state.gate.lock().await.ban(ip, reason),
+        Closed::Silent => {
+            eprintln!("No heartbeat from {peer_id}; dropping connection");
+            // A dead peer may never drain its buffer, so don't wait forever
+            let close = async { writer.lock().await.shutdown().await };
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, close).await;
+        }
+    }
+    let was_current = forget_connection(peer_id, writer, state).await;
+    if was_current {
+        state.routes.lock().await.forget(peer_id);
+        events.emit(Event::Link {
+            peer_id: peer_id.to_string(),
+            state: LinkState::Disconnected,
+        });
+    }
+    if was_current && matches!(closed, Closed::Silent) {
+        let gone: Vec<_> = state
+            .peers
+            .lock()
+            .await
+            .remove(peer_id)
+            .into_iter()
+            .collect();
+        contacts::left(&gone, db, events).await;
+        discovery::send_peer_list(state, events).await;
+    }
+}
+
+/// Connection closed — remove it only if it's still OUR writer.
+/// Returns true if it was.
+async fn forget_connection(peer_id: &str, writer: &SharedWriter, state: &SharedState) -> bool {
+    let mut conns = state.connections.lock().await;
+    match conns.get(peer_id) {
+        Some(existing) if Arc::ptr_eq(&existing.writer, writer) => {
+            conns.remove(peer_id);
+            state.sessions.lock().await.remove(peer_id);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Send a TCP message to a specific peer.
+/// Returns Err if not connected or write fails.
+pub async fn send_to_peer(
+    peer_id: &str,
+    msg: &TcpMessage,
+    state: &SharedState,
+) -> Result<(), String> {
+    let writer = {
+        let conns = state.connections.lock().await;
+        conns
+            .get(peer_id)
+            .map(|c| c.writer.clone())
+            .ok_or_else(|| "Not connected to peer".to_string())?
+    };
+
+    let mut w = writer.lock().await;
+    codec::write_frame(&mut *w, msg)
+        .await
+        .map_err(|e| format!("Write failed: {e}"))
+}
+
+/// Send `msg` to every peer we hold a session with that advertised
+/// `capability`, skipping the ones the write fails for.
+pub async fn send_to_all(msg: &TcpMessage, capability: &str, state: &SharedState) {
+    let writers: Vec<SharedWriter> = {
+        let conns = state.connections.lock().await;
+        let sessions = state.sessions.lock().await;
+        conns
+            .iter()
+            .filter(|(id, _)| {
+                sessions
+                    .get(*id)
+                    .is_some_and(|s| s.capabilities.iter().any(|c| c == capability))
+            })
+            .map(|(_, c)| c.writer.clone())
+            .collect()
+    };
+    for writer in writers {
+        let _ = write(&writer, msg).await;
+    }
+}
+
+/// Close every session for writing. Peers see a clean end of stream, and
+/// our readers stop once they close their side in turn.
+pub async fn close_all(state: &SharedState) {
+    let writers: Vec<SharedWriter> = {
+        let conns = state.connections.lock().await;
+        conns.values().map(|c| c.writer.clone()).collect()
+    };
+    close_writers(writers).await;
+}
+
+/// Close our sessions with `peer_ids`, as `close_all` does.
+pub async fn close(peer_ids: &[String], state: &SharedState) {
+    let writers: Vec<SharedWriter> = {
+        let conns = state.connections.lock().await;
+        peer_ids
+            .iter()
+            .filter_map(|id| conns.get(id))
+            .map(|c| c.writer.clone())
+            .collect()
+    };
+    close_writers(writers).await;
+}
+
+async fn close_writers(writers: Vec<SharedWriter>) {
+    for writer in writers {
+        let close = async { writer.lock().await.shutdown().await };
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, close).await;
+    }
+}
+
+/// Remove a dead connection from state so reconnect can happen.
+pub async fn remove_connection(peer_id: &str, state: &SharedState) {
+    let mut conns = state.connections.lock().await;
+    conns.remove(peer_id);
+}
+
+// ── Shared message processing ──────────────────────────────────────
+
+async fn process_incoming(
+    msg: &TcpMessage,
+    from: &str,
+    writer: &SharedWriter,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) {
+    if let Some(router) = state.router.get() {
+        if router.pass_on(msg, state).await {
+            return;
+        }
+    }
+    let claimed = match msg {
+        TcpMessage::DirectMessage { from_id, to, .. }
+        | TcpMessage::GroupMessage { from_id, to, .. } => Some((from_id, to)),
+        // Acks from older peers don't say whom they're from
+        TcpMessage::Ack {
+            from_id: Some(from_id),
+            to,
+            ..
+        } => Some((from_id, to)),
+        _ => None,
+    };
+    if let Some((from_id, to)) = claimed {
+        if !could_send(from_id, to.is_some(), from, state).await {
+            eprintln!("Dropping frame claiming to be from {from_id}, sent by {from}");
+            return;
+        }
+    }
+    match msg {
+        TcpMessage::DirectMessage {
+            id,
+            from_id,
+            from_name,
+            content,
+            timestamp,
+            to,
+            ..
+        } => {
+            let row = MessageRow {
+                id: id.clone(),
+                conversation_id: from_id.clone(),
+                from_id: from_id.clone(),
+                from_name: from_name.clone(),
+                content: content.clone(),
+                timestamp: timestamp.clone(),
+                is_group: false,
+                status: "delivered".into(),
+            };
+            db.insert_message(row.clone());
+            events.emit(Event::MessageReceived(row));
+            send_ack(writer, id, from_id, to.as_deref()).await;
+            if let Some(away) = state.away.get() {
+                away.message_from(from_id);
+            }
+        }
+        TcpMessage::GroupMessage {
+            id,
+            group_id,
+            from_id,
+            from_name,
+            content,
+            timestamp,
+            to,
+            ..
+        } => {
+            let row = MessageRow {
+                id: id.clone(),
+                conversation_id: group_id.clone(),
+                from_id: from_id.clone(),
+                from_name: from_name.clone(),
+                content: content.clone(),
+                timestamp: timestamp.clone(),
+                is_group: true,
+                status: "delivered".into(),
+            };
+            db.insert_message(row.clone());
+            events.emit(Event::MessageReceived(row));
+            send_ack(writer, id, from_id, to.as_deref()).await;
+        }
+        TcpMessage::Ack {
+            message_id, status, ..
+        } => {
+            db.update_message_status(message_id, status);
+            events.emit(Event::MessageAck {
+                message_id: message_id.clone(),
+                status: status.clone(),
+            });
+        }
+        TcpMessage::GroupCreate {
+            group_id,
+            name,
+            creator_id,
+            members,
+        } => {
+            db.create_group(group_id, name, creator_id, members);
+            let groups = db.get_groups().await;
+            events.emit(Event::GroupList(groups));
+        }
+        TcpMessage::GroupMemberAdd { group_id, peer_id } => {
+            db.add_group_member(group_id, peer_id);
+        }
+        TcpMessage::GroupMemberRemove { group_id, peer_id } => {
+            db.remove_group_member(group_id, peer_id);
+        }
+        TcpMessage::Profile {
+            username,
+            status,
+            presence,
+            avatar,
+        } => {
+            let theirs = Profile {
+                status: status.clone(),
+                presence: *presence,
+                avatar: avatar.clone(),
+            };
+            profile::received(from, username, theirs, state, db, events).await;
+        }
+        TcpMessage::PeerTable { peers } => gossip::merge(from, peers, state, db, events).await,
+        TcpMessage::Routes { routes } => routing::merge(from, routes, state, db, events).await,
+        TcpMessage::Ping => {
+            let mut w = writer.lock().await;
+            let _ = codec::write_frame(&mut *w, &TcpMessage::Pong).await;
+        }
+        TcpMessage::Hello { .. }
+        | TcpMessage::WorkspaceProof { .. }
+        | TcpMessage::Join { .. }
+        | TcpMessage::WorkspaceSecret { .. }
+        | TcpMessage::JoinRefused { .. }
+        | TcpMessage::Pong
+        | TcpMessage::Unknown => {}
+    }
+}
+
+/// Whether a message from `from_id` can have come over our session with
+/// `neighbour`: sent by the neighbour itself, or, if it names its
+/// recipient and so may have been passed on, by a neighbour that reaches
+/// `from_id`.
+async fn could_send(from_id: &str, addressed: bool, neighbour: &str, state: &SharedState) -> bool {
+    if from_id == neighbour {
+        return true;
+    }
+    if !addressed {
+        return false;
+    }
+    if state.routes.lock().await.reaches(neighbour, from_id) {
+        return true;
+    }
+    // Learnt through gossip, so frames for it go through the neighbour too
+    state
+        .peers
+        .lock()
+        .await
+        .get(from_id)
+        .is_some_and(|p| p.via.as_deref() == Some(neighbour))
+}
+
+/// Acknowledge a message back to its sender, which may be peers away. `me`
+/// is whom the message was addressed to, if it said.
+async fn send_ack(writer: &SharedWriter, message_id: &str, sender: &str, me: Option<&str>) {
+    let ack = TcpMessage::Ack {
+        message_id: message_id.to_string(),
+        status: "delivered".into(),
+        from_id: me.map(str::to_string),
+        to: Some(sender.to_string()),
+        hops: MAX_HOPS,
+    };
+    let mut w = writer.lock().await;
+    let _ = codec::write_frame(&mut *w, &ack).await;
+}
@@ -0,0 +1,583 @@
+use crate::away;
+use crate::contacts;
+use crate::db::{Database, MessageRow};
+use crate::discovery::{self, Discovery, DiscoveryConfig};
+use crate::events::{Event, Events};
+use crate::gossip;
+use crate::invite::{self, InviteTarget};
+use crate::limits::Limits;
+use crate::network::{self, Heartbeat};
+use crate::outbox::Outbox;
+use crate::profile::{self, Profile};
+use crate::protocol::{Presence, TcpMessage, MAX_HOPS};
+use crate::reconnect::{ReconnectConfig, Reconnector};
+use crate::relay;
+use crate::routing;
+use crate::state::{PeerInfo, SharedState};
+use crate::static_peers::StaticPeers;
+use crate::storage::Storage;
+use crate::transport::{NetTransport, Transport};
+use crate::workspace::{self, Workspace};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+
+pub const TCP_PORT: u16 = 9999;
+
+/// Time queued messages get to go out when the node shuts down.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+/// Where a node keeps its data and how it reaches the network.
+#[derive(Clone)]
+pub struct NodeConfig {
+    pub db_path: PathBuf,
+    pub tcp_port: u16,
+    pub discovery: DiscoveryConfig,
+    /// Abuse protection for inbound connections
+    pub limits: Limits,
+    pub heartbeat: Heartbeat,
+    /// Backoff for redialling peers we're chatting with
+    pub reconnect: ReconnectConfig,
+    /// Pass frames on between peers and re-announce the ones learnt from
+    /// other relays; see [`relay`]
+    pub relay: bool,
+    /// Turn away by ourselves once the user is idle for the time set with
+    /// [`Command::SetAutoAway`]; only for apps that report
+    /// [`Command::Activity`]
+    pub auto_away: bool,
+    /// Workspaces to be in besides the ones joined with
+    /// [`Command::JoinWorkspace`]; not saved
+    pub workspaces: Vec<Workspace>,
+    pub transport: Arc<dyn Transport>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            db_path: Database::data_dir().join("gustavio.db"),
+            tcp_port: TCP_PORT,
+            discovery: DiscoveryConfig::default(),
+            limits: Limits::default(),
+            heartbeat: Heartbeat::default(),
+            reconnect: ReconnectConfig::default(),
+            relay: false,
+            auto_away: false,
+            workspaces: Vec::new(),
+            transport: Arc::new(NetTransport::default()),
+        }
+    }
+}
+
+/// Requests a consumer can make of a running node.
+#[derive(Debug, Clone)]
+pub enum Command {
+    SetUsername {
+        username: String,
+    },
+    /// Change our avatar, status text and presence
+    SetProfile {
+        status: String,
+        presence: Presence,
+        avatar: Option<String>,
+    },
+    /// Turn away after `after_secs` without activity, 0 for never, and
+    /// answer DMs meanwhile with `reply`, empty for not at all
+    SetAutoAway {
+        after_secs: u64,
+        reply: String,
+    },
+    /// The user typed or clicked in the app
+    Activity,
+    SendMessage {
+        peer_id: String,
+        content: String,
+    },
+    SendGroupMessage {
+        group_id: String,
+        content: String,
+    },
+    LoadHistory {
+        conversation_id: String,
+    },
+    CreateGroup {
+        name: String,
+        members: Vec<String>,
+    },
+    GetPeers,
+    GetGroups,
+    /// Add a peer by `host:port`, for when discovery can't reach it
+    AddPeer {
+        address: String,
+    },
+    RemovePeer {
+        address: String,
+    },
+    /// Join a workspace, or change the secret of one we're in
+    JoinWorkspace {
+        name: String,
+        secret: String,
+    },
+    LeaveWorkspace {
+        name: String,
+    },
+    /// Issue a one-time code that lets its holder into a workspace we're in
+    InviteToWorkspace {
+        name: String,
+    },
+    InviteToGroup {
+        group_id: String,
+    },
+    /// Join whatever a code from `InviteToWorkspace`/`InviteToGroup` is for
+    RedeemInvite {
+        code: String,
+    },
+    MarkRead {
+        conversation_id: String,
+    },
+    /// Say goodbye to peers, finish pending work and stop the node
+    Shutdown,
+}
+
+/// Handle to a running node.
+#[derive(Clone)]
+pub struct NodeHandle {
+    pub commands: mpsc::UnboundedSender<Command>,
+    /// Live peer and connection tables
+    pub state: Arc<SharedState>,
+}
+
+/// Spawn a node on the current tokio runtime.
+pub fn spawn(config: NodeConfig, events: Events) -> NodeHandle {
+    let (tx, rx) = mpsc::unbounded_channel::<Command>();
+    let state = SharedState::new(
+        config.transport.clone(),
+        config.limits.clone(),
+        config.heartbeat.clone(),
+    );
+    tokio::spawn(run(config, state.clone(), rx, events));
+    NodeHandle {
+        commands: tx,
+        state,
+    }
+}
+
+/// Run a node until it is told to shut down or the command channel closes.
+pub async fn run(
+    config: NodeConfig,
+    state: Arc<SharedState>,
+    mut rx: mpsc::UnboundedReceiver<Command>,
+    events: Events,
+) {
+    let db = Storage::open(&config.db_path).expect("Failed to open database");
+    let peer_id = db.peer_id().await;
+    let username = db.get_config("username").await;
+
+    events.emit(Event::ConfigLoaded {
+        peer_id: peer_id.clone(),
+        username: username.clone(),
+    });
+    let ours = profile::load(&db).await;
+    *state.profile.lock().await = ours.clone();
+    events.emit(Event::MyProfile(ours));
+    away::send_settings(&db, &events).await;
+
+    let links = Reconnector::new(
+        peer_id.clone(),
+        state.clone(),
+        db.clone(),
+        events.clone(),
+        config.reconnect.clone(),
+    );
+    let outbox = Outbox::new(links.clone());
+    let gossip = tokio::spawn(gossip::run(links.clone(), config.discovery.gossip_interval));
+    let router = routing::start(peer_id.clone(), links.clone());
+    let routes = tokio::spawn(routing::run(
+        state.clone(),
+        config.discovery.gossip_interval,
+    ));
+    let queued = tokio::spawn(contacts::deliver_queued(
+        outbox.clone(),
+        state.clone(),
+        db.clone(),
+        events.clone(),
+    ));
+    let idle = away::start(
+        peer_id.clone(),
+        config.auto_away,
+        outbox.clone(),
+        state.clone(),
+        db.clone(),
+        events.clone(),
+    )
+    .await;
+    if config.relay {
+        relay::enable(peer_id.clone(), &state);
+    }
+    let mut ours = db.get_workspaces().await;
+    for w in &config.workspaces {
+        ours.retain(|o| o.name != w.name);
+        ours.push(w.clone());
+    }
+    workspace::apply(ours, &state, &events).await;
+    let static_peers = StaticPeers::new(peer_id.clone(), state.clone(), db.clone(), events.clone());
+    // Tasks recording the outcome of sends, awaited on shutdown
+    let mut recording = JoinSet::new();
+
+    let mut networking = None;
+    if let Some(username) = &username {
+        networking = Some(
+            start_networking(
+                peer_id.clone(),
+                username.clone(),
+                &config,
+                &static_peers,
+                state.clone(),
+                db.clone(),
+                events.clone(),
+            )
+            .await,
+        );
+        events.emit(Event::GroupList(db.get_groups().await));
+    }
+
+    while let Some(cmd) = rx.recv().await {
+        while recording.try_join_next().is_some() {}
+        match cmd {
+            Command::SetUsername { username } => {
+                db.set_config("username", &username);
+                let old = std::mem::replace(&mut *state.username.lock().await, username.clone());
+                match &mut networking {
+                    None => {
+                        networking = Some(
+                            start_networking(
+                                peer_id.clone(),
+                                username.clone(),
+                                &config,
+                                &static_peers,
+                                state.clone(),
+                                db.clone(),
+                                events.clone(),
+                            )
+                            .await,
+                        );
+                    }
+                    // Live rename: connected peers hear it now, the rest
+                    // with our next announce
+                    Some(networking) if old != username => {
+                        networking.discovery.rename(&username);
+                        profile::publish(&state).await;
+                    }
+                    Some(_) => {}
+                }
+                events.emit(Event::ConfigLoaded {
+                    peer_id: peer_id.clone(),
+                    username: Some(username),
+                });
+            }
+
+            Command::SetProfile {
+                status,
+                presence,
+                avatar,
+            } => {
+                let ours = Profile {
+                    status,
+                    presence,
+                    avatar,
+                };
+                profile::set(ours, &state, &db, &events).await;
+            }
+
+            Command::SetAutoAway { after_secs, reply } => {
+                away::configure(after_secs, reply, &state, &db, &events).await;
+            }
+
+            Command::Activity => {
+                if let Some(away) = state.away.get() {
+                    away.active();
+                }
+            }
+
+            Command::SendMessage {
+                peer_id: target_id,
+                content,
+            } => {
+                let timestamp = chrono::Utc::now().to_rfc3339();
+                let msg_id = uuid::Uuid::new_v4().to_string();
+
+                let uname = db.get_config("username").await.unwrap_or_default();
+
+                let tcp_msg = TcpMessage::DirectMessage {
+                    id: msg_id.clone(),
+                    from_id: peer_id.clone(),
+                    from_name: uname.clone(),
+                    content: content.clone(),
+                    timestamp: timestamp.clone(),
+                    to: Some(target_id.clone()),
+                    hops: MAX_HOPS,
+                };
+
+                let mut row = MessageRow {
+                    id: msg_id,
+                    conversation_id: target_id,
+                    from_id: peer_id.clone(),
+                    from_name: uname,
+                    content,
+                    timestamp,
+                    is_group: false,
+                    status: String::new(),
+                };
+
+                // Offline: keep it until the peer is back
+                if !contacts::online(&row.conversation_id, &state).await {
+                    row.status = contacts::QUEUED.into();
+                    db.insert_message(row.clone());
+                    events.emit(Event::MessageSent(row));
+                    continue;
+                }
+                let sent = outbox.send(&row.conversation_id, tcp_msg).await;
+
+                // Record the outcome once the peer's actor is done with it
+                let db = db.clone();
+                let events = events.clone();
+                recording.spawn(async move {
+                    row.status = match sent.await {
+                        Ok(Ok(())) => "sent".into(),
+                        Ok(Err(e)) => {
+                            events.emit(Event::Error(format!("Envio falhou: {e}")));
+                            "failed".into()
+                        }
+                        Err(_) => "failed".into(),
+                    };
+                    db.insert_message(row.clone());
+                    events.emit(Event::MessageSent(row));
+                });
+            }
+
+            Command::SendGroupMessage { group_id, content } => {
+                let timestamp = chrono::Utc::now().to_rfc3339();
+                let msg_id = uuid::Uuid::new_v4().to_string();
+
+                let uname = db.get_config("username").await.unwrap_or_default();
+
+                let members = db.get_group_members(&group_id).await;
+
+                let mut pending = Vec::new();
+                for member_id in members {
+                    if member_id == peer_id {
+                        continue;
+                    }
+                    let tcp_msg = TcpMessage::GroupMessage {
+                        id: msg_id.clone(),
+                        group_id: group_id.clone(),
+                        from_id: peer_id.clone(),
+                        from_name: uname.clone(),
+                        content: content.clone(),
+                        timestamp: timestamp.clone(),
+                        to: Some(member_id.clone()),
+                        hops: MAX_HOPS,
+                    };
+                    let sent = outbox.send(&member_id, tcp_msg).await;
+                    pending.push((member_id, sent));
+                }
+
+                let row = MessageRow {
+                    id: msg_id,
+                    conversation_id: group_id,
+                    from_id: peer_id.clone(),
+                    from_name: uname,
+                    content,
+                    timestamp,
+                    is_group: true,
+                    status: "sent".into(),
+                };
+
+                let db = db.clone();
+                let events = events.clone();
+                recording.spawn(async move {
+                    for (member_id, sent) in pending {
+                        if let Ok(Err(e)) = sent.await {
+                            eprintln!("Group send to {member_id}: {e}");
+                        }
+                    }
+                    db.insert_message(row.clone());
+                    events.emit(Event::MessageSent(row));
+                });
+            }
+
+            Command::LoadHistory { conversation_id } => {
+                let messages = db.load_history(&conversation_id, 200).await;
+                events.emit(Event::History(messages));
+            }
+
+            Command::CreateGroup { name, members } => {
+                let group_id = uuid::Uuid::new_v4().to_string();
+                let mut all_members = members.clone();
+                all_members.push(peer_id.clone());
+                db.create_group(&group_id, &name, &peer_id, &all_members);
+
+                let tcp_msg = TcpMessage::GroupCreate {
+                    group_id: group_id.clone(),
+                    name: name.clone(),
+                    creator_id: peer_id.clone(),
+                    members: all_members,
+                };
+                // Fire and forget: members we miss learn of the group later
+                for member_id in &members {
+                    drop(outbox.send(member_id, tcp_msg.clone()).await);
+                }
+
+                events.emit(Event::GroupList(db.get_groups().await));
+                events.emit(Event::GroupCreated(group_id));
+            }
+
+            Command::GetPeers => {
+                discovery::send_peer_list(&state, &events).await;
+                static_peers.emit().await;
+                let ours = state.workspaces.lock().await;
+                events.emit(Event::Workspaces(
+                    ours.iter().map(|w| w.name.clone()).collect(),
+                ));
+                drop(ours);
+                contacts::send_contacts(&db, &events).await;
+                events.emit(Event::MyProfile(state.profile.lock().await.clone()));
+                away::send_settings(&db, &events).await;
+            }
+
+            Command::GetGroups => {
+                events.emit(Event::GroupList(db.get_groups().await));
+            }
+
+            Command::AddPeer { address } => {
+                if let Err(e) = static_peers.add(&address).await {
+                    events.emit(Event::Error(e));
+                }
+            }
+
+            Command::RemovePeer { address } => static_peers.remove(&address).await,
+
+            Command::JoinWorkspace { name, secret } => {
+                let name = name.trim().to_string();
+                if name.is_empty() || secret.is_empty() {
+                    events.emit(Event::Error("A workspace needs a name and a secret".into()));
+                } else {
+                    let joined = Workspace { name, secret };
+                    workspace::enter(joined, &db, &state, &events).await;
+                }
+            }
+
+            Command::LeaveWorkspace { name } => {
+                workspace::leave(&name, &db, &state, &events).await;
+            }
+
+            Command::InviteToWorkspace { name } => {
+                let target = InviteTarget::Workspace(name);
+                match invite::create(target, config.tcp_port, &state, &db).await {
+                    Ok(code) => events.emit(Event::InviteCreated(code)),
+                    Err(e) => events.emit(Event::Error(e)),
+                }
+            }
+
+            Command::InviteToGroup { group_id } => {
+                let target = InviteTarget::Group(group_id);
+                match invite::create(target, config.tcp_port, &state, &db).await {
+                    Ok(code) => events.emit(Event::InviteCreated(code)),
+                    Err(e) => events.emit(Event::Error(e)),
+                }
+            }
+
+            Command::RedeemInvite { code } => {
+                let (me, outbox) = (peer_id.clone(), outbox.clone());
+                let (state, db, events) = (state.clone(), db.clone(), events.clone());
+                tokio::spawn(async move {
+                    let username = state.username.lock().await.clone();
+                    let redeemed =
+                        invite::redeem(&code, &me, &username, &outbox, &state, &db, &events);
+                    if let Err(e) = redeemed.await {
+                        events.emit(Event::Error(format!("Convite falhou: {e}")));
+                    }
+                });
+            }
+
+            Command::MarkRead { .. } => {}
+
+            Command::Shutdown => break,
+        }
+    }
+
+    // ── Shutdown ─────────────────────────────────────────────
+    if let Some(networking) = networking {
+        networking.stop().await;
+    }
+    gossip.abort();
+    routes.abort();
+    router.abort();
+    queued.abort();
+    idle.abort();
+    let drain = async {
+        outbox.close().await;
+        while recording.join_next().await.is_some() {}
+    };
+    if tokio::time::timeout(SHUTDOWN_GRACE, drain).await.is_err() {
+        eprintln!("Gave up on queued messages after {SHUTDOWN_GRACE:?}");
+    }
+    links.stop().await;
+    network::close_all(&state).await;
+    // Whoever is still online was last seen now
+    let online: Vec<PeerInfo> = state.peers.lock().await.values().cloned().collect();
+    contacts::left(&online, &db, &events).await;
+    db.flush().await;
+    events.emit(Event::Stopped);
+}
+
+/// The listener, discovery and static peer probes of a node that has an
+/// identity.
+pub struct Networking {
+    listener: JoinHandle<()>,
+    discovery: Discovery,
+    prober: JoinHandle<()>,
+}
+
+impl Networking {
+    /// Stop accepting connections and announce that we're leaving.
+    pub async fn stop(self) {
+        self.listener.abort();
+        self.prober.abort();
+        self.discovery.goodbye().await;
+    }
+}
+
+/// Spawn the TCP listener, UDP discovery and static peer probes for our
+/// identity.
+pub async fn start_networking(
+    peer_id: String,
+    username: String,
+    config: &NodeConfig,
+    static_peers: &StaticPeers,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+) -> Networking {
+    *state.username.lock().await = username;
+    let tcp_port = config.tcp_port;
+    let discovery_config = config.discovery.clone();
+    let prober = tokio::spawn(static_peers.clone().run(discovery_config.peer_timeout / 2));
+
+    let pid = peer_id.clone();
+    let st = state.clone();
+    let d = db.clone();
+    let ev = events.clone();
+    let listener = tokio::spawn(async move {
+        network::run_listener(pid, tcp_port, st, d, ev).await;
+    });
+
+    let discovery = discovery::run(peer_id, tcp_port, discovery_config, state, db, events).await;
+    Networking {
+        listener,
+        discovery,
+        prober,
+    }
+}
@@ -0,0 +1,120 @@
+//! Outgoing messages. Each peer gets an actor task that owns sending to it:
+//! it drains a bounded queue in order, connecting and reconnecting as needed,
+//! so a slow or unreachable peer only ever holds up its own messages.
+
+use crate::network;
+use crate::protocol::TcpMessage;
+use crate::reconnect::Reconnector;
+use crate::routing;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::{oneshot, Mutex as TokioMutex};
+use tokio::task::JoinHandle;
+
+/// Messages a peer's queue holds before new ones are refused.
+pub const QUEUE_DEPTH: usize = 64;
+
+/// Resolves once the message was written to the peer, or given up on.
+pub type Sent = oneshot::Receiver<Result<(), String>>;
+
+struct Outgoing {
+    msg: TcpMessage,
+    done: oneshot::Sender<Result<(), String>>,
+}
+
+/// A peer's queue and the actor draining it.
+struct PeerQueue {
+    tx: mpsc::Sender<Outgoing>,
+    actor: JoinHandle<()>,
+}
+
+/// Handle to the per-peer send queues. Cheap to clone.
+#[derive(Clone)]
+pub struct Outbox {
+    links: Reconnector,
+    queues: Arc<TokioMutex<HashMap<String, PeerQueue>>>,
+}
+
+impl Outbox {
+    pub fn new(links: Reconnector) -> Self {
+        Self {
+            links,
+            queues: Arc::new(TokioMutex::new(HashMap::new())),
+        }
+    }
+
+    /// Queue `msg` for `peer_id` and return at once. A full queue fails the
+    /// message right away instead of making the caller wait.
+    pub async fn send(&self, peer_id: &str, msg: TcpMessage) -> Sent {
+        self.links.keep(peer_id).await;
+        let (done, sent) = oneshot::channel();
+        let mut queues = self.queues.lock().await;
+        let queue = queues
+            .entry(peer_id.to_string())
+            .or_insert_with(|| self.spawn_actor(peer_id));
+        if let Err(e) = queue.tx.try_send(Outgoing { msg, done }) {
+            let (reason, out) = match e {
+                TrySendError::Full(out) => ("Fila de envio cheia", out),
+                TrySendError::Closed(out) => ("Fila de envio fechada", out),
+            };
+            let _ = out.done.send(Err(reason.into()));
+        }
+        sent
+    }
+
+    fn spawn_actor(&self, peer_id: &str) -> PeerQueue {
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+        let actor = tokio::spawn(run_peer(peer_id.to_string(), rx, self.clone()));
+        PeerQueue { tx, actor }
+    }
+
+    /// Stop taking messages and wait for every queue to drain.
+    pub async fn close(&self) {
+        let queues = std::mem::take(&mut *self.queues.lock().await);
+        for (_, queue) in queues {
+            drop(queue.tx);
+            let _ = queue.actor.await;
+        }
+    }
+
+    /// Try to connect to a peer if not already connected.
+    async fn ensure_connected(&self, target_peer_id: &str) {
+        if let Err(e) = self.links.connect(target_peer_id).await {
+            eprintln!("Connect to peer failed: {e}");
+        }
+    }
+
+    /// Send message to peer with retry: if first send fails, drop dead
+    /// connection, reconnect, and try once more. Frames that name their
+    /// recipient go by way of other peers when we can't reach it ourselves.
+    async fn send_with_retry(&self, target_peer_id: &str, msg: &TcpMessage) -> Result<(), String> {
+        let state = self.links.state();
+        let hop = match msg.route() {
+            Some(_) => routing::next_hop(target_peer_id, state).await,
+            None => target_peer_id.to_string(),
+        };
+        // First attempt
+        self.ensure_connected(&hop).await;
+        match network::send_to_peer(&hop, msg, state).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                eprintln!("Send failed (will retry): {e}");
+            }
+        }
+
+        // Remove dead connection and retry
+        network::remove_connection(&hop, state).await;
+        self.ensure_connected(&hop).await;
+        network::send_to_peer(&hop, msg, state).await
+    }
+}
+
+/// Actor for one peer: deliver queued messages in order until the node stops.
+async fn run_peer(peer_id: String, mut rx: mpsc::Receiver<Outgoing>, outbox: Outbox) {
+    while let Some(out) = rx.recv().await {
+        let result = outbox.send_with_retry(&peer_id, &out.msg).await;
+        let _ = out.done.send(result);
+    }
+}
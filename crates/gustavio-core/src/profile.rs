@@ -0,0 +1,132 @@
+//! Profiles: the avatar, status text and presence a peer shows next to its
+//! name. Ours is saved in the config table and sent in a `Profile` to each
+//! peer as we connect, then to every connected one when it changes; our
+//! announces carry just the presence, for peers we hold no session with.
+//! Peers' profiles are kept in [`SharedState::profiles`] and cached in the
+//! `peers` table for when they're offline.
+
+use crate::contacts;
+use crate::discovery;
+use crate::events::{Event, Events};
+use crate::network;
+use crate::protocol::{Presence, TcpMessage, CAP_PROFILE};
+use crate::state::SharedState;
+use crate::storage::Storage;
+
+/// Longest status text kept, in characters.
+pub const MAX_STATUS_CHARS: usize = 140;
+/// Largest avatar kept, as a data URL; the UI scales pictures down well
+/// below this.
+pub const MAX_AVATAR_LEN: usize = 32 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Profile {
+    /// Free text such as "em reunião até 15h"
+    pub status: String,
+    pub presence: Presence,
+    /// `data:image/...` URL
+    pub avatar: Option<String>,
+}
+
+impl Profile {
+    /// The profile with its status cut to length, and its avatar dropped
+    /// unless it's an image URL of acceptable size.
+    pub fn checked(mut self) -> Self {
+        if let Some((end, _)) = self.status.char_indices().nth(MAX_STATUS_CHARS) {
+            self.status.truncate(end);
+        }
+        self.avatar = self
+            .avatar
+            .filter(|a| a.len() <= MAX_AVATAR_LEN && is_image_url(a));
+        self
+    }
+}
+
+/// Image types an avatar may be in.
+const AVATAR_TYPES: &[&str] = &["png", "jpeg", "gif", "webp"];
+
+/// Whether `url` is a base64 `data:` URL of one of [`AVATAR_TYPES`], and
+/// nothing else: peers pick it, and the UI shows it.
+fn is_image_url(url: &str) -> bool {
+    let Some((kind, data)) = url
+        .strip_prefix("data:image/")
+        .and_then(|rest| rest.split_once(";base64,"))
+    else {
+        return false;
+    };
+    AVATAR_TYPES.contains(&kind)
+        && !data.is_empty()
+        && data
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+}
+
+/// Our profile as saved.
+pub async fn load(db: &Storage) -> Profile {
+    let presence = db.get_config("presence").await.unwrap_or_default();
+    Profile {
+        status: db.get_config("status").await.unwrap_or_default(),
+        presence: Presence::parse(&presence),
+        avatar: db.get_config("avatar").await.filter(|a| !a.is_empty()),
+    }
+}
+
+/// Our `Profile` message.
+pub async fn message(state: &SharedState) -> TcpMessage {
+    let username = state.username.lock().await.clone();
+    let ours = state.profile.lock().await.clone();
+    TcpMessage::Profile {
+        username,
+        status: ours.status,
+        presence: ours.presence,
+        avatar: ours.avatar,
+    }
+}
+
+/// Send our profile to every connected peer that reads them.
+pub async fn publish(state: &SharedState) {
+    network::send_to_all(&message(state).await, CAP_PROFILE, state).await;
+}
+
+/// Switch to `profile`: save it and tell peers and the UI. Away while we
+/// turned away for the user being idle isn't saved: it's ours, not theirs.
+pub async fn set(profile: Profile, state: &SharedState, db: &Storage, events: &Events) {
+    let profile = profile.checked();
+    let idle_away = profile.presence == Presence::Away
+        && state.away.get().is_some_and(|away| away.is_idle_away());
+    db.set_config("status", &profile.status);
+    if !idle_away {
+        db.set_config("presence", profile.presence.as_str());
+    }
+    db.set_config("avatar", profile.avatar.as_deref().unwrap_or_default());
+    apply(profile, state, events).await;
+}
+
+/// Switch to `profile` without saving it, for what we show on the user's
+/// behalf until they're back.
+pub async fn apply(profile: Profile, state: &SharedState, events: &Events) {
+    *state.profile.lock().await = profile.clone();
+    publish(state).await;
+    events.emit(Event::MyProfile(profile));
+}
+
+/// `peer_id` sent us its profile.
+pub async fn received(
+    peer_id: &str,
+    username: &str,
+    profile: Profile,
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) {
+    let profile = profile.checked();
+    contacts::rename(peer_id, username, state, db, events).await;
+    db.set_peer_profile(peer_id, &profile);
+    state
+        .profiles
+        .lock()
+        .await
+        .insert(peer_id.to_string(), profile);
+    discovery::send_peer_list(state, events).await;
+    contacts::send_contacts(db, events).await;
+}
@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+
+// ── UDP Discovery ────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum UdpPacket {
+    #[serde(rename = "announce")]
+    Announce {
+        peer_id: String,
+        username: String,
+        tcp_port: u16,
+        /// Interface addresses the sender can also be reached at
+        #[serde(default)]
+        addrs: Vec<String>,
+        /// Set when a relay announces a peer from another segment: the
+        /// relay's peer id, which frames for the peer are sent through
+        #[serde(default)]
+        via: Option<String>,
+        /// The sender's presence, for peers without a session to it
+        #[serde(default)]
+        presence: Presence,
+    },
+    #[serde(rename = "goodbye")]
+    Goodbye { peer_id: String },
+    /// Another packet, signed for a workspace. `packet` is its JSON and
+    /// `mac` the HMAC over it under the workspace secret; peers from before
+    /// workspaces drop these as unparseable
+    #[serde(rename = "signed")]
+    Signed {
+        workspace: String,
+        packet: String,
+        mac: String,
+    },
+}
+
+// ── TCP Messages ─────────────────────────────────────────────
+
+/// Version of the TCP protocol we speak, sent in `Hello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest peer version we still accept.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Answers `Ping` with `Pong`, and pings back.
+pub const CAP_HEARTBEAT: &str = "heartbeat";
+/// Sends and merges `PeerTable`s.
+pub const CAP_GOSSIP: &str = "gossip";
+/// Passes on frames addressed to other peers, and sends `Routes`.
+pub const CAP_ROUTE: &str = "route";
+/// Re-announces the peers of other segments; only relays advertise it.
+pub const CAP_RELAY: &str = "relay";
+/// Sends and reads `Profile`.
+pub const CAP_PROFILE: &str = "profile";
+/// Optional features we understand, advertised in `Hello`.
+pub const CAPABILITIES: &[&str] = &[CAP_HEARTBEAT, CAP_GOSSIP, CAP_ROUTE, CAP_PROFILE];
+/// Peers a frame may pass through on its way to `to`.
+pub const MAX_HOPS: u8 = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TcpMessage {
+    Hello {
+        peer_id: String,
+        username: String,
+        /// Peers from before versioning send none and read as 0
+        #[serde(default)]
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<String>,
+        /// Sent by nodes in a workspace, for the peer to sign in its
+        /// `WorkspaceProof`
+        #[serde(default)]
+        nonce: Option<String>,
+    },
+    /// Follows `Hello` between nodes in workspaces: the sender's proof for
+    /// each workspace it is in
+    WorkspaceProof {
+        proofs: Vec<Membership>,
+    },
+    /// Sent in place of `Hello` to redeem an invite. The issuer answers
+    /// with `WorkspaceSecret`, `GroupCreate` or `JoinRefused` and hangs up
+    Join {
+        token: String,
+        peer_id: String,
+        username: String,
+    },
+    /// What a workspace invite grants
+    WorkspaceSecret {
+        name: String,
+        secret: String,
+    },
+    JoinRefused {
+        reason: String,
+    },
+    DirectMessage {
+        id: String,
+        from_id: String,
+        from_name: String,
+        content: String,
+        timestamp: String,
+        /// Final recipient, for the peers in between to route by; older
+        /// peers send none
+        #[serde(default)]
+        to: Option<String>,
+        /// Peers the frame may still pass through
+        #[serde(default)]
+        hops: u8,
+    },
+    GroupMessage {
+        id: String,
+        group_id: String,
+        from_id: String,
+        from_name: String,
+        content: String,
+        timestamp: String,
+        #[serde(default)]
+        to: Option<String>,
+        #[serde(default)]
+        hops: u8,
+    },
+    Ack {
+        message_id: String,
+        status: String,
+        /// The peer acking, for peers in between to count the frame
+        /// against; older peers send none
+        #[serde(default)]
+        from_id: Option<String>,
+        #[serde(default)]
+        to: Option<String>,
+        #[serde(default)]
+        hops: u8,
+    },
+    GroupCreate {
+        group_id: String,
+        name: String,
+        creator_id: String,
+        members: Vec<String>,
+    },
+    GroupMemberAdd {
+        group_id: String,
+        peer_id: String,
+    },
+    GroupMemberRemove {
+        group_id: String,
+        peer_id: String,
+    },
+    /// The sender's profile, sent on connect and again when it changes;
+    /// only to peers that advertise `CAP_PROFILE`
+    Profile {
+        username: String,
+        #[serde(default)]
+        status: String,
+        #[serde(default)]
+        presence: Presence,
+        /// `data:image/...` URL
+        #[serde(default)]
+        avatar: Option<String>,
+    },
+    /// The peers the sender knows of, for peers that can't hear each
+    /// other's announces
+    PeerTable {
+        peers: Vec<KnownPeer>,
+    },
+    /// The peers the sender can pass frames on to
+    Routes {
+        routes: Vec<Route>,
+    },
+    /// Heartbeat; answered with `Pong`
+    Ping,
+    Pong,
+    /// A message type added after our version; logged and skipped.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Whether a peer is around to chat. Sent as its name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "&'static str")]
+pub enum Presence {
+    /// Also what states added after our version read as
+    #[default]
+    Online,
+    Away,
+    Busy,
+    /// Do not disturb
+    Dnd,
+}
+
+impl Presence {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Presence::Online => "online",
+            Presence::Away => "away",
+            Presence::Busy => "busy",
+            Presence::Dnd => "dnd",
+        }
+    }
+
+    /// The presence named `s`; `Online` for anything unknown.
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "away" => Presence::Away,
+            "busy" => Presence::Busy,
+            "dnd" => Presence::Dnd,
+            _ => Presence::Online,
+        }
+    }
+}
+
+impl From<String> for Presence {
+    fn from(s: String) -> Self {
+        Presence::parse(&s)
+    }
+}
+
+impl From<Presence> for &'static str {
+    fn from(p: Presence) -> Self {
+        p.as_str()
+    }
+}
+
+/// One entry of a gossiped peer table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownPeer {
+    pub peer_id: String,
+    pub username: String,
+    pub addrs: Vec<String>,
+    pub tcp_port: u16,
+    /// How long ago the sender last heard of this peer
+    pub last_seen_ms: u64,
+    /// Workspaces the sender met the peer in, out of those it shares with
+    /// us
+    #[serde(default)]
+    pub workspaces: Vec<String>,
+}
+
+/// One entry of a `Routes` advert.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub peer_id: String,
+    pub username: String,
+    /// 1 for a peer the sender holds a session with
+    pub hops: u8,
+    /// As in [`KnownPeer::workspaces`]
+    #[serde(default)]
+    pub workspaces: Vec<String>,
+}
+
+/// Proof of being in a workspace: the HMAC under its secret of both
+/// nonces, both ids and who dialed (see `workspace::Proving`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Membership {
+    pub workspace: String,
+    pub mac: String,
+}
+
+impl TcpMessage {
+    /// Our `Hello`, carrying the protocol version and capabilities.
+    pub fn hello(peer_id: &str, username: &str) -> Self {
+        Self::hello_with(peer_id, username, &[], None)
+    }
+
+    /// `hello`, advertising the `extra` capabilities too and carrying the
+    /// `nonce` of a node in workspaces.
+    pub fn hello_with(
+        peer_id: &str,
+        username: &str,
+        extra: &[&str],
+        nonce: Option<String>,
+    ) -> Self {
+        TcpMessage::Hello {
+            peer_id: peer_id.to_string(),
+            username: username.to_string(),
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES
+                .iter()
+                .chain(extra)
+                .map(|c| c.to_string())
+                .collect(),
+            nonce,
+        }
+    }
+
+    /// Final recipient and hops left, for frames peers pass on.
+    pub fn route(&self) -> Option<(&str, u8)> {
+        match self {
+            TcpMessage::DirectMessage {
+                to: Some(to), hops, ..
+            }
+            | TcpMessage::GroupMessage {
+                to: Some(to), hops, ..
+            }
+            | TcpMessage::Ack {
+                to: Some(to), hops, ..
+            } => Some((to, *hops)),
+            _ => None,
+        }
+    }
+
+    /// The frame as it is passed on, with one hop spent.
+    pub fn forwarded(mut self) -> Self {
+        if let TcpMessage::DirectMessage { hops, .. }
+        | TcpMessage::GroupMessage { hops, .. }
+        | TcpMessage::Ack { hops, .. } = &mut self
+        {
+            *hops = hops.saturating_sub(1);
+        }
+        self
+    }
+}
@@ -0,0 +1,233 @@
+//! Keeps sessions open to the peers we're chatting with. Each one gets a
+//! keeper task that redials whenever the connection drops, backing off
+//! exponentially (with jitter) while the peer stays unreachable.
+
+use crate::events::{Event, Events, LinkState};
+use crate::network;
+use crate::state::SharedState;
+use crate::storage::Storage;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex as TokioMutex;
+use tokio::time::Instant;
+
+/// How often a keeper checks that its session is still up.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// Wait after the first failed attempt; doubles with each failure
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// A peer we haven't sent anything to for this long is let go
+    pub keep_for: Duration,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            keep_for: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Handle to the reconnect manager. Cheap to clone.
+#[derive(Clone)]
+pub struct Reconnector {
+    my_peer_id: String,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+    config: ReconnectConfig,
+    /// Peers with a running keeper (peer_id -> last time we sent to them)
+    active: Arc<TokioMutex<HashMap<String, Instant>>>,
+    /// One dial at a time per peer, so the keeper and a sender don't open
+    /// two sessions that then fight over which one survives
+    dials: Arc<TokioMutex<HashMap<String, Arc<TokioMutex<()>>>>>,
+    /// Set on shutdown; no new sessions are opened after that
+    stopped: Arc<AtomicBool>,
+}
+
+impl Reconnector {
+    pub fn new(
+        my_peer_id: String,
+        state: Arc<SharedState>,
+        db: Storage,
+        events: Events,
+        config: ReconnectConfig,
+    ) -> Self {
+        Self {
+            my_peer_id,
+            state,
+            db,
+            events,
+            config,
+            active: Arc::new(TokioMutex::new(HashMap::new())),
+            dials: Arc::new(TokioMutex::new(HashMap::new())),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Note that we're talking to `peer_id`, starting its keeper if needed.
+    pub async fn keep(&self, peer_id: &str) {
+        let mut active = self.active.lock().await;
+        if active.insert(peer_id.to_string(), Instant::now()).is_none() {
+            tokio::spawn(self.clone().keep_open(peer_id.to_string()));
+        }
+    }
+
+    /// Let every peer go and refuse to dial from now on.
+    pub async fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.active.lock().await.clear();
+    }
+
+    /// The live tables the manager watches.
+    pub fn state(&self) -> &SharedState {
+        &self.state
+    }
+
+    /// Connect to a peer unless already connected, trying each of its
+    /// addresses in turn.
+    pub async fn connect(&self, peer_id: &str) -> Result<(), String> {
+        let dial = self
+            .dials
+            .lock()
+            .await
+            .entry(peer_id.to_string())
+            .or_default()
+            .clone();
+        let _dialing = dial.lock().await;
+        if self.stopped.load(Ordering::SeqCst) {
+            return Err("Shutting down".into());
+        }
+        if self.state.connections.lock().await.contains_key(peer_id) {
+            return Ok(());
+        }
+
+        let (addrs, port) = {
+            let peers = self.state.peers.lock().await;
+            match peers.get(peer_id) {
+                Some(p) => (p.addrs.clone(), p.tcp_port),
+                None => return Err("Peer not discovered".into()),
+            }
+        };
+        let my_username = self.state.username.lock().await.clone();
+
+        self.emit(peer_id, LinkState::Connecting);
+        let mut last_err = String::from("No address to dial");
+        for ip in addrs {
+            let dialled = network::connect_to_peer(
+                &ip,
+                port,
+                &self.my_peer_id,
+                &my_username,
+                self.state.clone(),
+                self.db.clone(),
+                self.events.clone(),
+            )
+            .await;
+            match dialled {
+                Ok(answered) if answered == peer_id => {
+                    self.prefer(peer_id, &ip).await;
+                    return Ok(());
+                }
+                Ok(_) => last_err = format!("{ip} is another peer"),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Dial `ip` first next time.
+    async fn prefer(&self, peer_id: &str, ip: &str) {
+        if let Some(p) = self.state.peers.lock().await.get_mut(peer_id) {
+            if let Some(i) = p.addrs.iter().position(|a| a == ip) {
+                let addr = p.addrs.remove(i);
+                p.addrs.insert(0, addr);
+            }
+        }
+    }
+
+    async fn keep_open(self, peer_id: String) {
+        let mut backoff = self.config.initial_backoff;
+        loop {
+            {
+                let mut active = self.active.lock().await;
+                let idle = active.get(&peer_id).map_or(Duration::MAX, |t| t.elapsed());
+                if idle >= self.config.keep_for {
+                    active.remove(&peer_id);
+                    return;
+                }
+            }
+
+            if self.connected_or_relayed(&peer_id).await {
+                backoff = self.config.initial_backoff;
+                tokio::time::sleep(CHECK_INTERVAL).await;
+                continue;
+            }
+
+            match self.connect(&peer_id).await {
+                Ok(()) => backoff = self.config.initial_backoff,
+                Err(e) => {
+                    let wait = jitter(backoff);
+                    eprintln!("Reconnect to {peer_id} failed, retrying in {wait:?}: {e}");
+                    self.emit(
+                        &peer_id,
+                        LinkState::BackingOff {
+                            retry_in_ms: wait.as_millis() as u64,
+                        },
+                    );
+                    tokio::time::sleep(wait).await;
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                }
+            }
+        }
+    }
+
+    /// True while we hold a session with the peer, or reach it through a
+    /// relay or a neighbour that routes for it instead.
+    async fn connected_or_relayed(&self, peer_id: &str) -> bool {
+        let connected: HashSet<String> = {
+            let conns = self.state.connections.lock().await;
+            if conns.contains_key(peer_id) {
+                return true;
+            }
+            conns.keys().cloned().collect()
+        };
+        if self
+            .state
+            .routes
+            .lock()
+            .await
+            .best(peer_id, &connected)
+            .is_some()
+        {
+            return true;
+        }
+        let peers = self.state.peers.lock().await;
+        peers.get(peer_id).is_some_and(|p| p.via.is_some())
+    }
+
+    fn emit(&self, peer_id: &str, state: LinkState) {
+        self.events.emit(Event::Link {
+            peer_id: peer_id.to_string(),
+            state,
+        });
+    }
+}
+
+/// Somewhere between half and all of `backoff`, so peers that lost each
+/// other at the same moment don't keep redialling in lockstep.
+fn jitter(backoff: Duration) -> Duration {
+    // A v4 UUID's second half is random but for its top two bits; 53 of
+    // the rest fill an f64 in [0, 1) evenly
+    let (_, random) = uuid::Uuid::new_v4().as_u64_pair();
+    let r = (random & ((1 << 53) - 1)) as f64 / (1u64 << 53) as f64;
+    backoff.mul_f64(0.5 + r / 2.0)
+}
@@ -0,0 +1,93 @@
+//! Relays bridge LAN segments that can't hear each other's announces, such
+//! as two offices joined by a tunnel that drops broadcasts. A headless relay
+//! runs in each segment and keeps a session with the relays of the others;
+//! they trade peer tables over it, each re-announces the other side's peers
+//! on its own segment, and frames addressed across are routed through them
+//! (see [`crate::routing`]).
+
+use crate::events::Event;
+use crate::node::{self, Command, NodeConfig};
+use crate::protocol::{Presence, UdpPacket};
+use crate::state::SharedState;
+use crate::workspace::Workspace;
+
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Marks a node as a relay. Kept in [`SharedState::relay`].
+pub struct Relay {
+    peer_id: String,
+}
+
+impl Relay {
+    /// Announces for the peers we only reach through others, such as the
+    /// relays of other segments, to go out on ours alongside our own. In
+    /// workspaces, each is signed for the ones of `ours` the peer is in.
+    pub async fn announces(&self, ours: &[Workspace], state: &SharedState) -> Vec<UdpPacket> {
+        let peers = state.peers.lock().await;
+        let profiles = state.profiles.lock().await;
+        let mut pkts = Vec::new();
+        for p in peers.values().filter(|p| p.via.is_some()) {
+            let pkt = UdpPacket::Announce {
+                peer_id: p.peer_id.clone(),
+                username: p.username.clone(),
+                tcp_port: p.tcp_port,
+                addrs: p.addrs.clone(),
+                via: Some(self.peer_id.clone()),
+                presence: profiles
+                    .get(&p.peer_id)
+                    .map_or_else(Presence::default, |profile| profile.presence),
+            };
+            if ours.is_empty() {
+                pkts.push(pkt);
+                continue;
+            }
+            let theirs = ours.iter().filter(|w| p.workspaces.contains(&w.name));
+            pkts.extend(theirs.map(|w| w.sign(&pkt)));
+        }
+        pkts
+    }
+}
+
+/// Make the node behind `state` a relay. Passing frames on is done by
+/// every node; relays also re-announce.
+pub fn enable(peer_id: String, state: &SharedState) {
+    let _ = state.relay.set(Relay { peer_id });
+}
+
+pub struct RelayConfig {
+    /// Name shown in everyone's peer list
+    pub username: String,
+    /// `host:port` of the relays in the other segments, kept connected as
+    /// static peers; one side of each pair is enough
+    pub links: Vec<String>,
+    pub node: NodeConfig,
+}
+
+/// Run a headless relay until the process is killed.
+pub async fn run(config: RelayConfig) {
+    let (ev_tx, mut ev_rx) = mpsc::unbounded_channel::<Event>();
+    let node = NodeConfig {
+        relay: true,
+        ..config.node
+    };
+    let commands = node::spawn(node, Arc::new(ev_tx)).commands;
+    let _ = commands.send(Command::SetUsername {
+        username: config.username,
+    });
+    for address in config.links {
+        let _ = commands.send(Command::AddPeer { address });
+    }
+
+    while let Some(event) = ev_rx.recv().await {
+        match event {
+            Event::StaticPeers(links) => {
+                for link in links {
+                    eprintln!("Relay link {}: {:?}", link.address, link.reachability);
+                }
+            }
+            Event::Error(e) => eprintln!("Relay: {e}"),
+            _ => {}
+        }
+    }
+}
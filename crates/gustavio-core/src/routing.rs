@@ -0,0 +1,365 @@
+//! Multi-hop routing, for peers we can't reach ourselves: client-isolated
+//! Wi-Fi lets laptops reach the desktop but not each other, and relays join
+//! whole segments. Every node passes on frames addressed to someone else.
+//! Connected peers tell each other who they can reach and in how many hops,
+//! and frames go to the neighbour closest to their recipient. Frames seen
+//! before are dropped, and a hop limit stops any that still go round.
+
+use crate::discovery;
+use crate::events::Events;
+use crate::network;
+use crate::protocol::{Route, TcpMessage, CAP_ROUTE, MAX_HOPS};
+use crate::reconnect::Reconnector;
+use crate::state::{PeerInfo, SharedState};
+use crate::storage::Storage;
+use crate::workspace;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Most routes we send or accept in one advert.
+const MAX_ROUTES: usize = 256;
+/// Forwarded frames we remember, to drop them if they come round again.
+const SEEN_CAPACITY: usize = 1024;
+/// Frames for others waiting to be passed on; more are dropped.
+pub const FORWARD_DEPTH: usize = 64;
+
+/// Routes our neighbours advertised (neighbour -> recipient -> route). Only
+/// neighbours we hold a session with count.
+#[derive(Default)]
+pub struct RouteTable {
+    by_neighbour: HashMap<String, HashMap<String, Route>>,
+}
+
+impl RouteTable {
+    /// The connected neighbour fewest hops from `to`.
+    pub fn best<'a>(
+        &'a self,
+        to: &str,
+        connected: &HashSet<String>,
+    ) -> Option<(&'a str, &'a Route)> {
+        self.by_neighbour
+            .iter()
+            .filter(|(n, _)| connected.contains(*n))
+            .filter_map(|(n, routes)| routes.get(to).map(|r| (n.as_str(), r)))
+            .min_by_key(|(n, r)| (r.hops, *n))
+    }
+
+    /// Whether `neighbour` told us it can reach `to`.
+    pub fn reaches(&self, neighbour: &str, to: &str) -> bool {
+        self.by_neighbour
+            .get(neighbour)
+            .is_some_and(|routes| routes.contains_key(to))
+    }
+
+    /// Drop what `neighbour` told us, once its session is gone.
+    pub fn forget(&mut self, neighbour: &str) {
+        self.by_neighbour.remove(neighbour);
+    }
+}
+
+/// Passes on frames addressed to other peers. Kept in
+/// [`SharedState::router`].
+pub struct Router {
+    peer_id: String,
+    /// Frames waiting to go to the next hop, as (next hop, frame)
+    forward: mpsc::Sender<(String, TcpMessage)>,
+    seen: Mutex<Seen>,
+}
+
+impl Router {
+    /// Take over `msg` if it is addressed to someone else, and pass it on
+    /// unless it was seen before, has run out of hops, or is for a peer we
+    /// don't know. False if it's ours to handle.
+    pub async fn pass_on(&self, msg: &TcpMessage, state: &SharedState) -> bool {
+        let Some((to, hops)) = msg.route() else {
+            return false;
+        };
+        if to == self.peer_id {
+            return false;
+        }
+        let first_time = self.seen.lock().unwrap().insert(seen_key(msg, to));
+        if !first_time {
+            eprintln!("Dropping frame for {to}: already passed on");
+        } else if hops == 0 {
+            eprintln!("Dropping frame for {to}: out of hops");
+        } else if let Some(hop) = towards(to, state).await {
+            if self
+                .forward
+                .try_send((hop, msg.clone().forwarded()))
+                .is_err()
+            {
+                eprintln!("Dropping frame for {to}: too many passed on already");
+            }
+        } else {
+            eprintln!("Dropping frame for {to}: unknown peer");
+        }
+        true
+    }
+}
+
+/// Frames we passed on, oldest first.
+#[derive(Default)]
+struct Seen {
+    order: VecDeque<String>,
+    keys: HashSet<String>,
+}
+
+impl Seen {
+    /// False if `key` is already remembered.
+    fn insert(&mut self, key: String) -> bool {
+        if !self.keys.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(old) = self.order.pop_front() {
+                self.keys.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+/// A group message goes out once per member under the same id, and each
+/// member acks it, so the recipient and the ack's sender are part of the key.
+fn seen_key(msg: &TcpMessage, to: &str) -> String {
+    match msg {
+        TcpMessage::Ack {
+            message_id,
+            from_id,
+            ..
+        } => {
+            let from = from_id.as_deref().unwrap_or_default();
+            format!("ack:{message_id}<{from}>{to}")
+        }
+        TcpMessage::DirectMessage { id, .. } | TcpMessage::GroupMessage { id, .. } => {
+            format!("{id}>{to}")
+        }
+        _ => String::new(),
+    }
+}
+
+/// Start passing on frames for others. They go straight to the next hop,
+/// dialled once if need be but not kept open, and at most
+/// [`FORWARD_DEPTH`] wait at a time so they can't crowd out our own. The
+/// returned task runs until aborted.
+pub fn start(peer_id: String, links: Reconnector) -> JoinHandle<()> {
+    let (forward, mut rx) = mpsc::channel::<(String, TcpMessage)>(FORWARD_DEPTH);
+    let _ = links.state().router.set(Router {
+        peer_id,
+        forward,
+        seen: Mutex::new(Seen::default()),
+    });
+    tokio::spawn(async move {
+        while let Some((hop, msg)) = rx.recv().await {
+            // Acks from the recipient tell the sender how it went
+            let sent = match links.connect(&hop).await {
+                Ok(()) => network::send_to_peer(&hop, &msg, links.state()).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = sent {
+                eprintln!("Passing frame on to {hop} failed: {e}");
+            }
+        }
+    })
+}
+
+/// Who to hand a frame for `to` to: `to` itself while we hold a session
+/// with it, else the connected neighbour with the shortest route to it, else
+/// the peer that told us of it, else `to`, to be dialled. None for peers we
+/// don't know at all.
+async fn towards(to: &str, state: &SharedState) -> Option<String> {
+    let connected = connected(state).await;
+    if connected.contains(to) {
+        return Some(to.to_string());
+    }
+    if let Some((neighbour, _)) = state.routes.lock().await.best(to, &connected) {
+        return Some(neighbour.to_string());
+    }
+    let peers = state.peers.lock().await;
+    let known = peers.get(to)?;
+    Some(known.via.clone().unwrap_or_else(|| to.to_string()))
+}
+
+/// [`towards`], falling back to `to` itself, to be dialled.
+pub async fn next_hop(to: &str, state: &SharedState) -> String {
+    towards(to, state).await.unwrap_or_else(|| to.to_string())
+}
+
+async fn connected(state: &SharedState) -> HashSet<String> {
+    state.connections.lock().await.keys().cloned().collect()
+}
+
+/// Every `interval`, tell each connected neighbour that routes who we can
+/// reach: the peers we hold sessions with, then the best of what our other
+/// neighbours told us, one hop further. Routes through a neighbour aren't
+/// sent back to it.
+pub async fn run(state: Arc<SharedState>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let connected = connected(&state).await;
+        let sessions: Vec<Neighbour> = {
+            let sessions = state.sessions.lock().await;
+            sessions
+                .iter()
+                .filter(|(id, _)| connected.contains(*id))
+                .map(|(id, s)| Neighbour {
+                    peer_id: id.clone(),
+                    username: s.username.clone(),
+                    routes: s.capabilities.iter().any(|c| c == CAP_ROUTE),
+                    workspaces: s.workspaces.clone(),
+                })
+                .collect()
+        };
+        let routes = state.routes.lock().await;
+        let mut adverts = Vec::new();
+        for neighbour in sessions.iter().filter(|n| n.routes) {
+            let theirs = &neighbour.workspaces;
+            let mut advert: Vec<Route> = sessions
+                .iter()
+                .filter(|s| s.peer_id != neighbour.peer_id)
+                .filter_map(|s| {
+                    Some(Route {
+                        peer_id: s.peer_id.clone(),
+                        username: s.username.clone(),
+                        hops: 1,
+                        workspaces: workspace::narrow(&s.workspaces, theirs)?,
+                    })
+                })
+                .collect();
+            let neighbour = &neighbour.peer_id;
+            let others: HashSet<String> = connected
+                .iter()
+                .filter(|id| *id != neighbour)
+                .cloned()
+                .collect();
+            let far: HashSet<&String> = routes
+                .by_neighbour
+                .values()
+                .flat_map(|r| r.keys())
+                .filter(|to| *to != neighbour && !connected.contains(*to))
+                .collect();
+            for to in far {
+                let Some((_, r)) = routes.best(to, &others) else {
+                    continue;
+                };
+                let Some(workspaces) = workspace::narrow(&r.workspaces, theirs) else {
+                    continue;
+                };
+                if r.hops < MAX_HOPS {
+                    advert.push(Route {
+                        hops: r.hops + 1,
+                        workspaces,
+                        ..r.clone()
+                    });
+                }
+            }
+            advert.truncate(MAX_ROUTES);
+            adverts.push((neighbour.clone(), advert));
+        }
+        drop(routes);
+
+        for (neighbour, routes) in adverts {
+            let _ = network::send_to_peer(&neighbour, &TcpMessage::Routes { routes }, &state).await;
+        }
+    }
+}
+
+/// A connected peer, as routes are advertised to it.
+struct Neighbour {
+    peer_id: String,
+    username: String,
+    /// Takes `Routes`
+    routes: bool,
+    workspaces: Vec<String>,
+}
+
+/// Take the routes `from` advertised in place of its last ones, leaving out
+/// recipients in workspaces it doesn't share with us. Recipients we hadn't
+/// heard of join the peer table, reached through `from`; they and new names
+/// are saved and shown as for announces.
+pub async fn merge(
+    from: &str,
+    routes: &[Route],
+    state: &SharedState,
+    db: &Storage,
+    events: &Events,
+) {
+    let me = state.router.get().map(|r| r.peer_id.as_str());
+    let Some(theirs) = state
+        .sessions
+        .lock()
+        .await
+        .get(from)
+        .map(|s| s.workspaces.clone())
+    else {
+        return;
+    };
+    let routes: HashMap<String, Route> = routes
+        .iter()
+        .take(MAX_ROUTES)
+        .filter(|r| r.peer_id != from && Some(r.peer_id.as_str()) != me)
+        .filter_map(|r| {
+            let workspaces = workspace::narrow(&r.workspaces, &theirs)?;
+            Some((
+                r.peer_id.clone(),
+                Route {
+                    workspaces,
+                    ..r.clone()
+                },
+            ))
+        })
+        .collect();
+
+    let mut added = false;
+    // Peers to note as announces are, with the name we knew them by
+    let mut noted = Vec::new();
+    let mut peers = state.peers.lock().await;
+    for route in routes.values() {
+        match peers.get_mut(&route.peer_id) {
+            Some(p) if p.indirect && p.via.is_some() => {
+                p.last_seen = Instant::now();
+                workspace::merge(&mut p.workspaces, &route.workspaces);
+                if p.username != route.username {
+                    let old = std::mem::replace(&mut p.username, route.username.clone());
+                    noted.push((p.clone(), Some(old)));
+                    added = true;
+                }
+            }
+            Some(_) => {}
+            None => {
+                let info = PeerInfo {
+                    peer_id: route.peer_id.clone(),
+                    username: route.username.clone(),
+                    addrs: Vec::new(),
+                    tcp_port: 0,
+                    last_seen: Instant::now(),
+                    indirect: true,
+                    via: Some(from.to_string()),
+                    workspaces: route.workspaces.clone(),
+                };
+                peers.insert(info.peer_id.clone(), info.clone());
+                noted.push((info, None));
+                added = true;
+            }
+        }
+    }
+    drop(peers);
+    for (info, old_name) in noted {
+        discovery::note(&info, old_name, db, events).await;
+    }
+    state
+        .routes
+        .lock()
+        .await
+        .by_neighbour
+        .insert(from.to_string(), routes);
+    if added {
+        discovery::send_peer_list(state, events).await;
+    }
+}
@@ -0,0 +1,473 @@
+//! In-process simulated network for deterministic multi-peer tests.
+//!
+//! Every host gets its own IP on a shared [`SimNetwork`]. Links between two
+//! hosts can be delayed, made lossy (datagrams only) or partitioned. Under a
+//! paused tokio clock all of it is reproducible.
+
+use crate::netif::{self, Interface};
+use crate::transport::{BoxFuture, Connection, DatagramSocket, Listener, Transport};
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex as TokioMutex};
+use tokio::task::AbortHandle;
+
+const STREAM_BUFFER: usize = 64 * 1024;
+const FIRST_EPHEMERAL_PORT: u16 = 40000;
+/// Every host sits on a /24 with the hosts sharing its first three octets.
+const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+
+/// Behaviour of the link between two hosts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkConditions {
+    /// One-way latency added to every datagram and stream chunk
+    pub delay: Duration,
+    /// Fraction of datagrams silently dropped (0.0 ..= 1.0)
+    pub loss: f64,
+    /// Nothing passes: connects fail and open connections are reset
+    pub partitioned: bool,
+}
+
+type Datagram = (Vec<u8>, SocketAddr);
+type LinkKey = (IpAddr, IpAddr);
+
+fn link_key(a: IpAddr, b: IpAddr) -> LinkKey {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+struct Inner {
+    sockets: HashMap<SocketAddr, mpsc::UnboundedSender<Datagram>>,
+    /// Multicast groups each datagram socket joined
+    groups: HashMap<SocketAddr, HashSet<IpAddr>>,
+    listeners: HashMap<SocketAddr, mpsc::UnboundedSender<Connection>>,
+    links: HashMap<LinkKey, LinkConditions>,
+    /// Pump tasks of open streams, so partitions can reset them
+    streams: Vec<(LinkKey, AbortHandle)>,
+    next_port: u16,
+    rng: u64,
+}
+
+impl Inner {
+    fn link(&self, a: IpAddr, b: IpAddr) -> LinkConditions {
+        if a == b {
+            return LinkConditions::default();
+        }
+        self.links.get(&link_key(a, b)).copied().unwrap_or_default()
+    }
+
+    /// xorshift64: deterministic for a given seed.
+    fn roll(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn reset_streams(&mut self, key: LinkKey) {
+        self.streams.retain(|(k, handle)| {
+            if *k == key {
+                handle.abort();
+                false
+            } else {
+                !handle.is_finished()
+            }
+        });
+    }
+}
+
+/// A shared simulated LAN. Cheap to clone; clones refer to the same network.
+#[derive(Clone)]
+pub struct SimNetwork {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for SimNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimNetwork {
+    pub fn new() -> Self {
+        Self::with_seed(0x5eed)
+    }
+
+    /// Seed for the datagram loss generator.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                sockets: HashMap::new(),
+                groups: HashMap::new(),
+                listeners: HashMap::new(),
+                links: HashMap::new(),
+                streams: Vec::new(),
+                next_port: FIRST_EPHEMERAL_PORT,
+                rng: seed.max(1),
+            })),
+        }
+    }
+
+    /// A transport for the host at `ip`.
+    pub fn host(&self, ip: impl Into<IpAddr>) -> SimTransport {
+        SimTransport {
+            net: self.clone(),
+            ip: ip.into(),
+            extra: Vec::new(),
+        }
+    }
+
+    pub fn link(&self, a: impl Into<IpAddr>, b: impl Into<IpAddr>) -> LinkConditions {
+        self.inner.lock().unwrap().link(a.into(), b.into())
+    }
+
+    pub fn set_link(&self, a: impl Into<IpAddr>, b: impl Into<IpAddr>, cond: LinkConditions) {
+        let key = link_key(a.into(), b.into());
+        let mut inner = self.inner.lock().unwrap();
+        inner.links.insert(key, cond);
+        if cond.partitioned {
+            inner.reset_streams(key);
+        }
+    }
+
+    /// Cut the link between two hosts, resetting open connections.
+    pub fn partition(&self, a: impl Into<IpAddr>, b: impl Into<IpAddr>) {
+        let (a, b) = (a.into(), b.into());
+        let cond = LinkConditions {
+            partitioned: true,
+            ..self.link(a, b)
+        };
+        self.set_link(a, b, cond);
+    }
+
+    pub fn heal(&self, a: impl Into<IpAddr>, b: impl Into<IpAddr>) {
+        let (a, b) = (a.into(), b.into());
+        let cond = LinkConditions {
+            partitioned: false,
+            ..self.link(a, b)
+        };
+        self.set_link(a, b, cond);
+    }
+
+    pub fn set_delay(&self, a: impl Into<IpAddr>, b: impl Into<IpAddr>, delay: Duration) {
+        let (a, b) = (a.into(), b.into());
+        let cond = LinkConditions {
+            delay,
+            ..self.link(a, b)
+        };
+        self.set_link(a, b, cond);
+    }
+
+    pub fn set_loss(&self, a: impl Into<IpAddr>, b: impl Into<IpAddr>, loss: f64) {
+        let (a, b) = (a.into(), b.into());
+        let cond = LinkConditions {
+            loss,
+            ..self.link(a, b)
+        };
+        self.set_link(a, b, cond);
+    }
+
+    /// Reset every open connection between two hosts without changing the
+    /// link, like a dropped TCP session after laptop sleep.
+    pub fn disconnect(&self, a: impl Into<IpAddr>, b: impl Into<IpAddr>) {
+        let key = link_key(a.into(), b.into());
+        self.inner.lock().unwrap().reset_streams(key);
+    }
+}
+
+/// One host's view of a [`SimNetwork`].
+#[derive(Clone)]
+pub struct SimTransport {
+    net: SimNetwork,
+    ip: IpAddr,
+    /// Interfaces the host lists besides the one at `ip`, in front of it
+    extra: Vec<Interface>,
+}
+
+impl SimTransport {
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// The host with `extra` interfaces too, such as container bridges no
+    /// other host reaches. Only listed, not carried traffic.
+    pub fn with_interfaces(mut self, extra: Vec<Interface>) -> Self {
+        self.extra = extra;
+        self
+    }
+}
+
+impl Transport for SimTransport {
+    fn bind_datagram(&self, port: u16) -> BoxFuture<'_, io::Result<Arc<dyn DatagramSocket>>> {
+        Box::pin(async move {
+            let addr = SocketAddr::new(self.ip, port);
+            let (tx, rx) = mpsc::unbounded_channel();
+            let mut inner = self.net.inner.lock().unwrap();
+            if inner.sockets.contains_key(&addr) {
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
+            inner.sockets.insert(addr, tx);
+            Ok(Arc::new(SimSocket {
+                net: self.net.clone(),
+                addr,
+                rx: TokioMutex::new(rx),
+            }) as Arc<dyn DatagramSocket>)
+        })
+    }
+
+    fn listen(&self, port: u16) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let addr = SocketAddr::new(self.ip, port);
+            let (tx, rx) = mpsc::unbounded_channel();
+            let mut inner = self.net.inner.lock().unwrap();
+            if inner.listeners.contains_key(&addr) {
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
+            inner.listeners.insert(addr, tx);
+            Ok(Box::new(SimListener {
+                net: self.net.clone(),
+                addr,
+                rx,
+            }) as Box<dyn Listener>)
+        })
+    }
+
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            let (listener, local_addr) = {
+                let mut inner = self.net.inner.lock().unwrap();
+                if inner.link(self.ip, addr.ip()).partitioned {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                let listener = inner
+                    .listeners
+                    .get(&addr)
+                    .cloned()
+                    .ok_or(io::ErrorKind::ConnectionRefused)?;
+                let port = inner.next_port;
+                inner.next_port = inner
+                    .next_port
+                    .checked_add(1)
+                    .unwrap_or(FIRST_EPHEMERAL_PORT);
+                (listener, SocketAddr::new(self.ip, port))
+            };
+
+            let (client, client_far) = tokio::io::duplex(STREAM_BUFFER);
+            let (server, server_far) = tokio::io::duplex(STREAM_BUFFER);
+            let (client_far_rd, client_far_wr) = tokio::io::split(client_far);
+            let (server_far_rd, server_far_wr) = tokio::io::split(server_far);
+
+            let up = tokio::spawn(pump(
+                client_far_rd,
+                server_far_wr,
+                self.net.clone(),
+                self.ip,
+                addr.ip(),
+            ));
+            let down = tokio::spawn(pump(
+                server_far_rd,
+                client_far_wr,
+                self.net.clone(),
+                addr.ip(),
+                self.ip,
+            ));
+            {
+                let key = link_key(self.ip, addr.ip());
+                let mut inner = self.net.inner.lock().unwrap();
+                inner.streams.retain(|(_, h)| !h.is_finished());
+                inner.streams.push((key, up.abort_handle()));
+                inner.streams.push((key, down.abort_handle()));
+            }
+
+            let (rd, wr) = tokio::io::split(server);
+            listener
+                .send(Connection {
+                    reader: Box::new(rd),
+                    writer: Box::new(wr),
+                    remote_addr: local_addr,
+                })
+                .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+
+            let (rd, wr) = tokio::io::split(client);
+            Ok(Connection {
+                reader: Box::new(rd),
+                writer: Box::new(wr),
+                remote_addr: addr,
+            })
+        })
+    }
+
+    fn interfaces(&self) -> Vec<Interface> {
+        let broadcast = match self.ip {
+            IpAddr::V4(v4) => netif::broadcast_of(v4, NETMASK),
+            IpAddr::V6(_) => None,
+        };
+        let mut all = self.extra.clone();
+        all.push(Interface {
+            name: "sim0".into(),
+            addr: self.ip,
+            index: 1,
+            broadcast,
+        });
+        all
+    }
+}
+
+/// Carry bytes one way across a link, applying its current conditions.
+async fn pump<R, W>(mut reader: R, mut writer: W, net: SimNetwork, from: IpAddr, to: IpAddr)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 8192];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let cond = net.link(from, to);
+        if cond.partitioned {
+            break;
+        }
+        if !cond.delay.is_zero() {
+            tokio::time::sleep(cond.delay).await;
+        }
+        if writer.write_all(&buf[..n]).await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+struct SimSocket {
+    net: SimNetwork,
+    addr: SocketAddr,
+    rx: TokioMutex<mpsc::UnboundedReceiver<Datagram>>,
+}
+
+impl Drop for SimSocket {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.net.inner.lock() {
+            inner.sockets.remove(&self.addr);
+            inner.groups.remove(&self.addr);
+        }
+    }
+}
+
+/// Limited broadcast, or the directed broadcast of `receiver`'s subnet.
+fn is_broadcast(ip: IpAddr, receiver: IpAddr) -> bool {
+    match (ip, receiver) {
+        (IpAddr::V4(v4), _) if v4 == Ipv4Addr::BROADCAST => true,
+        (IpAddr::V4(v4), IpAddr::V4(rx)) => netif::broadcast_of(rx, NETMASK) == Some(v4),
+        _ => false,
+    }
+}
+
+impl DatagramSocket for SimSocket {
+    fn send_to<'a>(
+        &'a self,
+        data: &'a [u8],
+        target: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(async move {
+            let deliveries: Vec<_> = {
+                let mut inner = self.net.inner.lock().unwrap();
+                let targets: Vec<_> = inner
+                    .sockets
+                    .iter()
+                    .filter(|(addr, _)| {
+                        if is_broadcast(target.ip(), addr.ip()) {
+                            addr.port() == target.port()
+                        } else if target.ip().is_multicast() {
+                            addr.port() == target.port()
+                                && inner
+                                    .groups
+                                    .get(addr)
+                                    .is_some_and(|g| g.contains(&target.ip()))
+                        } else {
+                            **addr == target
+                        }
+                    })
+                    .map(|(addr, tx)| (addr.ip(), tx.clone()))
+                    .collect();
+                let mut out = Vec::new();
+                for (ip, tx) in targets {
+                    let cond = inner.link(self.addr.ip(), ip);
+                    if cond.partitioned || (cond.loss > 0.0 && inner.roll() < cond.loss) {
+                        continue;
+                    }
+                    out.push((tx, cond.delay));
+                }
+                out
+            };
+
+            for (tx, delay) in deliveries {
+                let datagram = (data.to_vec(), self.addr);
+                if delay.is_zero() {
+                    let _ = tx.send(datagram);
+                } else {
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let _ = tx.send(datagram);
+                    });
+                }
+            }
+            Ok(data.len())
+        })
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        Box::pin(async move {
+            let (data, from) = self
+                .rx
+                .lock()
+                .await
+                .recv()
+                .await
+                .ok_or(io::ErrorKind::NotConnected)?;
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            Ok((n, from))
+        })
+    }
+    fn join_multicast(&self, group: IpAddr, _ttl: u32) -> io::Result<()> {
+        let mut inner = self.net.inner.lock().unwrap();
+        inner.groups.entry(self.addr).or_default().insert(group);
+        Ok(())
+    }
+}
+
+struct SimListener {
+    net: SimNetwork,
+    addr: SocketAddr,
+    rx: mpsc::UnboundedReceiver<Connection>,
+}
+
+impl Drop for SimListener {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.net.inner.lock() {
+            inner.listeners.remove(&self.addr);
+        }
+    }
+}
+
+impl Listener for SimListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            self.rx
+                .recv()
+                .await
+                .ok_or_else(|| io::ErrorKind::NotConnected.into())
+        })
+    }
+}
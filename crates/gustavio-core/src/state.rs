@@ -0,0 +1,131 @@
+use crate::away::Away;
+use crate::invite::Invites;
+use crate::limits::{Gate, Limits};
+use crate::network::Heartbeat;
+use crate::profile::Profile;
+use crate::relay::Relay;
+use crate::routing::{RouteTable, Router};
+use crate::transport::{BoxWriter, Transport};
+use crate::workspace::Workspace;
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub username: String,
+    /// Addresses to dial, best first: the last one that worked, then where
+    /// its announces come from, then the ones it announced
+    pub addrs: Vec<String>,
+    pub tcp_port: u16,
+    pub last_seen: tokio::time::Instant,
+    /// Only known from another peer's table; we haven't heard it ourselves
+    pub indirect: bool,
+    /// Peer that told us of this one, or the relay announcing it from
+    /// another segment; frames for it go through there while no better
+    /// route is known
+    pub via: Option<String>,
+    /// Workspaces we met the peer in; empty while we're in none
+    pub workspaces: Vec<String>,
+}
+
+impl PeerInfo {
+    /// The address we'd dial first.
+    pub fn ip(&self) -> &str {
+        self.addrs.first().map_or("", String::as_str)
+    }
+}
+
+/// What a connected peer told us in its `Hello`.
+#[derive(Debug, Clone)]
+pub struct PeerSession {
+    pub username: String,
+    /// Protocol version both sides speak (the lower of the two)
+    pub version: u32,
+    pub capabilities: Vec<String>,
+    /// Workspaces the peer proved it shares with us
+    pub workspaces: Vec<String>,
+}
+
+/// Wraps a connection's write half so it can be shared (stored in state + used by readers for acks).
+pub type SharedWriter = Arc<Mutex<BoxWriter>>;
+
+/// The one live TCP session we keep with a peer.
+#[derive(Clone)]
+pub struct PeerConnection {
+    pub writer: SharedWriter,
+    /// Opened by the lower peer id; wins when both sides dialled at once
+    pub preferred: bool,
+}
+
+/// Tie-break for two peers that connected to each other at the same time:
+/// both keep the connection initiated by the lower peer id.
+pub fn is_preferred(initiator_id: &str, acceptor_id: &str) -> bool {
+    initiator_id < acceptor_id
+}
+
+pub struct SharedState {
+    /// The name we go by, as announced and sent in `Hello`; empty until
+    /// it's set
+    pub username: Mutex<String>,
+    /// Our avatar, status text and presence
+    pub profile: Mutex<Profile>,
+    /// Last profile or presence heard from each peer (peer_id -> profile)
+    pub profiles: Mutex<HashMap<String, Profile>>,
+    /// Discovered peers (peer_id -> info)
+    pub peers: Mutex<HashMap<String, PeerInfo>>,
+    /// Active TCP sessions (peer_id -> connection)
+    pub connections: Mutex<HashMap<String, PeerConnection>>,
+    /// Negotiated protocol details of connected peers (peer_id -> session)
+    pub sessions: Mutex<HashMap<String, PeerSession>>,
+    /// Inbound connection limits and banned IPs
+    pub gate: Mutex<Gate>,
+    pub heartbeat: Heartbeat,
+    /// Set once the node is up; passes on frames for other peers
+    pub router: OnceLock<Router>,
+    /// What connected peers told us they can reach
+    pub routes: Mutex<RouteTable>,
+    /// Set on relay nodes only
+    pub relay: OnceLock<Relay>,
+    /// Set once the node is up; turns us away while the user is idle
+    pub away: OnceLock<Away>,
+    /// Workspaces we're in; see [`crate::workspace`]
+    pub workspaces: Mutex<Vec<Workspace>>,
+    /// Invites we issued that are still open
+    pub invites: Mutex<Invites>,
+    /// Sockets used for discovery and peer connections
+    pub transport: Arc<dyn Transport>,
+}
+
+impl SharedState {
+    pub fn new(transport: Arc<dyn Transport>, limits: Limits, heartbeat: Heartbeat) -> Arc<Self> {
+        Arc::new(Self {
+            username: Mutex::new(String::new()),
+            profile: Mutex::new(Profile::default()),
+            profiles: Mutex::new(HashMap::new()),
+            peers: Mutex::new(HashMap::new()),
+            connections: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            gate: Mutex::new(Gate::new(limits)),
+            heartbeat,
+            router: OnceLock::new(),
+            routes: Mutex::new(RouteTable::default()),
+            relay: OnceLock::new(),
+            away: OnceLock::new(),
+            workspaces: Mutex::new(Vec::new()),
+            invites: Mutex::new(Invites::default()),
+            transport,
+        })
+    }
+
+    /// True if the connected peer advertised `capability` in its `Hello`.
+    pub async fn peer_supports(&self, peer_id: &str, capability: &str) -> bool {
+        self.sessions
+            .lock()
+            .await
+            .get(peer_id)
+            .is_some_and(|s| s.capabilities.iter().any(|c| c == capability))
+    }
+}
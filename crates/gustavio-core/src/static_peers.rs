@@ -0,0 +1,236 @@
+//! Peers added by `host:port`, for networks discovery can't reach (another
+//! VLAN, a VPN). Each address is probed over TCP now and again; the `Hello`
+//! that comes back tells us who is there, and they join the peer table like
+//! any discovered peer.
+
+use crate::discovery;
+use crate::events::{Event, Events, Reachability, StaticPeer};
+use crate::network;
+use crate::node::TCP_PORT;
+use crate::state::{PeerInfo, SharedState};
+use crate::storage::Storage;
+use crate::workspace;
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex as TokioMutex;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Handle to the static peer list. Cheap to clone.
+#[derive(Clone)]
+pub struct StaticPeers {
+    my_peer_id: String,
+    state: Arc<SharedState>,
+    db: Storage,
+    events: Events,
+    /// Probe results by address
+    probes: Arc<TokioMutex<BTreeMap<String, StaticPeer>>>,
+}
+
+impl StaticPeers {
+    pub fn new(my_peer_id: String, state: Arc<SharedState>, db: Storage, events: Events) -> Self {
+        Self {
+            my_peer_id,
+            state,
+            db,
+            events,
+            probes: Arc::new(TokioMutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Load the saved addresses and probe every one of them each
+    /// `interval`, until the task is aborted. Keep `interval` under the
+    /// discovery peer timeout so reachable peers stay listed.
+    pub async fn run(self, interval: Duration) {
+        for address in self.db.get_static_peers().await {
+            self.track(&address).await;
+        }
+        self.emit().await;
+
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            let addresses: Vec<String> = self.probes.lock().await.keys().cloned().collect();
+            let mut round = JoinSet::new();
+            for address in addresses {
+                round.spawn(self.clone().probe(address));
+            }
+            while round.join_next().await.is_some() {}
+        }
+    }
+
+    /// Save a `host:port` (the port defaults to ours) and probe it now.
+    pub async fn add(&self, address: &str) -> Result<(), String> {
+        let address = normalize(address)?;
+        self.db.add_static_peer(&address);
+        self.track(&address).await;
+        self.emit().await;
+        tokio::spawn(self.clone().probe(address));
+        Ok(())
+    }
+
+    /// Stop probing `address`. Whoever was there ages out of the peer
+    /// table like a peer that went quiet.
+    pub async fn remove(&self, address: &str) {
+        self.db.remove_static_peer(address);
+        self.probes.lock().await.remove(address);
+        self.emit().await;
+    }
+
+    /// Report the list and each address's reachability.
+    pub async fn emit(&self) {
+        let list = self.probes.lock().await.values().cloned().collect();
+        self.events.emit(Event::StaticPeers(list));
+    }
+
+    async fn track(&self, address: &str) {
+        self.probes
+            .lock()
+            .await
+            .entry(address.to_string())
+            .or_insert_with(|| StaticPeer {
+                address: address.to_string(),
+                peer_id: None,
+                username: None,
+                reachability: Reachability::Probing,
+            });
+    }
+
+    async fn probe(self, address: String) {
+        let result = self.reach(&address).await;
+
+        let mut probes = self.probes.lock().await;
+        // Removed while we were dialling
+        let Some(entry) = probes.get_mut(&address) else {
+            return;
+        };
+        let before = entry.clone();
+        match result {
+            Ok((peer_id, username)) => {
+                entry.peer_id = Some(peer_id);
+                entry.username = Some(username);
+                entry.reachability = Reachability::Reachable;
+            }
+            Err(error) => entry.reachability = Reachability::Unreachable { error },
+        }
+        let changed = *entry != before;
+        drop(probes);
+        if changed {
+            self.emit().await;
+        }
+    }
+
+    /// Make sure we hold a session with whoever is at `address`, and list
+    /// them as a peer. Returns their id and username.
+    async fn reach(&self, address: &str) -> Result<(String, String), String> {
+        let known = self
+            .probes
+            .lock()
+            .await
+            .get(address)
+            .and_then(|p| p.peer_id.clone());
+        let targets: Vec<SocketAddr> = tokio::net::lookup_host(address)
+            .await
+            .map_err(|e| format!("Can't resolve {address}: {e}"))?
+            .collect();
+
+        let connected = match known {
+            Some(id) if self.state.connections.lock().await.contains_key(&id) => Some(id),
+            _ => None,
+        };
+        let (peer_id, target) = match connected {
+            Some(id) => (id, targets.first().copied()),
+            None => self.dial(&targets).await?,
+        };
+        let (username, workspaces) = match self.state.sessions.lock().await.get(&peer_id) {
+            Some(session) => (session.username.clone(), session.workspaces.clone()),
+            None => return Err(format!("{address} hung up")),
+        };
+
+        let mut peers = self.state.peers.lock().await;
+        let ip = target.map(|t| t.ip().to_string());
+        let listed = match peers.get_mut(&peer_id) {
+            Some(p) => {
+                p.last_seen = Instant::now();
+                p.indirect = false;
+                p.via = None;
+                workspace::merge(&mut p.workspaces, &workspaces);
+                if let Some(ip) = ip.filter(|ip| !p.addrs.contains(ip)) {
+                    p.addrs.push(ip);
+                }
+                true
+            }
+            None => {
+                peers.insert(
+                    peer_id.clone(),
+                    PeerInfo {
+                        peer_id: peer_id.clone(),
+                        username: username.clone(),
+                        addrs: ip.into_iter().collect(),
+                        tcp_port: target.map_or(TCP_PORT, |t| t.port()),
+                        last_seen: Instant::now(),
+                        indirect: false,
+                        via: None,
+                        workspaces,
+                    },
+                );
+                false
+            }
+        };
+        drop(peers);
+        if !listed {
+            discovery::send_peer_list(&self.state, &self.events).await;
+        }
+        Ok((peer_id, username))
+    }
+
+    /// Connect to the first of `targets` that answers.
+    async fn dial(&self, targets: &[SocketAddr]) -> Result<(String, Option<SocketAddr>), String> {
+        let my_username = self.state.username.lock().await.clone();
+        let mut last_err = String::from("No address to dial");
+        for target in targets {
+            match network::connect_to_peer(
+                &target.ip().to_string(),
+                target.port(),
+                &self.my_peer_id,
+                &my_username,
+                self.state.clone(),
+                self.db.clone(),
+                self.events.clone(),
+            )
+            .await
+            {
+                Ok(peer_id) => return Ok((peer_id, Some(*target))),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+/// `host:port` with our port filled in when none is given, IPv6 hosts in
+/// brackets.
+fn normalize(input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if let Ok(addr) = input.parse::<SocketAddr>() {
+        return Ok(addr.to_string());
+    }
+    if let Ok(ip) = input.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, TCP_PORT).to_string());
+    }
+    let (host, port) = match input.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) => (host, port),
+            Err(_) => return Err(format!("Bad port in {input}")),
+        },
+        None => (input, TCP_PORT),
+    };
+    let bad = |c: char| c.is_whitespace() || c == ':' || c == '[' || c == ']';
+    if host.is_empty() || host.contains(bad) {
+        return Err(format!("Bad address {input}"));
+    }
+    Ok(format!("{host}:{port}"))
+}
@@ -0,0 +1,416 @@
+//! The database lives on its own thread so SQLite never blocks a runtime
+//! worker. Callers send typed requests over a channel; writes are queued
+//! without waiting and reads get their answer back on a oneshot.
+
+use crate::db::{ContactRow, Database, GroupRow, MessageRow};
+use crate::profile::Profile;
+use crate::workspace::Workspace;
+
+use std::path::Path;
+use std::sync::mpsc;
+use tokio::sync::oneshot;
+
+/// Most message inserts committed in one transaction.
+const MAX_BATCH: usize = 256;
+
+enum Request {
+    GetConfig {
+        key: String,
+        reply: oneshot::Sender<Option<String>>,
+    },
+    SetConfig {
+        key: String,
+        value: String,
+    },
+    PeerId {
+        reply: oneshot::Sender<String>,
+    },
+    InsertMessage(MessageRow),
+    LoadHistory {
+        conversation_id: String,
+        limit: i64,
+        reply: oneshot::Sender<Vec<MessageRow>>,
+    },
+    UpdateMessageStatus {
+        id: String,
+        status: String,
+    },
+    GetQueuedMessages {
+        reply: oneshot::Sender<Vec<MessageRow>>,
+    },
+    UpsertPeer {
+        peer_id: String,
+        username: String,
+        ip: String,
+    },
+    RenamePeer {
+        peer_id: String,
+        username: String,
+    },
+    SetPeerProfile {
+        peer_id: String,
+        profile: Profile,
+    },
+    GetContacts {
+        reply: oneshot::Sender<Vec<ContactRow>>,
+    },
+    AddStaticPeer {
+        address: String,
+    },
+    RemoveStaticPeer {
+        address: String,
+    },
+    GetStaticPeers {
+        reply: oneshot::Sender<Vec<String>>,
+    },
+    AddWorkspace(Workspace),
+    RemoveWorkspace {
+        name: String,
+    },
+    GetWorkspaces {
+        reply: oneshot::Sender<Vec<Workspace>>,
+    },
+    CreateGroup {
+        group_id: String,
+        name: String,
+        creator_id: String,
+        members: Vec<String>,
+    },
+    AddGroupMember {
+        group_id: String,
+        peer_id: String,
+    },
+    RemoveGroupMember {
+        group_id: String,
+        peer_id: String,
+    },
+    GetGroups {
+        reply: oneshot::Sender<Vec<GroupRow>>,
+    },
+    GetGroupMembers {
+        group_id: String,
+        reply: oneshot::Sender<Vec<String>>,
+    },
+    Flush {
+        reply: oneshot::Sender<()>,
+    },
+}
+
+/// Handle to the storage thread. Cheap to clone; the thread exits once
+/// every handle is dropped.
+#[derive(Clone)]
+pub struct Storage {
+    tx: mpsc::Sender<Request>,
+}
+
+impl Storage {
+    /// Open the database at `path` and start its thread.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let db = Database::open_at(path)?;
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("gustavio-storage".into())
+            .spawn(move || serve(db, rx))
+            .expect("Failed to spawn storage thread");
+        Ok(Self { tx })
+    }
+
+    fn push(&self, req: Request) {
+        if self.tx.send(req).is_err() {
+            eprintln!("Storage thread is gone; write dropped");
+        }
+    }
+
+    /// Send a read and wait for its answer.
+    async fn ask<T>(&self, make: impl FnOnce(oneshot::Sender<T>) -> Request) -> T
+    where
+        T: Default + Send + 'static,
+    {
+        let (reply, answer) = oneshot::channel();
+        self.push(make(reply));
+        wait(answer).await.unwrap_or_default()
+    }
+
+    // ── Config ───────────────────────────────────────────────
+
+    pub async fn get_config(&self, key: &str) -> Option<String> {
+        let key = key.to_string();
+        self.ask(|reply| Request::GetConfig { key, reply }).await
+    }
+
+    pub fn set_config(&self, key: &str, value: &str) {
+        self.push(Request::SetConfig {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    /// Our own peer id, generated on first run.
+    pub async fn peer_id(&self) -> String {
+        self.ask(|reply| Request::PeerId { reply }).await
+    }
+
+    // ── Messages ─────────────────────────────────────────────
+
+    /// Queue a message for insertion; bursts are committed together.
+    pub fn insert_message(&self, row: MessageRow) {
+        self.push(Request::InsertMessage(row));
+    }
+
+    pub async fn load_history(&self, conversation_id: &str, limit: i64) -> Vec<MessageRow> {
+        let conversation_id = conversation_id.to_string();
+        self.ask(|reply| Request::LoadHistory {
+            conversation_id,
+            limit,
+            reply,
+        })
+        .await
+    }
+
+    pub fn update_message_status(&self, id: &str, status: &str) {
+        self.push(Request::UpdateMessageStatus {
+            id: id.to_string(),
+            status: status.to_string(),
+        });
+    }
+
+    pub async fn get_queued_messages(&self) -> Vec<MessageRow> {
+        self.ask(|reply| Request::GetQueuedMessages { reply }).await
+    }
+
+    // ── Peers ────────────────────────────────────────────────
+
+    pub fn upsert_peer(&self, peer_id: &str, username: &str, ip: &str) {
+        self.push(Request::UpsertPeer {
+            peer_id: peer_id.to_string(),
+            username: username.to_string(),
+            ip: ip.to_string(),
+        });
+    }
+
+    pub fn rename_peer(&self, peer_id: &str, username: &str) {
+        self.push(Request::RenamePeer {
+            peer_id: peer_id.to_string(),
+            username: username.to_string(),
+        });
+    }
+
+    pub fn set_peer_profile(&self, peer_id: &str, profile: &Profile) {
+        self.push(Request::SetPeerProfile {
+            peer_id: peer_id.to_string(),
+            profile: profile.clone(),
+        });
+    }
+
+    pub async fn get_contacts(&self) -> Vec<ContactRow> {
+        self.ask(|reply| Request::GetContacts { reply }).await
+    }
+
+    pub fn add_static_peer(&self, address: &str) {
+        self.push(Request::AddStaticPeer {
+            address: address.to_string(),
+        });
+    }
+
+    pub fn remove_static_peer(&self, address: &str) {
+        self.push(Request::RemoveStaticPeer {
+            address: address.to_string(),
+        });
+    }
+
+    /// Addresses of the peers added by hand.
+    pub async fn get_static_peers(&self) -> Vec<String> {
+        self.ask(|reply| Request::GetStaticPeers { reply }).await
+    }
+
+    // ── Workspaces ───────────────────────────────────────────
+
+    pub fn add_workspace(&self, workspace: &Workspace) {
+        self.push(Request::AddWorkspace(workspace.clone()));
+    }
+
+    pub fn remove_workspace(&self, name: &str) {
+        self.push(Request::RemoveWorkspace {
+            name: name.to_string(),
+        });
+    }
+
+    /// The workspaces we joined, with their secrets.
+    pub async fn get_workspaces(&self) -> Vec<Workspace> {
+        self.ask(|reply| Request::GetWorkspaces { reply }).await
+    }
+
+    // ── Groups ───────────────────────────────────────────────
+
+    /// Create a group (if new) and add `members` to it.
+    pub fn create_group(&self, group_id: &str, name: &str, creator_id: &str, members: &[String]) {
+        self.push(Request::CreateGroup {
+            group_id: group_id.to_string(),
+            name: name.to_string(),
+            creator_id: creator_id.to_string(),
+            members: members.to_vec(),
+        });
+    }
+
+    pub fn add_group_member(&self, group_id: &str, peer_id: &str) {
+        self.push(Request::AddGroupMember {
+            group_id: group_id.to_string(),
+            peer_id: peer_id.to_string(),
+        });
+    }
+
+    pub fn remove_group_member(&self, group_id: &str, peer_id: &str) {
+        self.push(Request::RemoveGroupMember {
+            group_id: group_id.to_string(),
+            peer_id: peer_id.to_string(),
+        });
+    }
+
+    pub async fn get_groups(&self) -> Vec<GroupRow> {
+        self.ask(|reply| Request::GetGroups { reply }).await
+    }
+
+    pub async fn get_group_members(&self, group_id: &str) -> Vec<String> {
+        let group_id = group_id.to_string();
+        self.ask(|reply| Request::GetGroupMembers { group_id, reply })
+            .await
+    }
+
+    /// Wait until every write queued before this call is on disk.
+    pub async fn flush(&self) {
+        self.ask(|reply| Request::Flush { reply }).await
+    }
+}
+
+/// The answer to a read; `None` if the storage thread is gone. Waited for
+/// on the blocking pool, which tokio counts as busy: on a runtime with a
+/// paused clock (the simulated-network tests) a plain await would look
+/// idle and let time jump ahead while the storage thread works.
+async fn wait<T: Send + 'static>(answer: oneshot::Receiver<T>) -> Option<T> {
+    tokio::task::spawn_blocking(move || answer.blocking_recv())
+        .await
+        .ok()?
+        .ok()
+}
+
+// ── Storage thread ───────────────────────────────────────────
+
+fn serve(db: Database, rx: mpsc::Receiver<Request>) {
+    let mut next = None;
+    loop {
+        let req = match next.take() {
+            Some(req) => req,
+            None => match rx.recv() {
+                Ok(req) => req,
+                Err(_) => return,
+            },
+        };
+
+        // Gather a burst of inserts into one transaction
+        let Request::InsertMessage(row) = req else {
+            handle(&db, req);
+            continue;
+        };
+        let mut batch = vec![row];
+        while batch.len() < MAX_BATCH {
+            match rx.try_recv() {
+                Ok(Request::InsertMessage(row)) => batch.push(row),
+                Ok(other) => {
+                    next = Some(other);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        match db.insert_messages(&batch) {
+            Ok(failed) => {
+                for (id, e) in failed {
+                    eprintln!("Insert of message {id} failed: {e}");
+                }
+            }
+            Err(e) => eprintln!("Insert of {} messages failed: {e}", batch.len()),
+        }
+    }
+}
+
+fn handle(db: &Database, req: Request) {
+    let result = match req {
+        Request::GetConfig { key, reply } => {
+            let _ = reply.send(db.get_config(&key));
+            Ok(())
+        }
+        Request::SetConfig { key, value } => db.set_config(&key, &value),
+        Request::PeerId { reply } => {
+            let _ = reply.send(db.get_or_create_peer_id());
+            Ok(())
+        }
+        Request::InsertMessage(row) => db.insert_message(&row),
+        Request::LoadHistory {
+            conversation_id,
+            limit,
+            reply,
+        } => {
+            let _ = reply.send(db.load_history(&conversation_id, limit));
+            Ok(())
+        }
+        Request::UpdateMessageStatus { id, status } => db.update_message_status(&id, &status),
+        Request::GetQueuedMessages { reply } => {
+            let _ = reply.send(db.get_queued_messages());
+            Ok(())
+        }
+        Request::UpsertPeer {
+            peer_id,
+            username,
+            ip,
+        } => db.upsert_peer(&peer_id, &username, &ip),
+        Request::RenamePeer { peer_id, username } => db.rename_peer(&peer_id, &username),
+        Request::SetPeerProfile { peer_id, profile } => db.set_peer_profile(&peer_id, &profile),
+        Request::GetContacts { reply } => {
+            let _ = reply.send(db.get_contacts());
+            Ok(())
+        }
+        Request::AddStaticPeer { address } => db.add_static_peer(&address),
+        Request::RemoveStaticPeer { address } => db.remove_static_peer(&address),
+        Request::GetStaticPeers { reply } => {
+            let _ = reply.send(db.get_static_peers());
+            Ok(())
+        }
+        Request::AddWorkspace(w) => db.add_workspace(&w.name, &w.secret),
+        Request::RemoveWorkspace { name } => db.remove_workspace(&name),
+        Request::GetWorkspaces { reply } => {
+            let _ = reply.send(db.get_workspaces());
+            Ok(())
+        }
+        Request::CreateGroup {
+            group_id,
+            name,
+            creator_id,
+            members,
+        } => db
+            .create_group(&group_id, &name, &creator_id)
+            .and_then(|_| {
+                members
+                    .iter()
+                    .try_for_each(|m| db.add_group_member(&group_id, m))
+            }),
+        Request::AddGroupMember { group_id, peer_id } => db.add_group_member(&group_id, &peer_id),
+        Request::RemoveGroupMember { group_id, peer_id } => {
+            db.remove_group_member(&group_id, &peer_id)
+        }
+        Request::GetGroups { reply } => {
+            let _ = reply.send(db.get_groups());
+            Ok(())
+        }
+        Request::GetGroupMembers { group_id, reply } => {
+            let _ = reply.send(db.get_group_members(&group_id));
+            Ok(())
+        }
+        Request::Flush { reply } => {
+            let _ = reply.send(());
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Storage write failed: {e}");
+    }
+}
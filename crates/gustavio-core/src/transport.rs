@@ -0,0 +1,375 @@
+use crate::netif;
+
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::Mutex as TokioMutex;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+pub type BoxReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type BoxWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// An established peer connection, already split into halves.
+pub struct Connection {
+    pub reader: BoxReader,
+    pub writer: BoxWriter,
+    pub remote_addr: SocketAddr,
+}
+
+/// How discovery datagrams and peer connections reach the network.
+/// `NetTransport` uses real sockets; `sim::SimTransport` stays in-process.
+pub trait Transport: Send + Sync + 'static {
+    /// Bind the discovery socket on `port`, able to send broadcasts.
+    fn bind_datagram(&self, port: u16) -> BoxFuture<'_, io::Result<Arc<dyn DatagramSocket>>>;
+
+    /// Accept peer connections on `port`.
+    fn listen(&self, port: u16) -> BoxFuture<'_, io::Result<Box<dyn Listener>>>;
+
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>>;
+
+    /// This host's interfaces, for subnet broadcasts and telling peers
+    /// where else they can reach us.
+    fn interfaces(&self) -> Vec<netif::Interface>;
+
+    /// Whether mDNS can run next to this transport. It opens sockets of its
+    /// own, so only the real network qualifies.
+    fn supports_mdns(&self) -> bool {
+        false
+    }
+}
+
+pub trait DatagramSocket: Send + Sync {
+    fn send_to<'a>(
+        &'a self,
+        data: &'a [u8],
+        target: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>>;
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8])
+        -> BoxFuture<'a, io::Result<(usize, SocketAddr)>>;
+
+    /// Receive datagrams sent to multicast `group`, and send to it, on
+    /// every interface. `ttl` bounds how many routers it may cross. Called
+    /// again, it joins on interfaces that came up since and leaves the ones
+    /// that went away.
+    fn join_multicast(&self, group: IpAddr, ttl: u32) -> io::Result<()>;
+}
+
+pub trait Listener: Send {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>>;
+}
+
+// ── Real sockets ────────────────────────────────────────────
+
+/// UDP broadcast and multicast for discovery, TCP for peer connections.
+#[derive(Debug, Clone)]
+pub struct NetTransport {
+    /// Idle time before the OS starts probing a TCP connection; catches
+    /// sessions that died while we weren't writing to them
+    pub keepalive: Duration,
+}
+
+impl Default for NetTransport {
+    fn default() -> Self {
+        Self {
+            keepalive: Duration::from_secs(15),
+        }
+    }
+}
+
+impl Transport for NetTransport {
+    fn bind_datagram(&self, port: u16) -> BoxFuture<'_, io::Result<Arc<dyn DatagramSocket>>> {
+        Box::pin(async move {
+            let v4 = UdpSocket::from_std(create_broadcast_socket(port)?)?;
+            // IPv6 is optional: without it we still discover over IPv4
+            let v6 = match create_v6_socket(port) {
+                Ok(s) => Some(UdpSocket::from_std(s)?),
+                Err(e) => {
+                    eprintln!("No IPv6 discovery socket: {e}");
+                    None
+                }
+            };
+            Ok(Arc::new(NetSocket {
+                v4,
+                v6,
+                groups: Mutex::new(Vec::new()),
+                sending: TokioMutex::new(()),
+            }) as Arc<dyn DatagramSocket>)
+        })
+    }
+
+    fn listen(&self, port: u16) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            // One socket for both families, where the host has IPv6
+            let listener = match create_dual_stack_listener(port) {
+                Ok(l) => TcpListener::from_std(l)?,
+                Err(e) => {
+                    eprintln!("No IPv6 listener, taking IPv4 peers only: {e}");
+                    TcpListener::bind(("0.0.0.0", port)).await?
+                }
+            };
+            Ok(Box::new(NetListener {
+                listener,
+                keepalive: self.keepalive,
+            }) as Box<dyn Listener>)
+        })
+    }
+
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            let stream = TcpStream::connect(addr).await?;
+            set_keepalive(&stream, self.keepalive)?;
+            Ok(split_stream(stream, addr))
+        })
+    }
+
+    fn interfaces(&self) -> Vec<netif::Interface> {
+        netif::list()
+    }
+
+    fn supports_mdns(&self) -> bool {
+        true
+    }
+}
+
+/// The discovery socket: IPv4 for broadcast and IPv4 multicast, plus IPv6
+/// for link-local multicast when the host has it.
+struct NetSocket {
+    v4: UdpSocket,
+    v6: Option<UdpSocket>,
+    /// Joined groups and the interfaces we joined them on
+    groups: Mutex<Vec<(IpAddr, Vec<netif::Interface>)>>,
+    /// Held while picking the outgoing interface and sending on it
+    sending: TokioMutex<()>,
+}
+
+impl NetSocket {
+    fn v6(&self) -> io::Result<&UdpSocket> {
+        self.v6
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no IPv6 socket"))
+    }
+
+    fn interfaces_for(&self, group: IpAddr) -> Option<Vec<netif::Interface>> {
+        let groups = self.groups.lock().unwrap();
+        groups
+            .iter()
+            .find(|(g, _)| *g == group)
+            .map(|(_, i)| i.clone())
+    }
+
+    /// Send a copy to `target` out of each interface we joined its group on.
+    async fn send_multicast(
+        &self,
+        data: &[u8],
+        target: SocketAddr,
+        interfaces: Vec<netif::Interface>,
+    ) -> io::Result<usize> {
+        let mut sent = Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no interface to send on",
+        ));
+        match target {
+            SocketAddr::V4(target) => {
+                let _sending = self.sending.lock().await;
+                for iface in &interfaces {
+                    let IpAddr::V4(addr) = iface.addr else {
+                        continue;
+                    };
+                    let result = match SockRef::from(&self.v4).set_multicast_if_v4(&addr) {
+                        Ok(()) => self.v4.send_to(data, target).await,
+                        Err(e) => Err(e),
+                    };
+                    sent = sent.or(result);
+                }
+            }
+            SocketAddr::V6(target) => {
+                let v6 = self.v6()?;
+                for iface in &interfaces {
+                    let scoped = SocketAddrV6::new(*target.ip(), target.port(), 0, iface.index);
+                    sent = sent.or(v6.send_to(data, scoped).await);
+                }
+            }
+        }
+        sent
+    }
+}
+
+impl DatagramSocket for NetSocket {
+    fn send_to<'a>(
+        &'a self,
+        data: &'a [u8],
+        target: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(async move {
+            if let Some(interfaces) = self.interfaces_for(target.ip()) {
+                return self.send_multicast(data, target, interfaces).await;
+            }
+            match target {
+                SocketAddr::V4(_) => self.v4.send_to(data, target).await,
+                SocketAddr::V6(_) => self.v6()?.send_to(data, target).await,
+            }
+        })
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        Box::pin(async move {
+            let Some(v6) = &self.v6 else {
+                return self.v4.recv_from(buf).await;
+            };
+            loop {
+                let ready = tokio::select! {
+                    r = self.v4.readable() => r.map(|_| &self.v4),
+                    r = v6.readable() => r.map(|_| v6),
+                }?;
+                match ready.try_recv_from(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result,
+                }
+            }
+        })
+    }
+
+    fn join_multicast(&self, group: IpAddr, ttl: u32) -> io::Result<()> {
+        let mut groups = self.groups.lock().unwrap();
+        let had = groups
+            .iter()
+            .find(|(g, _)| *g == group)
+            .map(|(_, i)| i.clone())
+            .unwrap_or_default();
+        let mut joined = Vec::new();
+        let mut last_err = None;
+        match group {
+            IpAddr::V4(group) => {
+                let sock = SockRef::from(&self.v4);
+                sock.set_multicast_ttl_v4(ttl)?;
+                for iface in netif::list() {
+                    let IpAddr::V4(addr) = iface.addr else {
+                        continue;
+                    };
+                    if had.contains(&iface) {
+                        joined.push(iface);
+                        continue;
+                    }
+                    match sock.join_multicast_v4(&group, &addr) {
+                        Ok(()) => joined.push(iface),
+                        Err(e) => last_err = Some(e),
+                    }
+                }
+                for gone in had.iter().filter(|i| !joined.contains(i)) {
+                    if let IpAddr::V4(addr) = gone.addr {
+                        // Fails where the address went with the interface
+                        let _ = sock.leave_multicast_v4(&group, &addr);
+                    }
+                }
+            }
+            IpAddr::V6(group) => {
+                let sock = SockRef::from(self.v6()?);
+                sock.set_multicast_hops_v6(ttl)?;
+                let on = |list: &[netif::Interface], index| list.iter().any(|i| i.index == index);
+                for iface in netif::list() {
+                    // One join per interface, however many addresses it has
+                    if !iface.addr.is_ipv6() || on(&joined, iface.index) {
+                        continue;
+                    }
+                    if on(&had, iface.index) {
+                        joined.push(iface);
+                        continue;
+                    }
+                    match sock.join_multicast_v6(&group, iface.index) {
+                        Ok(()) => joined.push(iface),
+                        Err(e) => last_err = Some(e),
+                    }
+                }
+                for gone in had.iter().filter(|i| !on(&joined, i.index)) {
+                    let _ = sock.leave_multicast_v6(&group, gone.index);
+                }
+            }
+        }
+        groups.retain(|(g, _)| *g != group);
+        if joined.is_empty() {
+            return Err(last_err.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::AddrNotAvailable, "no interface to join on")
+            }));
+        }
+        groups.push((group, joined));
+        Ok(())
+    }
+}
+
+struct NetListener {
+    listener: TcpListener,
+    keepalive: Duration,
+}
+
+impl Listener for NetListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            let (stream, addr) = self.listener.accept().await?;
+            set_keepalive(&stream, self.keepalive)?;
+            // IPv4 peers reach the dual-stack socket as ::ffff:a.b.c.d
+            let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+            Ok(split_stream(stream, addr))
+        })
+    }
+}
+
+fn set_keepalive(stream: &TcpStream, idle: Duration) -> io::Result<()> {
+    let keepalive = TcpKeepalive::new().with_time(idle);
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    let keepalive = keepalive.with_interval(idle / 3);
+    SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
+
+fn split_stream(stream: TcpStream, remote_addr: SocketAddr) -> Connection {
+    let (rd, wr) = stream.into_split();
+    Connection {
+        reader: Box::new(rd),
+        writer: Box::new(wr),
+        remote_addr,
+    }
+}
+
+fn create_broadcast_socket(port: u16) -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// A TCP listener on `[::]` that takes IPv4 peers too.
+fn create_dual_stack_listener(port: u16) -> io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    // As `TcpListener::bind` does; on Windows it would let others take the port
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    let addr = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0);
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+fn create_v6_socket(port: u16) -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    let addr = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0);
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
@@ -0,0 +1,231 @@
+//! Workspaces keep teams that share a network apart. A workspace is a name
+//! and a secret configured on every member machine. Our announces go out
+//! signed with the secret of each workspace we're in, and announces not
+//! signed for one of ours are dropped. On TCP, each side's `Hello` carries
+//! a nonce and its `WorkspaceProof` signs both, so a session only stands
+//! between peers that share a workspace. A node in none announces unsigned
+//! and only sees others in none, as before workspaces existed.
+//!
+//! Peers remember the workspaces we met them in, which gives each workspace
+//! its own peer list; gossip and routes only tell a peer of the ones it
+//! shares with us.
+
+use crate::discovery;
+use crate::events::{Event, Events};
+use crate::network;
+use crate::protocol::{Membership, UdpPacket};
+use crate::state::SharedState;
+use crate::storage::Storage;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// One side's proof in a handshake: it covers both ids, both nonces and
+/// who dialed, so a node relaying the handshake between two members can't
+/// pass one's proof off on its own connection to the other.
+pub struct Proving<'a> {
+    pub prover: &'a str,
+    pub prover_nonce: &'a str,
+    pub verifier: &'a str,
+    pub verifier_nonce: &'a str,
+    /// The prover opened the connection
+    pub dialed: bool,
+}
+
+impl Proving<'_> {
+    fn parts<'a>(&'a self, workspace: &'a str) -> [&'a str; 7] {
+        let role = if self.dialed { "dialer" } else { "listener" };
+        [
+            "hello",
+            workspace,
+            role,
+            self.prover,
+            self.prover_nonce,
+            self.verifier,
+            self.verifier_nonce,
+        ]
+    }
+}
+
+/// A workspace we're in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    pub name: String,
+    pub secret: String,
+}
+
+impl Workspace {
+    /// HMAC over `parts`, each length-prefixed so they can't run together.
+    fn mac(&self, parts: &[&str]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        for part in parts {
+            mac.update(&(part.len() as u64).to_be_bytes());
+            mac.update(part.as_bytes());
+        }
+        mac
+    }
+
+    fn tag(&self, parts: &[&str]) -> String {
+        hex::encode(self.mac(parts).finalize().into_bytes())
+    }
+
+    fn verify(&self, parts: &[&str], tag: &str) -> bool {
+        hex::decode(tag).is_ok_and(|tag| self.mac(parts).verify_slice(&tag).is_ok())
+    }
+
+    /// `packet` signed for this workspace.
+    pub fn sign(&self, packet: &UdpPacket) -> UdpPacket {
+        let packet = serde_json::to_string(packet).unwrap();
+        UdpPacket::Signed {
+            mac: self.tag(&["packet", &self.name, &packet]),
+            workspace: self.name.clone(),
+            packet,
+        }
+    }
+
+    /// Our answer to a peer's `Hello`, with us as `proving.prover`.
+    pub fn prove(&self, proving: &Proving) -> Membership {
+        Membership {
+            workspace: self.name.clone(),
+            mac: self.tag(&proving.parts(&self.name)),
+        }
+    }
+}
+
+/// `packet` as we send it: once signed for each of `ours`, or unsigned
+/// when we're in no workspace.
+pub fn seal(ours: &[Workspace], packet: UdpPacket) -> Vec<UdpPacket> {
+    if ours.is_empty() {
+        return vec![packet];
+    }
+    ours.iter().map(|w| w.sign(&packet)).collect()
+}
+
+/// A received packet checked against `ours`: the packet inside and the
+/// workspace it was signed for. `None` if it isn't for us: unsigned while
+/// we're in a workspace, signed while we're in none, signed for a workspace
+/// we're not in, or with a MAC that doesn't match.
+pub fn open(ours: &[Workspace], packet: UdpPacket) -> Option<(UdpPacket, Option<String>)> {
+    match packet {
+        UdpPacket::Signed {
+            workspace,
+            packet,
+            mac,
+        } => {
+            let ws = ours.iter().find(|w| w.name == workspace)?;
+            if !ws.verify(&["packet", &workspace, &packet], &mac) {
+                return None;
+            }
+            match serde_json::from_str(&packet).ok()? {
+                UdpPacket::Signed { .. } => None,
+                inner => Some((inner, Some(workspace))),
+            }
+        }
+        packet if ours.is_empty() => Some((packet, None)),
+        _ => None,
+    }
+}
+
+/// Which of `ours` the peer proved it is in, with the peer as
+/// `proving.prover`.
+pub fn check(ours: &[Workspace], proving: &Proving, proofs: &[Membership]) -> Vec<String> {
+    ours.iter()
+        .filter(|w| {
+            proofs
+                .iter()
+                .any(|m| m.workspace == w.name && w.verify(&proving.parts(&w.name), &m.mac))
+        })
+        .map(|w| w.name.clone())
+        .collect()
+}
+
+/// A fresh nonce for our `Hello`, when we're in a workspace.
+pub fn nonce(ours: &[Workspace]) -> Option<String> {
+    (!ours.is_empty()).then(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// The workspaces of an entry (a peer in `entry`) that a peer in `theirs`
+/// may be told of, or tell us of: the ones both are in, `None` if that
+/// leaves nothing. Sessions in no workspace share everything.
+pub fn narrow(entry: &[String], theirs: &[String]) -> Option<Vec<String>> {
+    if theirs.is_empty() {
+        return Some(Vec::new());
+    }
+    let shared: Vec<String> = entry
+        .iter()
+        .filter(|w| theirs.contains(w))
+        .cloned()
+        .collect();
+    (!shared.is_empty()).then_some(shared)
+}
+
+/// Add `more` to `into`, skipping the ones already there.
+pub fn merge(into: &mut Vec<String>, more: &[String]) {
+    for w in more {
+        if !into.contains(w) {
+            into.push(w.clone());
+        }
+    }
+}
+
+/// Join `joined`, or change the secret of the one by its name, and save it.
+pub async fn enter(joined: Workspace, db: &Storage, state: &SharedState, events: &Events) {
+    db.add_workspace(&joined);
+    let mut ours = state.workspaces.lock().await.clone();
+    ours.retain(|w| w.name != joined.name);
+    ours.push(joined);
+    apply(ours, state, events).await;
+}
+
+/// Leave the workspace called `name`.
+pub async fn leave(name: &str, db: &Storage, state: &SharedState, events: &Events) {
+    db.remove_workspace(name);
+    let mut ours = state.workspaces.lock().await.clone();
+    ours.retain(|w| w.name != name);
+    apply(ours, state, events).await;
+}
+
+/// Switch to the workspaces in `ours`. Peers and sessions that share none
+/// of them are dropped; with no workspace left, only those in none stay.
+pub async fn apply(ours: Vec<Workspace>, state: &SharedState, events: &Events) {
+    let names: Vec<String> = ours.iter().map(|w| w.name.clone()).collect();
+    *state.workspaces.lock().await = ours;
+    // Which of `names` an entry in `entry` keeps; `None` to drop it
+    let keep = |entry: &[String]| {
+        if names.is_empty() {
+            entry.is_empty().then(Vec::new)
+        } else {
+            narrow(entry, &names)
+        }
+    };
+
+    state
+        .peers
+        .lock()
+        .await
+        .retain(|_, p| match keep(&p.workspaces) {
+            Some(kept) => {
+                p.workspaces = kept;
+                true
+            }
+            None => false,
+        });
+    let outsiders: Vec<String> = {
+        let mut sessions = state.sessions.lock().await;
+        let mut outsiders = Vec::new();
+        for (peer_id, session) in sessions.iter_mut() {
+            match keep(&session.workspaces) {
+                Some(kept) => session.workspaces = kept,
+                None => outsiders.push(peer_id.clone()),
+            }
+        }
+        outsiders
+    };
+    network::close(&outsiders, state).await;
+
+    discovery::send_peer_list(state, events).await;
+    events.emit(Event::Workspaces(names));
+}
@@ -0,0 +1,94 @@
+//! Framing and version handling of the peer wire format.
+
+use gustavio_core::codec::{self, MAX_FRAME_SIZE};
+use gustavio_core::protocol::{TcpMessage, PROTOCOL_VERSION};
+
+use tokio::io::AsyncWriteExt;
+
+#[tokio::test]
+async fn frames_round_trip() {
+    let (mut a, mut b) = tokio::io::duplex(64 * 1024);
+    let msg = TcpMessage::Ack {
+        message_id: "m1".into(),
+        status: "delivered".into(),
+        from_id: None,
+        to: None,
+        hops: 0,
+    };
+    codec::write_frame(&mut a, &msg).await.unwrap();
+    codec::write_frame(&mut a, &TcpMessage::hello("p1", "ana"))
+        .await
+        .unwrap();
+    drop(a);
+
+    let body = codec::read_frame(&mut b, MAX_FRAME_SIZE).await.unwrap().unwrap();
+    assert!(matches!(
+        codec::decode(&body).unwrap(),
+        TcpMessage::Ack { message_id, .. } if message_id == "m1"
+    ));
+    let body = codec::read_frame(&mut b, MAX_FRAME_SIZE).await.unwrap().unwrap();
+    assert!(matches!(
+        codec::decode(&body).unwrap(),
+        TcpMessage::Hello { version, .. } if version == PROTOCOL_VERSION
+    ));
+    assert!(codec::read_frame(&mut b, MAX_FRAME_SIZE).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn oversized_frame_is_rejected_before_reading_it() {
+    let (mut a, mut b) = tokio::io::duplex(64);
+    let len = (MAX_FRAME_SIZE as u32 + 1).to_be_bytes();
+    a.write_all(&len).await.unwrap();
+
+    let err = codec::read_frame(&mut b, MAX_FRAME_SIZE).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn oversized_message_is_not_sent() {
+    let (mut a, _b) = tokio::io::duplex(64);
+    let msg = TcpMessage::DirectMessage {
+        id: "m1".into(),
+        from_id: "p1".into(),
+        from_name: "ana".into(),
+        content: "x".repeat(MAX_FRAME_SIZE),
+        timestamp: String::new(),
+        to: None,
+        hops: 0,
+    };
+    assert!(codec::write_frame(&mut a, &msg).await.is_err());
+}
+
+#[tokio::test]
+async fn old_newline_peers_are_recognised() {
+    let (mut a, mut b) = tokio::io::duplex(256);
+    a.write_all(b"{\"type\":\"Hello\",\"peer_id\":\"p1\",\"username\":\"ana\"}\n")
+        .await
+        .unwrap();
+
+    let err = codec::read_frame(&mut b, MAX_FRAME_SIZE).await.unwrap_err();
+    assert!(err.to_string().contains("newline"));
+}
+
+#[test]
+fn unknown_message_types_decode_as_unknown() {
+    let body = br#"{"type":"Reaction","message_id":"m1","emoji":"+1"}"#;
+    assert!(matches!(codec::decode(body).unwrap(), TcpMessage::Unknown));
+    assert_eq!(codec::message_type(body), "Reaction");
+}
+
+#[test]
+fn hello_without_version_reads_as_zero() {
+    let body = br#"{"type":"Hello","peer_id":"p1","username":"ana"}"#;
+    match codec::decode(body).unwrap() {
+        TcpMessage::Hello {
+            version,
+            capabilities,
+            ..
+        } => {
+            assert_eq!(version, 0);
+            assert!(capabilities.is_empty());
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+}
@@ -0,0 +1,206 @@
+//! Helpers shared by the multi-peer test suites.
+
+#![allow(dead_code)]
+
+use gustavio_core::events::Event;
+use gustavio_core::node::{self, Command, NodeConfig};
+use gustavio_core::state::SharedState;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long (on the tokio clock) to wait for an expected event.
+pub const EVENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A node running in this process with its own data directory, plus
+/// everything it reports.
+pub struct TestNode {
+    pub name: String,
+    pub peer_id: String,
+    pub commands: mpsc::UnboundedSender<Command>,
+    pub state: Arc<SharedState>,
+    pub events: mpsc::UnboundedReceiver<Event>,
+    dir: PathBuf,
+    /// Set for isolated nodes; shutting it down kills every node task
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+/// Fresh, empty directory for one node's database.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gustavio-test-{name}-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A port nothing is listening on right now.
+pub fn free_tcp_port() -> u16 {
+    let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    l.local_addr().unwrap().port()
+}
+
+pub fn free_udp_port() -> u16 {
+    let s = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    s.local_addr().unwrap().port()
+}
+
+impl TestNode {
+    /// Start a node on the current runtime and set its username;
+    /// `config.db_path` is replaced with one inside a temp directory.
+    pub async fn start(name: &str, config: NodeConfig) -> Self {
+        Self::launch(name, config, None).await
+    }
+
+    /// Like `start`, but on a dedicated runtime so `stop` can kill the node
+    /// abruptly, the way a crashed process would go away.
+    pub async fn start_isolated(name: &str, config: NodeConfig) -> Self {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+        Self::launch(name, config, Some(rt)).await
+    }
+
+    async fn launch(
+        name: &str,
+        mut config: NodeConfig,
+        runtime: Option<tokio::runtime::Runtime>,
+    ) -> Self {
+        let dir = temp_dir(name);
+        config.db_path = dir.join("gustavio.db");
+
+        let (tx, events) = mpsc::unbounded_channel();
+        let handle = match &runtime {
+            Some(rt) => {
+                let _guard = rt.enter();
+                node::spawn(config, Arc::new(tx))
+            }
+            None => node::spawn(config, Arc::new(tx)),
+        };
+        let mut node = Self {
+            name: name.to_string(),
+            peer_id: String::new(),
+            commands: handle.commands,
+            state: handle.state,
+            events,
+            dir,
+            runtime,
+        };
+
+        node.send(Command::SetUsername {
+            username: name.to_string(),
+        });
+        node.peer_id = node
+            .wait_for(|e| match e {
+                Event::ConfigLoaded {
+                    peer_id,
+                    username: Some(_),
+                } => Some(peer_id.clone()),
+                _ => None,
+            })
+            .await;
+        node
+    }
+
+    /// Kill an isolated node: no goodbye, sockets just close.
+    pub fn stop(&mut self) {
+        if let Some(rt) = self.runtime.take() {
+            rt.shutdown_background();
+        }
+    }
+
+    /// Where the node keeps its database.
+    pub fn db_path(&self) -> PathBuf {
+        self.dir.join("gustavio.db")
+    }
+
+    pub fn send(&self, cmd: Command) {
+        self.commands.send(cmd).expect("node stopped");
+    }
+
+    pub fn send_message(&self, to: &TestNode, content: &str) {
+        self.send(Command::SendMessage {
+            peer_id: to.peer_id.clone(),
+            content: content.to_string(),
+        });
+    }
+
+    /// Skip events until `f` returns `Some`, panicking after `EVENT_TIMEOUT`.
+    pub async fn wait_for<T>(&mut self, mut f: impl FnMut(&Event) -> Option<T>) -> T {
+        let name = self.name.clone();
+        let fut = async {
+            loop {
+                let event = self.events.recv().await.expect("node stopped");
+                if let Some(v) = f(&event) {
+                    return v;
+                }
+            }
+        };
+        match tokio::time::timeout(EVENT_TIMEOUT, fut).await {
+            Ok(v) => v,
+            Err(_) => panic!("{name}: timed out waiting for event"),
+        }
+    }
+
+    /// True if an event matching `f` shows up within `within`.
+    pub async fn sees(&mut self, within: Duration, mut f: impl FnMut(&Event) -> bool) -> bool {
+        let fut = async {
+            loop {
+                match self.events.recv().await {
+                    Some(event) if f(&event) => return true,
+                    Some(_) => {}
+                    None => return false,
+                }
+            }
+        };
+        tokio::time::timeout(within, fut).await.unwrap_or(false)
+    }
+
+    /// Wait until `other` shows up in our peer list.
+    pub async fn wait_for_peer(&mut self, other: &TestNode) {
+        let id = other.peer_id.clone();
+        self.wait_for(|e| match e {
+            Event::PeerList(peers) if peers.iter().any(|p| p.peer_id == id) => Some(()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait for a chat message with this content to arrive.
+    pub async fn wait_for_message(&mut self, content: &str) -> gustavio_core::db::MessageRow {
+        self.wait_for(|e| match e {
+            Event::MessageReceived(row) if row.content == content => Some(row.clone()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait for our message with this content to be sent; returns its row.
+    pub async fn wait_for_sent(&mut self, content: &str) -> gustavio_core::db::MessageRow {
+        self.wait_for(|e| match e {
+            Event::MessageSent(row) if row.content == content => Some(row.clone()),
+            _ => None,
+        })
+        .await
+    }
+
+    pub async fn wait_for_ack(&mut self, message_id: &str) -> String {
+        self.wait_for(|e| match e {
+            Event::MessageAck {
+                message_id: id,
+                status,
+            } if id == message_id => Some(status.clone()),
+            _ => None,
+        })
+        .await
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,205 @@
+//! Inbound connection limits, exercised by a rogue host on the simulated LAN.
+
+mod common;
+
+use common::TestNode;
+use gustavio_core::codec;
+use gustavio_core::limits::Limits;
+use gustavio_core::node::{NodeConfig, TCP_PORT};
+use gustavio_core::protocol::{Route, TcpMessage};
+use gustavio_core::sim::SimNetwork;
+use gustavio_core::transport::{Connection, Transport};
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const ROGUE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 66);
+
+fn limits() -> Limits {
+    Limits {
+        handshake_timeout: Duration::from_secs(2),
+        max_frame_size: 4096,
+        max_connections_per_ip: 3,
+        messages_per_second: 5,
+        message_burst: 10,
+        ban_duration: Duration::from_secs(60),
+    }
+}
+
+async fn victim(net: &SimNetwork) -> TestNode {
+    let config = NodeConfig {
+        transport: Arc::new(net.host(Ipv4Addr::new(10, 0, 0, 1))),
+        limits: limits(),
+        ..NodeConfig::default()
+    };
+    TestNode::start("alice", config).await
+}
+
+async fn connect(net: &SimNetwork) -> Connection {
+    let addr = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), TCP_PORT));
+    net.host(ROGUE).connect(addr).await.unwrap()
+}
+
+/// Complete the handshake as a well-behaved peer called `peer_id` would.
+async fn hello(conn: &mut Connection, peer_id: &str) {
+    codec::write_frame(&mut conn.writer, &TcpMessage::hello(peer_id, "rogue"))
+        .await
+        .unwrap();
+    codec::read_frame(&mut conn.reader, codec::MAX_FRAME_SIZE)
+        .await
+        .unwrap()
+        .expect("no hello back");
+}
+
+/// Wait for the victim to hang up on us, skipping whatever it still sends.
+async fn closed_within(conn: &mut Connection, within: Duration) -> bool {
+    let drain = async {
+        let mut buf = [0u8; 1024];
+        while conn.reader.read(&mut buf).await.is_ok_and(|n| n > 0) {}
+    };
+    tokio::time::timeout(within, drain).await.is_ok()
+}
+
+async fn is_banned(node: &TestNode) -> bool {
+    node.state.gate.lock().await.is_banned(IpAddr::V4(ROGUE))
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_connection_is_dropped_and_banned() {
+    let net = SimNetwork::new();
+    let alice = victim(&net).await;
+
+    let mut conn = connect(&net).await;
+    assert!(closed_within(&mut conn, Duration::from_secs(5)).await);
+    assert!(is_banned(&alice).await);
+
+    // Further connections are refused straight away
+    let mut again = connect(&net).await;
+    assert!(closed_within(&mut again, Duration::from_millis(100)).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn oversized_frame_gets_the_peer_banned() {
+    let net = SimNetwork::new();
+    let alice = victim(&net).await;
+
+    let mut conn = connect(&net).await;
+    hello(&mut conn, "rogue").await;
+    conn.writer
+        .write_all(&100_000u32.to_be_bytes())
+        .await
+        .unwrap();
+
+    assert!(closed_within(&mut conn, Duration::from_secs(1)).await);
+    assert!(is_banned(&alice).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn flooding_peer_is_cut_off() {
+    let net = SimNetwork::new();
+    let alice = victim(&net).await;
+
+    let mut conn = connect(&net).await;
+    hello(&mut conn, "rogue").await;
+    for i in 0..50 {
+        let msg = TcpMessage::Ack {
+            message_id: format!("m{i}"),
+            status: "delivered".into(),
+            from_id: None,
+            to: None,
+            hops: 0,
+        };
+        if codec::write_frame(&mut conn.writer, &msg).await.is_err() {
+            break;
+        }
+    }
+
+    assert!(closed_within(&mut conn, Duration::from_secs(1)).await);
+    assert!(is_banned(&alice).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn passing_on_for_made_up_peers_is_still_a_flood() {
+    let net = SimNetwork::new();
+    let alice = victim(&net).await;
+
+    let mut conn = connect(&net).await;
+    hello(&mut conn, "rogue").await;
+    // Claims to reach 30 peers, then passes on a few frames from each:
+    // within each one's rate, far over the connection's
+    let made_up: Vec<String> = (0..30).map(|i| format!("fake-{i}")).collect();
+    let routes = made_up
+        .iter()
+        .map(|peer_id| Route {
+            peer_id: peer_id.clone(),
+            username: peer_id.clone(),
+            hops: 1,
+            workspaces: Vec::new(),
+        })
+        .collect();
+    codec::write_frame(&mut conn.writer, &TcpMessage::Routes { routes })
+        .await
+        .unwrap();
+    'flood: for i in 0..3 {
+        for from in &made_up {
+            let msg = TcpMessage::Ack {
+                message_id: format!("m{i}"),
+                status: "delivered".into(),
+                from_id: Some(from.clone()),
+                to: None,
+                hops: 0,
+            };
+            if codec::write_frame(&mut conn.writer, &msg).await.is_err() {
+                break 'flood;
+            }
+        }
+    }
+
+    assert!(closed_within(&mut conn, Duration::from_secs(1)).await);
+    assert!(is_banned(&alice).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn connections_per_ip_are_capped() {
+    let net = SimNetwork::new();
+    let alice = victim(&net).await;
+
+    let mut open = Vec::new();
+    // Distinct peers behind one address, so none replaces another
+    for i in 0..3 {
+        let mut conn = connect(&net).await;
+        hello(&mut conn, &format!("rogue-{i}")).await;
+        open.push(conn);
+    }
+    let mut extra = connect(&net).await;
+    assert!(closed_within(&mut extra, Duration::from_millis(100)).await);
+    assert!(is_banned(&alice).await);
+
+    // Connections admitted before the ban stay up
+    assert!(!closed_within(&mut open[0], Duration::from_millis(100)).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn polite_peer_is_not_banned() {
+    let net = SimNetwork::new();
+    let alice = victim(&net).await;
+
+    let mut conn = connect(&net).await;
+    hello(&mut conn, "rogue").await;
+    for i in 0..20 {
+        let msg = TcpMessage::Ack {
+            message_id: format!("m{i}"),
+            status: "delivered".into(),
+            from_id: None,
+            to: None,
+            hops: 0,
+        };
+        codec::write_frame(&mut conn.writer, &msg).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+    }
+
+    assert!(!closed_within(&mut conn, Duration::from_millis(100)).await);
+    assert!(!is_banned(&alice).await);
+}
@@ -0,0 +1,199 @@
+//! End-to-end behaviour of several nodes talking real UDP/TCP over 127.0.0.1.
+//! Each node runs on its own runtime with its own data directory and ports.
+
+mod common;
+
+use common::{free_tcp_port, free_udp_port, TestNode};
+use gustavio_core::discovery::DiscoveryConfig;
+use gustavio_core::events::Event;
+use gustavio_core::node::{Command, NodeConfig};
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(200);
+const PEER_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Start `names.len()` nodes that announce to each other over loopback.
+async fn loopback_lan(names: &[&str]) -> Vec<TestNode> {
+    let ports: Vec<u16> = names.iter().map(|_| free_udp_port()).collect();
+    let targets: Vec<SocketAddr> = ports
+        .iter()
+        .map(|p| (Ipv4Addr::LOCALHOST, *p).into())
+        .collect();
+
+    let mut nodes = Vec::new();
+    for (name, port) in names.iter().zip(&ports) {
+        let config = NodeConfig {
+            tcp_port: free_tcp_port(),
+            discovery: DiscoveryConfig {
+                port: *port,
+                announce_to: targets.clone(),
+                subnet_broadcast: false,
+                mdns: false,
+                announce_interval: ANNOUNCE_INTERVAL,
+                peer_timeout: PEER_TIMEOUT,
+                ..DiscoveryConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        nodes.push(TestNode::start_isolated(name, config).await);
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn direct_message_is_delivered_and_acked() {
+    let mut nodes = loopback_lan(&["alice", "bob"]).await;
+    let [alice, bob] = &mut nodes[..] else {
+        unreachable!()
+    };
+    alice.wait_for_peer(bob).await;
+
+    alice.send_message(bob, "oi bob");
+    let row = bob.wait_for_message("oi bob").await;
+    assert_eq!(row.from_id, alice.peer_id);
+    assert_eq!(alice.wait_for_ack(&row.id).await, "delivered");
+
+    bob.send_message(alice, "oi alice");
+    let row = alice.wait_for_message("oi alice").await;
+    assert_eq!(bob.wait_for_ack(&row.id).await, "delivered");
+}
+
+#[tokio::test]
+async fn group_creation_propagates_to_members() {
+    let mut nodes = loopback_lan(&["alice", "bob", "carol"]).await;
+    let [alice, bob, carol] = &mut nodes[..] else {
+        unreachable!()
+    };
+    alice.wait_for_peer(bob).await;
+    alice.wait_for_peer(carol).await;
+
+    alice.send(Command::CreateGroup {
+        name: "infra".into(),
+        members: vec![bob.peer_id.clone(), carol.peer_id.clone()],
+    });
+    let group_id = alice
+        .wait_for(|e| match e {
+            Event::GroupCreated(id) => Some(id.clone()),
+            _ => None,
+        })
+        .await;
+
+    for member in [&mut *bob, &mut *carol] {
+        let group = member
+            .wait_for(|e| match e {
+                Event::GroupList(groups) => groups.iter().find(|g| g.group_id == group_id).cloned(),
+                _ => None,
+            })
+            .await;
+        assert_eq!(group.name, "infra");
+        assert_eq!(group.creator_id, alice.peer_id);
+    }
+
+    bob.send(Command::SendGroupMessage {
+        group_id: group_id.clone(),
+        content: "deploy as 17h".into(),
+    });
+    for member in [&mut *alice, &mut *carol] {
+        let row = member.wait_for_message("deploy as 17h").await;
+        assert_eq!(row.conversation_id, group_id);
+    }
+}
+
+#[tokio::test]
+async fn silent_peer_times_out_of_the_peer_list() {
+    let mut nodes = loopback_lan(&["alice", "bob"]).await;
+    let [alice, bob] = &mut nodes[..] else {
+        unreachable!()
+    };
+    alice.wait_for_peer(bob).await;
+
+    bob.stop();
+    let bob_id = bob.peer_id.clone();
+    alice
+        .wait_for(|e| match e {
+            Event::PeerList(peers) if !peers.iter().any(|p| p.peer_id == bob_id) => Some(()),
+            _ => None,
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn reconnects_after_the_writer_is_dropped() {
+    let mut nodes = loopback_lan(&["alice", "bob"]).await;
+    let [alice, bob] = &mut nodes[..] else {
+        unreachable!()
+    };
+    alice.wait_for_peer(bob).await;
+
+    alice.send_message(bob, "primeira");
+    bob.wait_for_message("primeira").await;
+
+    // Kill the session under alice's feet but leave the dead writer in the
+    // table, as happens when a peer's Wi-Fi drops.
+    let writer = alice
+        .state
+        .connections
+        .lock()
+        .await
+        .get(&bob.peer_id)
+        .map(|c| c.writer.clone())
+        .expect("alice should be connected to bob");
+    writer.lock().await.shutdown().await.unwrap();
+    drop(writer);
+
+    alice.send_message(bob, "segunda");
+    let row = bob.wait_for_message("segunda").await;
+    assert_eq!(alice.wait_for_ack(&row.id).await, "delivered");
+}
+
+#[tokio::test]
+async fn peers_find_each_other_over_mdns_alone() {
+    let config = || NodeConfig {
+        tcp_port: free_tcp_port(),
+        discovery: DiscoveryConfig {
+            port: free_udp_port(),
+            announce_to: Vec::new(),
+            subnet_broadcast: false,
+            announce_interval: ANNOUNCE_INTERVAL,
+            peer_timeout: PEER_TIMEOUT,
+            ..DiscoveryConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start_isolated("alice", config()).await;
+    let mut bob = TestNode::start_isolated("bob", config()).await;
+    alice.wait_for_peer(&bob).await;
+
+    alice.send_message(&bob, "achei pelo mdns");
+    bob.wait_for_message("achei pelo mdns").await;
+}
+
+#[tokio::test]
+async fn peers_are_reached_over_ipv6_loopback() {
+    let config = |tcp_port| NodeConfig {
+        tcp_port,
+        discovery: DiscoveryConfig {
+            port: free_udp_port(),
+            announce_to: Vec::new(),
+            subnet_broadcast: false,
+            mdns: false,
+            announce_interval: ANNOUNCE_INTERVAL,
+            peer_timeout: PEER_TIMEOUT,
+            ..DiscoveryConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    let alice_port = free_tcp_port();
+    let mut alice = TestNode::start_isolated("alice", config(alice_port)).await;
+    let mut bob = TestNode::start_isolated("bob", config(free_tcp_port())).await;
+
+    bob.send(Command::AddPeer {
+        address: format!("[::1]:{alice_port}"),
+    });
+    bob.wait_for_peer(&alice).await;
+    bob.send_message(&alice, "oi pelo ipv6");
+    alice.wait_for_message("oi pelo ipv6").await;
+}
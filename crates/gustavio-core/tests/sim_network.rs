@@ -0,0 +1,1562 @@
+//! Multi-peer behaviour over the in-memory simulated network.
+
+mod common;
+
+use common::TestNode;
+use gustavio_core::codec;
+use gustavio_core::discovery::{DiscoveryConfig, DISCOVERY_PORT, MULTICAST_V6};
+use gustavio_core::events::{Event, LinkState, Reachability};
+use gustavio_core::invite::{Invite, INVITE_TTL};
+use gustavio_core::limits::Limits;
+use gustavio_core::netif::Interface;
+use gustavio_core::node::{Command, NodeConfig, TCP_PORT};
+use gustavio_core::protocol::{Presence, TcpMessage, MAX_HOPS};
+use gustavio_core::reconnect::ReconnectConfig;
+use gustavio_core::sim::SimNetwork;
+use gustavio_core::state::PeerInfo;
+use gustavio_core::storage::Storage;
+use gustavio_core::transport::{Connection, Transport};
+use gustavio_core::workspace::Workspace;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+fn ip(n: u8) -> Ipv4Addr {
+    Ipv4Addr::new(10, 0, 0, n)
+}
+
+async fn sim_node(net: &SimNetwork, name: &str, host: u8) -> TestNode {
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        ..NodeConfig::default()
+    };
+    TestNode::start(name, config).await
+}
+
+fn ws(name: &str, secret: &str) -> Workspace {
+    Workspace {
+        name: name.into(),
+        secret: secret.into(),
+    }
+}
+
+async fn ws_node(net: &SimNetwork, name: &str, host: u8, workspaces: &[Workspace]) -> TestNode {
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        workspaces: workspaces.to_vec(),
+        ..NodeConfig::default()
+    };
+    TestNode::start(name, config).await
+}
+
+#[tokio::test(start_paused = true)]
+async fn direct_message_is_delivered_and_acked() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    alice.send_message(&bob, "oi bob");
+    let row = bob.wait_for_message("oi bob").await;
+    assert_eq!(row.from_id, alice.peer_id);
+    assert_eq!(row.conversation_id, alice.peer_id);
+    assert!(!row.is_group);
+
+    assert_eq!(alice.wait_for_ack(&row.id).await, "delivered");
+}
+
+#[tokio::test(start_paused = true)]
+async fn group_create_and_message_fan_out_to_every_member() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    let mut carol = sim_node(&net, "carol", 3).await;
+    alice.wait_for_peer(&bob).await;
+    alice.wait_for_peer(&carol).await;
+
+    alice.send(Command::CreateGroup {
+        name: "time".into(),
+        members: vec![bob.peer_id.clone(), carol.peer_id.clone()],
+    });
+    let group_id = alice
+        .wait_for(|e| match e {
+            Event::GroupCreated(id) => Some(id.clone()),
+            _ => None,
+        })
+        .await;
+    for member in [&mut bob, &mut carol] {
+        member
+            .wait_for(|e| match e {
+                Event::GroupList(groups) if groups.iter().any(|g| g.group_id == group_id) => {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+    }
+
+    alice.send(Command::SendGroupMessage {
+        group_id: group_id.clone(),
+        content: "bom dia".into(),
+    });
+    for member in [&mut bob, &mut carol] {
+        let row = member.wait_for_message("bom dia").await;
+        assert!(row.is_group);
+        assert_eq!(row.conversation_id, group_id);
+        assert_eq!(row.from_id, alice.peer_id);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn partitioned_member_does_not_block_the_group() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    let mut carol = sim_node(&net, "carol", 3).await;
+    alice.wait_for_peer(&bob).await;
+    alice.wait_for_peer(&carol).await;
+
+    alice.send(Command::CreateGroup {
+        name: "time".into(),
+        members: vec![bob.peer_id.clone(), carol.peer_id.clone()],
+    });
+    let group_id = alice
+        .wait_for(|e| match e {
+            Event::GroupCreated(id) => Some(id.clone()),
+            _ => None,
+        })
+        .await;
+
+    net.partition(ip(1), ip(3));
+    alice.send(Command::SendGroupMessage {
+        group_id,
+        content: "so o bob".into(),
+    });
+    bob.wait_for_message("so o bob").await;
+    let carol_got_it = carol
+        .sees(
+            Duration::from_secs(20),
+            |e| matches!(e, Event::MessageReceived(row) if row.content == "so o bob"),
+        )
+        .await;
+    assert!(!carol_got_it);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reconnects_after_connection_reset() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    alice.send_message(&bob, "primeira");
+    bob.wait_for_message("primeira").await;
+
+    net.disconnect(ip(1), ip(2));
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    alice.send_message(&bob, "segunda");
+    let row = bob.wait_for_message("segunda").await;
+    assert_eq!(alice.wait_for_ack(&row.id).await, "delivered");
+}
+
+#[tokio::test(start_paused = true)]
+async fn send_fails_while_partitioned_and_recovers_after_heal() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    net.partition(ip(1), ip(2));
+    alice.send_message(&bob, "perdida");
+    let row = alice.wait_for_sent("perdida").await;
+    assert_eq!(row.status, "failed");
+
+    net.heal(ip(1), ip(2));
+    alice.wait_for_peer(&bob).await;
+    alice.send_message(&bob, "de volta");
+    bob.wait_for_message("de volta").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn discovery_and_delivery_survive_a_slow_lossy_link() {
+    let net = SimNetwork::with_seed(42);
+    net.set_loss(ip(1), ip(2), 0.5);
+    net.set_delay(ip(1), ip(2), Duration::from_millis(300));
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    for i in 0..5 {
+        alice.send_message(&bob, &format!("msg {i}"));
+    }
+    for i in 0..5 {
+        bob.wait_for_message(&format!("msg {i}")).await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn stalled_peer_does_not_hold_up_others() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    let carol = sim_node(&net, "carol", 3).await;
+    alice.wait_for_peer(&bob).await;
+    alice.wait_for_peer(&carol).await;
+
+    net.set_delay(ip(1), ip(3), Duration::from_secs(60));
+    alice.send_message(&carol, "oi carol");
+    alice.send_message(&bob, "oi bob");
+    let bob_got_it = bob
+        .sees(
+            Duration::from_secs(1),
+            |e| matches!(e, Event::MessageReceived(row) if row.content == "oi bob"),
+        )
+        .await;
+    assert!(bob_got_it);
+}
+
+#[tokio::test(start_paused = true)]
+async fn full_queue_fails_new_messages_right_away() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    net.set_delay(ip(1), ip(2), Duration::from_secs(60));
+    for i in 0..=gustavio_core::outbox::QUEUE_DEPTH + 1 {
+        alice.send_message(&bob, &format!("msg {i}"));
+    }
+    let refused = alice
+        .sees(
+            Duration::from_secs(1),
+            |e| matches!(e, Event::MessageSent(row) if row.status == "failed"),
+        )
+        .await;
+    assert!(refused);
+}
+
+#[tokio::test(start_paused = true)]
+async fn simultaneous_dials_settle_on_one_connection() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+    bob.wait_for_peer(&alice).await;
+
+    // Both handshakes are in flight before either completes
+    net.set_delay(ip(1), ip(2), Duration::from_millis(50));
+    alice.send_message(&bob, "oi bob");
+    bob.send_message(&alice, "oi alice");
+    bob.wait_for_message("oi bob").await;
+    alice.wait_for_message("oi alice").await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    for (node, other) in [(&alice, &bob), (&bob, &alice)] {
+        let conns = node.state.connections.lock().await;
+        assert!(
+            conns[&other.peer_id].preferred,
+            "{} kept the losing side",
+            node.name
+        );
+    }
+
+    // The surviving session carries traffic both ways
+    alice.send_message(&bob, "ainda aqui?");
+    bob.wait_for_message("ainda aqui?").await;
+    bob.send_message(&alice, "sim");
+    alice.wait_for_message("sim").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_connection_is_torn_down_by_heartbeat() {
+    let net = SimNetwork::new();
+    // Discovery alone would keep bob listed for a minute
+    let discovery = DiscoveryConfig {
+        peer_timeout: Duration::from_secs(60),
+        ..DiscoveryConfig::default()
+    };
+    let config = |host| NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        discovery: discovery.clone(),
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config(1)).await;
+    let mut bob = TestNode::start("bob", config(2)).await;
+    alice.wait_for_peer(&bob).await;
+
+    alice.send_message(&bob, "oi bob");
+    bob.wait_for_message("oi bob").await;
+
+    net.set_delay(ip(1), ip(2), Duration::from_secs(600));
+    let bob_id = bob.peer_id.clone();
+    let dropped = alice
+        .sees(
+            Duration::from_secs(10),
+            |e| matches!(e, Event::PeerList(peers) if !peers.iter().any(|p| p.peer_id == bob_id)),
+        )
+        .await;
+    assert!(dropped);
+    assert!(!alice
+        .state
+        .connections
+        .lock()
+        .await
+        .contains_key(&bob.peer_id));
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_connection_stays_up() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    alice.send_message(&bob, "oi bob");
+    bob.wait_for_message("oi bob").await;
+    tokio::time::sleep(Duration::from_secs(30)).await;
+
+    assert!(alice
+        .state
+        .connections
+        .lock()
+        .await
+        .contains_key(&bob.peer_id));
+}
+
+#[tokio::test(start_paused = true)]
+async fn dropped_session_is_reopened_without_a_send() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    alice.send_message(&bob, "oi bob");
+    bob.wait_for_message("oi bob").await;
+
+    net.disconnect(ip(1), ip(2));
+    let bob_id = bob.peer_id.clone();
+    let link_to_bob = |state: LinkState| {
+        let bob_id = bob_id.clone();
+        move |e: &Event| matches!(e, Event::Link { peer_id, state: s } if *peer_id == bob_id && *s == state)
+    };
+    assert!(
+        alice
+            .sees(Duration::from_secs(1), link_to_bob(LinkState::Disconnected))
+            .await
+    );
+    assert!(
+        alice
+            .sees(Duration::from_secs(5), link_to_bob(LinkState::Connected))
+            .await
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn unreachable_peer_is_retried_with_growing_backoff() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    net.partition(ip(1), ip(2));
+    alice.send_message(&bob, "perdida");
+    let mut waits = Vec::new();
+    while waits.len() < 3 {
+        let wait = alice
+            .wait_for(|e| match e {
+                Event::Link {
+                    state: LinkState::BackingOff { retry_in_ms },
+                    ..
+                } => Some(*retry_in_ms),
+                _ => None,
+            })
+            .await;
+        waits.push(wait);
+    }
+    assert!(waits[2] > waits[0], "backoff did not grow: {waits:?}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn retries_are_spread_over_the_whole_backoff() {
+    let net = SimNetwork::new();
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(1))),
+        reconnect: ReconnectConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(1),
+            ..ReconnectConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config).await;
+    let bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    net.partition(ip(1), ip(2));
+    alice.send_message(&bob, "perdida");
+    let mut waits = Vec::new();
+    while waits.len() < 30 {
+        let wait = alice
+            .wait_for(|e| match e {
+                Event::Link {
+                    state: LinkState::BackingOff { retry_in_ms },
+                    ..
+                } => Some(*retry_in_ms),
+                _ => None,
+            })
+            .await;
+        waits.push(wait);
+    }
+    // Between half and all of it, from one end to the other
+    assert!(waits.iter().all(|w| (500..=1000).contains(w)), "{waits:?}");
+    assert!(waits.iter().any(|w| *w < 650), "{waits:?}");
+    assert!(waits.iter().any(|w| *w > 850), "{waits:?}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn shutdown_delivers_queued_messages_and_says_goodbye() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+    bob.wait_for_peer(&alice).await;
+
+    let started = tokio::time::Instant::now();
+    alice.send_message(&bob, "até logo");
+    alice.send(Command::Shutdown);
+    alice
+        .wait_for(|e| matches!(e, Event::Stopped).then_some(()))
+        .await;
+
+    let alice_id = alice.peer_id.clone();
+    let (mut delivered, mut gone) = (false, false);
+    bob.wait_for(|e| {
+        match e {
+            Event::MessageReceived(row) if row.content == "até logo" => delivered = true,
+            Event::PeerList(peers) if !peers.iter().any(|p| p.peer_id == alice_id) => gone = true,
+            _ => {}
+        }
+        (delivered && gone).then_some(())
+    })
+    .await;
+    // Well before discovery would have timed alice out
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test(start_paused = true)]
+async fn peers_find_each_other_over_ipv6_multicast_alone() {
+    let net = SimNetwork::new();
+    let discovery = DiscoveryConfig {
+        announce_to: vec![SocketAddrV6::new(MULTICAST_V6, DISCOVERY_PORT, 0, 0).into()],
+        subnet_broadcast: false,
+        ..DiscoveryConfig::default()
+    };
+    let config = |host| NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        discovery: discovery.clone(),
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config(1)).await;
+    let bob = TestNode::start("bob", config(2)).await;
+    alice.wait_for_peer(&bob).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn broadcast_mode_still_discovers_peers() {
+    let net = SimNetwork::new();
+    let config = |host| NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        discovery: DiscoveryConfig::broadcast(),
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config(1)).await;
+    let mut bob = TestNode::start("bob", config(2)).await;
+    alice.wait_for_peer(&bob).await;
+
+    alice.send_message(&bob, "oi bob");
+    bob.wait_for_message("oi bob").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn multicast_announces_skip_hosts_outside_the_group() {
+    let net = SimNetwork::new();
+    let mut alice = TestNode::start(
+        "alice",
+        NodeConfig {
+            transport: Arc::new(net.host(ip(1))),
+            discovery: DiscoveryConfig {
+                subnet_broadcast: false,
+                ..DiscoveryConfig::default()
+            },
+            ..NodeConfig::default()
+        },
+    )
+    .await;
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(2))),
+        discovery: DiscoveryConfig {
+            announce_to: vec![SocketAddrV4::new(ip(1), DISCOVERY_PORT).into()],
+            subnet_broadcast: false,
+            ..DiscoveryConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    // Bob announces straight to alice but joins no group
+    let mut bob = TestNode::start("bob", config).await;
+    alice.wait_for_peer(&bob).await;
+
+    let alice_id = alice.peer_id.clone();
+    let found = bob
+        .sees(
+            Duration::from_secs(10),
+            |e| matches!(e, Event::PeerList(peers) if peers.iter().any(|p| p.peer_id == alice_id)),
+        )
+        .await;
+    assert!(!found);
+}
+
+#[tokio::test(start_paused = true)]
+async fn subnet_broadcast_alone_discovers_peers() {
+    let net = SimNetwork::new();
+    let config = |host| NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        discovery: DiscoveryConfig {
+            announce_to: Vec::new(),
+            ..DiscoveryConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config(1)).await;
+    let bob = TestNode::start("bob", config(2)).await;
+    alice.wait_for_peer(&bob).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn hosts_with_many_interfaces_announce_their_lan_address() {
+    let net = SimNetwork::new();
+    // Two hundred container bridges, listed before the LAN interface
+    let bridges = (0..200)
+        .map(|i| Interface {
+            name: format!("br-{i}"),
+            addr: IpAddr::V4(Ipv4Addr::new(172, 18, i, 1)),
+            index: 10 + u32::from(i),
+            broadcast: None,
+        })
+        .collect();
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(1)).with_interfaces(bridges)),
+        ..NodeConfig::default()
+    };
+    let alice = TestNode::start("alice", config).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+
+    bob.wait_for_peer(&alice).await;
+    let addrs = bob.state.peers.lock().await[&alice.peer_id].addrs.clone();
+    assert_eq!(
+        addrs.first().map(String::as_str),
+        Some("10.0.0.1"),
+        "{addrs:?}"
+    );
+    assert!(addrs.len() <= 8, "{addrs:?}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn unreachable_addresses_are_skipped_in_turn() {
+    let net = SimNetwork::new();
+    let alice = sim_node(&net, "alice", 1).await;
+    // Bob never announces, so alice only knows what we tell her
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(2))),
+        discovery: DiscoveryConfig {
+            announce_to: Vec::new(),
+            subnet_broadcast: false,
+            ..DiscoveryConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    let mut bob = TestNode::start("bob", config).await;
+    net.partition(ip(1), ip(99));
+    alice.state.peers.lock().await.insert(
+        bob.peer_id.clone(),
+        PeerInfo {
+            peer_id: bob.peer_id.clone(),
+            username: "bob".into(),
+            addrs: vec![ip(99).to_string(), "10.0.0.200".into(), ip(2).to_string()],
+            tcp_port: TCP_PORT,
+            last_seen: Instant::now(),
+            indirect: false,
+            via: None,
+            workspaces: Vec::new(),
+        },
+    );
+
+    alice.send_message(&bob, "oi bob");
+    bob.wait_for_message("oi bob").await;
+    let peers = alice.state.peers.lock().await;
+    assert_eq!(peers[&bob.peer_id].ip(), ip(2).to_string());
+}
+
+#[tokio::test(start_paused = true)]
+async fn static_peer_is_probed_and_listed() {
+    let net = SimNetwork::new();
+    // Discovery that reaches nobody, as across VLANs
+    let config = |host| NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        discovery: DiscoveryConfig {
+            announce_to: Vec::new(),
+            subnet_broadcast: false,
+            ..DiscoveryConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config(1)).await;
+    let mut bob = TestNode::start("bob", config(2)).await;
+
+    alice.send(Command::AddPeer {
+        address: format!("{}:{TCP_PORT}", ip(2)),
+    });
+    alice.wait_for_peer(&bob).await;
+    let bob_id = bob.peer_id.clone();
+    alice
+        .wait_for(|e| match e {
+            Event::StaticPeers(list) => list
+                .iter()
+                .any(|s| {
+                    s.peer_id.as_deref() == Some(bob_id.as_str())
+                        && s.username.as_deref() == Some("bob")
+                        && s.reachability == Reachability::Reachable
+                })
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+
+    alice.send_message(&bob, "oi do outro lado");
+    bob.wait_for_message("oi do outro lado").await;
+
+    net.partition(ip(1), ip(2));
+    alice
+        .wait_for(|e| match e {
+            Event::StaticPeers(list) => list
+                .iter()
+                .any(|s| matches!(s.reachability, Reachability::Unreachable { .. }))
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+    // Dropped once the probes stop getting through
+    alice
+        .wait_for(|e| match e {
+            Event::PeerList(peers) if !peers.iter().any(|p| p.peer_id == bob_id) => Some(()),
+            _ => None,
+        })
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn bad_static_address_is_refused() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    alice.send(Command::AddPeer {
+        address: "10.0.0.2:porta".into(),
+    });
+    alice
+        .wait_for(|e| match e {
+            Event::Error(e) if e.contains("Bad port") => Some(()),
+            _ => None,
+        })
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn peers_learn_of_each_other_through_gossip() {
+    let net = SimNetwork::new();
+    // Alice and carol only reach bob with their announces
+    let config = |host, to: &[u8]| NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        discovery: DiscoveryConfig {
+            announce_to: to
+                .iter()
+                .map(|h| SocketAddrV4::new(ip(*h), DISCOVERY_PORT).into())
+                .collect(),
+            subnet_broadcast: false,
+            ..DiscoveryConfig::default()
+        },
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config(1, &[2])).await;
+    let mut bob = TestNode::start("bob", config(2, &[1, 3])).await;
+    let mut carol = TestNode::start("carol", config(3, &[2])).await;
+    alice.wait_for_peer(&bob).await;
+    bob.wait_for_peer(&carol).await;
+
+    // Gossip flows over sessions, so alice needs one with bob
+    alice.send_message(&bob, "oi bob");
+    bob.wait_for_message("oi bob").await;
+
+    let carol_id = carol.peer_id.clone();
+    alice
+        .wait_for(|e| match e {
+            Event::PeerList(peers) => peers
+                .iter()
+                .any(|p| p.peer_id == carol_id && p.indirect)
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+    // Reached on our own from then on
+    alice
+        .wait_for(|e| match e {
+            Event::Link { peer_id, state } if *peer_id == carol_id => {
+                (*state == LinkState::Connected).then_some(())
+            }
+            _ => None,
+        })
+        .await;
+    alice.send_message(&carol, "oi carol");
+    carol.wait_for_message("oi carol").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn peers_heard_of_through_others_are_saved_and_their_renames_shown() {
+    let net = SimNetwork::new();
+    // Alice only hears of carol through bob, and can't reach her
+    net.partition(ip(1), ip(3));
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    let carol = sim_node(&net, "carol", 3).await;
+    alice.wait_for_peer(&bob).await;
+    alice.send_message(&bob, "oi bob");
+    bob.wait_for_message("oi bob").await;
+
+    let carol_id = carol.peer_id.clone();
+    let contact = |name: &'static str| {
+        let id = carol_id.clone();
+        move |e: &Event| match e {
+            Event::Contacts(list) => list
+                .iter()
+                .any(|c| c.peer_id == id && c.username == name)
+                .then_some(()),
+            _ => None,
+        }
+    };
+    alice.wait_for(contact("carol")).await;
+
+    carol.send(Command::SetUsername {
+        username: "carolina".into(),
+    });
+    // Saved under the new name, then shown as renamed
+    alice.wait_for(contact("carolina")).await;
+    let (old, new) = alice
+        .wait_for(|e| match e {
+            Event::PeerRenamed { peer_id, old, new } if *peer_id == carol_id => {
+                Some((old.clone(), new.clone()))
+            }
+            _ => None,
+        })
+        .await;
+    assert_eq!((old.as_str(), new.as_str()), ("carol", "carolina"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn relays_bridge_two_segments() {
+    let net = SimNetwork::new();
+    let config = |host, relay| NodeConfig {
+        transport: Arc::new(net.host(ip(host))),
+        relay,
+        ..NodeConfig::default()
+    };
+    // Office one holds alice and relay-1, office two bob and relay-2; only
+    // the relays reach across
+    let mut alice = TestNode::start("alice", config(1, false)).await;
+    let relay1 = TestNode::start("relay-1", config(2, true)).await;
+    let _relay2 = TestNode::start("relay-2", config(3, true)).await;
+    let mut bob = TestNode::start("bob", config(4, false)).await;
+    for (a, b) in [(1, 3), (1, 4), (2, 4)] {
+        net.partition(ip(a), ip(b));
+    }
+    relay1.send(Command::AddPeer {
+        address: format!("{}:{TCP_PORT}", ip(3)),
+    });
+
+    let bob_id = bob.peer_id.clone();
+    alice
+        .wait_for(|e| match e {
+            Event::PeerList(peers) => peers
+                .iter()
+                .any(|p| p.peer_id == bob_id && p.indirect)
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+    alice.send_message(&bob, "oi bob");
+    let row = bob.wait_for_message("oi bob").await;
+    assert_eq!(row.from_id, alice.peer_id);
+    // The ack comes back from bob himself, the same way
+    assert_eq!(alice.wait_for_ack(&row.id).await, "delivered");
+
+    bob.send_message(&alice, "oi alice");
+    alice.wait_for_message("oi alice").await;
+    assert!(!alice
+        .state
+        .connections
+        .lock()
+        .await
+        .contains_key(&bob.peer_id));
+}
+
+#[tokio::test(start_paused = true)]
+async fn isolated_clients_talk_through_a_shared_peer() {
+    let net = SimNetwork::new();
+    // Client-isolated Wi-Fi: both laptops reach the desktop, not each other
+    net.partition(ip(1), ip(3));
+    let mut laptop1 = sim_node(&net, "laptop-1", 1).await;
+    let mut desktop = sim_node(&net, "desktop", 2).await;
+    let mut laptop2 = sim_node(&net, "laptop-2", 3).await;
+    laptop1.wait_for_peer(&desktop).await;
+    laptop2.wait_for_peer(&desktop).await;
+    for laptop in [&laptop1, &laptop2] {
+        laptop.send_message(&desktop, "oi");
+        desktop.wait_for_message("oi").await;
+    }
+
+    laptop1.wait_for_peer(&laptop2).await;
+    laptop1.send_message(&laptop2, "oi laptop");
+    let row = laptop2.wait_for_message("oi laptop").await;
+    assert_eq!(row.from_id, laptop1.peer_id);
+    assert_eq!(laptop1.wait_for_ack(&row.id).await, "delivered");
+
+    laptop2.send_message(&laptop1, "oi de volta");
+    laptop1.wait_for_message("oi de volta").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn bursts_passed_on_for_several_peers_are_not_a_flood() {
+    let net = SimNetwork::new();
+    // The laptops only reach the desktop, and laptop-2 allows a burst of
+    // 12 frames per peer
+    for (a, b) in [(1, 3), (1, 4), (3, 4)] {
+        net.partition(ip(a), ip(b));
+    }
+    let mut laptop1 = sim_node(&net, "laptop-1", 1).await;
+    let mut desktop = sim_node(&net, "desktop", 2).await;
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(3))),
+        limits: Limits {
+            messages_per_second: 1,
+            message_burst: 12,
+            ..Limits::default()
+        },
+        ..NodeConfig::default()
+    };
+    let mut laptop2 = TestNode::start("laptop-2", config).await;
+    let mut laptop3 = sim_node(&net, "laptop-3", 4).await;
+    for laptop in [&mut laptop1, &mut laptop2, &mut laptop3] {
+        laptop.wait_for_peer(&desktop).await;
+        laptop.send_message(&desktop, "oi");
+        desktop.wait_for_message("oi").await;
+    }
+    for sender in [&mut laptop1, &mut laptop3] {
+        sender.wait_for_peer(&laptop2).await;
+        laptop2.wait_for_peer(sender).await;
+    }
+
+    // 16 frames in a row over the desktop's session, 8 from each laptop
+    for i in 0..8 {
+        laptop1.send_message(&laptop2, &format!("um {i}"));
+        laptop3.send_message(&laptop2, &format!("tres {i}"));
+    }
+    for i in 0..8 {
+        laptop2.wait_for_message(&format!("tres {i}")).await;
+    }
+    assert!(!laptop2.state.gate.lock().await.is_banned(IpAddr::V4(ip(2))));
+    laptop1.send_message(&laptop2, "depois");
+    laptop2.wait_for_message("depois").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn messages_cross_a_chain_of_peers() {
+    let net = SimNetwork::new();
+    // Each node only reaches its neighbours in the chain
+    for (a, b) in [(1, 3), (1, 4), (2, 4)] {
+        net.partition(ip(a), ip(b));
+    }
+    let mut nodes = Vec::new();
+    for (name, host) in [("a", 1), ("b", 2), ("c", 3), ("d", 4)] {
+        nodes.push(sim_node(&net, name, host).await);
+    }
+    for i in 0..3 {
+        let (left, right) = nodes.split_at_mut(i + 1);
+        left[i].wait_for_peer(&right[0]).await;
+        left[i].send_message(&right[0], "oi vizinho");
+        right[0].wait_for_message("oi vizinho").await;
+    }
+
+    let (a, rest) = nodes.split_first_mut().unwrap();
+    let d = &mut rest[2];
+    a.wait_for_peer(d).await;
+    a.send_message(d, "oi d");
+    let row = d.wait_for_message("oi d").await;
+    assert_eq!(row.from_id, a.peer_id);
+    assert_eq!(a.wait_for_ack(&row.id).await, "delivered");
+}
+
+#[tokio::test(start_paused = true)]
+async fn workspaces_only_see_their_own_members() {
+    let net = SimNetwork::new();
+    let mut alice = ws_node(&net, "alice", 1, &[ws("dev", "s3nha")]).await;
+    let bob = ws_node(&net, "bob", 2, &[ws("dev", "s3nha")]).await;
+    let carol = ws_node(&net, "carol", 3, &[ws("ops", "outra")]).await;
+    // Right name, wrong secret
+    let mallory = ws_node(&net, "mallory", 4, &[ws("dev", "chute")]).await;
+    let mut dave = sim_node(&net, "dave", 5).await;
+
+    alice.wait_for_peer(&bob).await;
+    let outsiders = [&carol, &mallory, &dave].map(|n| n.peer_id.clone());
+    let leaked = alice
+        .sees(Duration::from_secs(30), |e| {
+            matches!(e, Event::PeerList(peers) if peers.iter().any(|p| outsiders.contains(&p.peer_id)))
+        })
+        .await;
+    assert!(!leaked);
+    assert_eq!(
+        alice.state.peers.lock().await[&bob.peer_id].workspaces,
+        ["dev"]
+    );
+    for outsider in [&carol, &mallory, &dave] {
+        assert!(outsider.state.peers.lock().await.is_empty());
+    }
+
+    // Joining later works too
+    dave.send(Command::JoinWorkspace {
+        name: "dev".into(),
+        secret: "s3nha".into(),
+    });
+    dave.wait_for_peer(&alice).await;
+    dave.send_message(&alice, "cheguei");
+    alice.wait_for_message("cheguei").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn hello_proves_the_workspace_secret() {
+    let net = SimNetwork::new();
+    let alice = ws_node(&net, "alice", 1, &[ws("dev", "s3nha")]).await;
+    // Knows the workspace's name but not its secret
+    let mut mallory = ws_node(&net, "mallory", 2, &[ws("dev", "chute")]).await;
+
+    mallory.send(Command::AddPeer {
+        address: format!("{}:{TCP_PORT}", ip(1)),
+    });
+    let error = mallory
+        .wait_for(|e| match e {
+            Event::StaticPeers(list) => list.iter().find_map(|s| match &s.reachability {
+                Reachability::Unreachable { error } => Some(error.clone()),
+                _ => None,
+            }),
+            _ => None,
+        })
+        .await;
+    assert!(error.contains("none of our workspaces"), "{error}");
+    assert!(!alice
+        .state
+        .connections
+        .lock()
+        .await
+        .contains_key(&mallory.peer_id));
+}
+
+/// The next frame on `conn`, `None` once it's closed.
+async fn next_frame(conn: &mut Connection) -> Option<TcpMessage> {
+    let body = codec::read_frame(&mut conn.reader, codec::MAX_FRAME_SIZE)
+        .await
+        .ok()??;
+    codec::decode(&body).ok()
+}
+
+fn nonce_of(hello: Option<TcpMessage>) -> String {
+    match hello {
+        Some(TcpMessage::Hello {
+            nonce: Some(nonce), ..
+        }) => nonce,
+        other => panic!("expected a hello with a nonce, got {other:?}"),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn relayed_workspace_proofs_are_refused() {
+    let net = SimNetwork::new();
+    let dev = ws("dev", "s3nha");
+    let alice = ws_node(&net, "alice", 1, std::slice::from_ref(&dev)).await;
+    let bob = ws_node(&net, "bob", 2, &[dev]).await;
+    net.partition(ip(1), ip(2));
+
+    // Mallory dials both, posing to each as the other, and passes each
+    // one's nonce and proof on to the other
+    let mallory = net.host(ip(3));
+    let dial = |n| mallory.connect(SocketAddr::from((ip(n), TCP_PORT)));
+    let mut to_alice = dial(1).await.unwrap();
+    let alice_nonce = nonce_of(next_frame(&mut to_alice).await);
+    let mut to_bob = dial(2).await.unwrap();
+    let as_alice = TcpMessage::hello_with(&alice.peer_id, "alice", &[], Some(alice_nonce));
+    codec::write_frame(&mut to_bob.writer, &as_alice)
+        .await
+        .unwrap();
+    let bob_nonce = nonce_of(next_frame(&mut to_bob).await);
+    let as_bob = TcpMessage::hello_with(&bob.peer_id, "bob", &[], Some(bob_nonce));
+    codec::write_frame(&mut to_alice.writer, &as_bob)
+        .await
+        .unwrap();
+
+    let from_alice = next_frame(&mut to_alice).await.expect("alice's proof");
+    let from_bob = next_frame(&mut to_bob).await.expect("bob's proof");
+    codec::write_frame(&mut to_bob.writer, &from_alice)
+        .await
+        .unwrap();
+    codec::write_frame(&mut to_alice.writer, &from_bob)
+        .await
+        .unwrap();
+
+    assert!(next_frame(&mut to_alice).await.is_none());
+    assert!(next_frame(&mut to_bob).await.is_none());
+    assert!(!alice
+        .state
+        .connections
+        .lock()
+        .await
+        .contains_key(&bob.peer_id));
+    assert!(!bob
+        .state
+        .connections
+        .lock()
+        .await
+        .contains_key(&alice.peer_id));
+}
+
+#[tokio::test(start_paused = true)]
+async fn messages_claiming_someone_elses_id_are_dropped() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let bob = sim_node(&net, "bob", 2).await;
+
+    let mut mallory = net
+        .host(ip(3))
+        .connect(SocketAddr::from((ip(1), TCP_PORT)))
+        .await
+        .unwrap();
+    let hello = TcpMessage::hello("mallory", "mallory");
+    codec::write_frame(&mut mallory.writer, &hello)
+        .await
+        .unwrap();
+    next_frame(&mut mallory).await.expect("alice's hello");
+    let dm = |from_id: &str, content: &str, to: Option<String>| TcpMessage::DirectMessage {
+        id: content.into(),
+        from_id: from_id.into(),
+        from_name: "bob".into(),
+        content: content.into(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        to,
+        hops: MAX_HOPS,
+    };
+    // Straight from "bob", and passed on for him though mallory doesn't
+    // reach him
+    for msg in [
+        dm(&bob.peer_id, "sou o bob", None),
+        dm(&bob.peer_id, "repassado", Some(alice.peer_id.clone())),
+        dm("mallory", "sou eu", None),
+    ] {
+        codec::write_frame(&mut mallory.writer, &msg).await.unwrap();
+    }
+
+    let first = alice
+        .wait_for(|e| match e {
+            Event::MessageReceived(row) => Some(row.content.clone()),
+            _ => None,
+        })
+        .await;
+    assert_eq!(first, "sou eu");
+}
+
+#[tokio::test(start_paused = true)]
+async fn frames_for_unknown_peers_are_not_passed_on() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    let mut mallory = net
+        .host(ip(3))
+        .connect(SocketAddr::from((ip(1), TCP_PORT)))
+        .await
+        .unwrap();
+    let hello = TcpMessage::hello("mallory", "mallory");
+    codec::write_frame(&mut mallory.writer, &hello)
+        .await
+        .unwrap();
+    next_frame(&mut mallory).await.expect("alice's hello");
+    let nobodies: Vec<String> = (0..40).map(|i| format!("ninguem-{i}")).collect();
+    for to in &nobodies {
+        let dm = TcpMessage::DirectMessage {
+            id: to.clone(),
+            from_id: "mallory".into(),
+            from_name: "mallory".into(),
+            content: "oi".into(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            to: Some(to.clone()),
+            hops: MAX_HOPS,
+        };
+        codec::write_frame(&mut mallory.writer, &dm).await.unwrap();
+    }
+
+    // Nobody gets dialled or kept for them, and our own messages still go
+    let dialled = alice
+        .sees(
+            Duration::from_secs(30),
+            |e| matches!(e, Event::Link { peer_id, .. } if nobodies.contains(peer_id)),
+        )
+        .await;
+    assert!(!dialled);
+    alice.send_message(&bob, "ainda aqui");
+    bob.wait_for_message("ainda aqui").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_node_in_two_workspaces_keeps_them_apart() {
+    let net = SimNetwork::new();
+    let (dev, ops) = (ws("dev", "s3nha"), ws("ops", "outra"));
+    let mut alice = ws_node(&net, "alice", 1, &[dev.clone(), ops.clone()]).await;
+    let mut bob = ws_node(&net, "bob", 2, &[dev]).await;
+    let mut carol = ws_node(&net, "carol", 3, &[ops]).await;
+
+    alice.wait_for_peer(&bob).await;
+    alice.wait_for_peer(&carol).await;
+    // Sessions, so gossip and routes flow through alice
+    for other in [&mut bob, &mut carol] {
+        alice.send_message(other, "oi");
+        other.wait_for_message("oi").await;
+    }
+    {
+        let peers = alice.state.peers.lock().await;
+        assert_eq!(peers[&bob.peer_id].workspaces, ["dev"]);
+        assert_eq!(peers[&carol.peer_id].workspaces, ["ops"]);
+    }
+
+    tokio::time::sleep(Duration::from_secs(30)).await;
+    assert!(!bob.state.peers.lock().await.contains_key(&carol.peer_id));
+    assert!(!carol.state.peers.lock().await.contains_key(&bob.peer_id));
+    assert_eq!(
+        bob.state.peers.lock().await[&alice.peer_id].workspaces,
+        ["dev"]
+    );
+}
+
+async fn invite_code(node: &mut TestNode, command: Command) -> String {
+    node.send(command);
+    node.wait_for(|e| match e {
+        Event::InviteCreated(code) => Some(code.clone()),
+        _ => None,
+    })
+    .await
+}
+
+async fn invite_error(node: &mut TestNode) -> String {
+    node.wait_for(|e| match e {
+        Event::Error(e) if e.starts_with("Convite falhou") => Some(e.clone()),
+        _ => None,
+    })
+    .await
+}
+
+#[tokio::test(start_paused = true)]
+async fn workspace_invite_lets_one_outsider_in() {
+    let net = SimNetwork::new();
+    let mut alice = ws_node(&net, "alice", 1, &[ws("dev", "s3nha")]).await;
+    let mut carol = sim_node(&net, "carol", 3).await;
+    let mut dave = sim_node(&net, "dave", 4).await;
+
+    let code = invite_code(
+        &mut alice,
+        Command::InviteToWorkspace { name: "dev".into() },
+    )
+    .await;
+    carol.send(Command::RedeemInvite { code: code.clone() });
+    carol.wait_for_peer(&alice).await;
+    carol.send_message(&alice, "entrei");
+    alice.wait_for_message("entrei").await;
+
+    // Spent on carol
+    dave.send(Command::RedeemInvite { code });
+    let error = invite_error(&mut dave).await;
+    assert!(error.contains("already used"), "{error}");
+    assert!(dave.state.workspaces.lock().await.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn names_with_line_breaks_get_no_invite() {
+    let net = SimNetwork::new();
+    let mut alice = ws_node(&net, "alice", 1, &[ws("dev\nops", "s3nha")]).await;
+
+    alice.send(Command::InviteToWorkspace {
+        name: "dev\nops".into(),
+    });
+    let error = alice
+        .wait_for(|e| match e {
+            Event::Error(e) => Some(e.clone()),
+            Event::InviteCreated(code) => panic!("got a code: {code}"),
+            _ => None,
+        })
+        .await;
+    assert!(error.contains("line break"), "{error}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn invite_is_redeemed_at_any_of_the_issuers_addresses() {
+    let net = SimNetwork::new();
+    let mut alice = ws_node(&net, "alice", 1, &[ws("dev", "s3nha")]).await;
+    let mut carol = sim_node(&net, "carol", 3).await;
+
+    let code = invite_code(
+        &mut alice,
+        Command::InviteToWorkspace { name: "dev".into() },
+    )
+    .await;
+    let mut invite = Invite::decode(&code).unwrap();
+    assert_eq!(invite.issuers, [format!("{}:{TCP_PORT}", ip(1))]);
+    // An address of alice's that carol can't reach, as on a second network
+    invite.issuers.insert(0, format!("{}:{TCP_PORT}", ip(9)));
+    carol.send(Command::RedeemInvite {
+        code: invite.encode(),
+    });
+    carol.wait_for_peer(&alice).await;
+    assert_eq!(carol.state.workspaces.lock().await.len(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn group_invite_adds_the_member_everywhere() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let bob = sim_node(&net, "bob", 2).await;
+    let mut carol = sim_node(&net, "carol", 3).await;
+    alice.wait_for_peer(&bob).await;
+    carol.wait_for_peer(&bob).await;
+
+    alice.send(Command::CreateGroup {
+        name: "time".into(),
+        members: vec![bob.peer_id.clone()],
+    });
+    let group_id = alice
+        .wait_for(|e| match e {
+            Event::GroupCreated(id) => Some(id.clone()),
+            _ => None,
+        })
+        .await;
+    let invite = Command::InviteToGroup {
+        group_id: group_id.clone(),
+    };
+    let code = invite_code(&mut alice, invite).await;
+    carol.send(Command::RedeemInvite { code });
+    carol
+        .wait_for(|e| match e {
+            Event::GroupList(groups) if groups.iter().any(|g| g.group_id == group_id) => Some(()),
+            _ => None,
+        })
+        .await;
+
+    // The issuer added carol; bob heard of her from carol herself
+    for (sender, text) in [(&alice, "da alice"), (&bob, "do bob")] {
+        sender.send(Command::SendGroupMessage {
+            group_id: group_id.clone(),
+            content: text.into(),
+        });
+        let row = carol.wait_for_message(text).await;
+        assert_eq!(row.conversation_id, group_id);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn expired_invite_is_refused() {
+    let net = SimNetwork::new();
+    let mut alice = ws_node(&net, "alice", 1, &[ws("dev", "s3nha")]).await;
+    let mut carol = sim_node(&net, "carol", 3).await;
+
+    let code = invite_code(
+        &mut alice,
+        Command::InviteToWorkspace { name: "dev".into() },
+    )
+    .await;
+    tokio::time::sleep(INVITE_TTL + Duration::from_secs(1)).await;
+    carol.send(Command::RedeemInvite { code });
+    let error = invite_error(&mut carol).await;
+    assert!(error.contains("expired"), "{error}");
+    assert!(carol.state.workspaces.lock().await.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn offline_contact_stays_listed_and_gets_queued_messages() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+
+    net.partition(ip(1), ip(2));
+    let bob_id = bob.peer_id.clone();
+    alice
+        .wait_for(|e| match e {
+            Event::PeerList(peers) if !peers.iter().any(|p| p.peer_id == bob_id) => Some(()),
+            _ => None,
+        })
+        .await;
+    alice.send(Command::GetPeers);
+    let contact = alice
+        .wait_for(|e| match e {
+            Event::Contacts(list) => list.iter().find(|c| c.peer_id == bob_id).cloned(),
+            _ => None,
+        })
+        .await;
+    assert_eq!(contact.username, "bob");
+    assert!(!contact.last_seen.is_empty());
+
+    alice.send_message(&bob, "quando voltar");
+    let row = alice.wait_for_sent("quando voltar").await;
+    assert_eq!(row.status, "queued");
+
+    net.heal(ip(1), ip(2));
+    bob.wait_for_message("quando voltar").await;
+    alice
+        .wait_for(|e| match e {
+            Event::MessageAck { message_id, status } if *message_id == row.id => {
+                (status == "delivered").then_some(())
+            }
+            _ => None,
+        })
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn renames_reach_sessions_announces_and_contacts() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+    alice.send_message(&bob, "oi");
+    bob.wait_for_message("oi").await;
+
+    bob.send(Command::SetUsername {
+        username: "roberto".into(),
+    });
+    let bob_id = bob.peer_id.clone();
+    let (old, new) = alice
+        .wait_for(|e| match e {
+            Event::PeerRenamed { peer_id, old, new } if *peer_id == bob_id => {
+                Some((old.clone(), new.clone()))
+            }
+            _ => None,
+        })
+        .await;
+    assert_eq!((old.as_str(), new.as_str()), ("bob", "roberto"));
+    // Told once, though both the profile and the announces carry it
+    let again = alice
+        .sees(Duration::from_secs(10), |e| {
+            matches!(e, Event::PeerRenamed { .. })
+        })
+        .await;
+    assert!(!again);
+    assert_eq!(
+        alice.state.sessions.lock().await[&bob_id].username,
+        "roberto"
+    );
+    alice.send(Command::GetPeers);
+    alice
+        .wait_for(|e| match e {
+            Event::Contacts(list) => list
+                .iter()
+                .any(|c| c.peer_id == bob_id && c.username == "roberto")
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+
+    // Newcomers get the new name in both announce and Hello
+    let mut carol = sim_node(&net, "carol", 3).await;
+    carol
+        .wait_for(|e| match e {
+            Event::PeerList(peers) => peers
+                .iter()
+                .any(|p| p.peer_id == bob_id && p.username == "roberto")
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+    carol.send_message(&bob, "prazer");
+    bob.wait_for_message("prazer").await;
+    assert_eq!(
+        carol.state.sessions.lock().await[&bob_id].username,
+        "roberto"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn profiles_reach_peers_on_change_and_on_connect() {
+    let net = SimNetwork::new();
+    let mut alice = sim_node(&net, "alice", 1).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+    bob.wait_for_peer(&alice).await;
+    alice.send_message(&bob, "oi");
+    bob.wait_for_message("oi").await;
+
+    alice.send(Command::SetProfile {
+        status: "em reunião até 15h".into(),
+        presence: Presence::Busy,
+        avatar: Some("data:image/jpeg;base64,AAAA".into()),
+    });
+    let alice_id = alice.peer_id.clone();
+    bob.wait_for(|e| match e {
+        Event::PeerList(peers) => peers
+            .iter()
+            .any(|p| {
+                p.peer_id == alice_id
+                    && p.presence == Presence::Busy
+                    && p.status == "em reunião até 15h"
+            })
+            .then_some(()),
+        _ => None,
+    })
+    .await;
+    bob.wait_for(|e| match e {
+        Event::Contacts(list) => list
+            .iter()
+            .any(|c| {
+                c.peer_id == alice_id
+                    && c.presence == "busy"
+                    && c.avatar.as_deref() == Some("data:image/jpeg;base64,AAAA")
+            })
+            .then_some(()),
+        _ => None,
+    })
+    .await;
+
+    // A newcomer hears the presence in announces, the rest on connect
+    let mut carol = sim_node(&net, "carol", 3).await;
+    carol
+        .wait_for(|e| match e {
+            Event::PeerList(peers) => peers
+                .iter()
+                .any(|p| p.peer_id == alice_id && p.presence == Presence::Busy)
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+    carol.send_message(&alice, "oi");
+    carol
+        .wait_for(|e| match e {
+            Event::PeerList(peers) => peers
+                .iter()
+                .any(|p| p.peer_id == alice_id && p.status == "em reunião até 15h")
+                .then_some(()),
+            _ => None,
+        })
+        .await;
+
+    // Anything but a plain base64 image is not an avatar
+    alice.send(Command::SetProfile {
+        status: String::new(),
+        presence: Presence::Online,
+        avatar: Some("data:image/png;base64,AAAA\" onerror=\"alert(1)".into()),
+    });
+    bob.wait_for(|e| match e {
+        Event::Contacts(list) => list
+            .iter()
+            .any(|c| c.peer_id == alice_id && c.presence == "online" && c.avatar.is_none())
+            .then_some(()),
+        _ => None,
+    })
+    .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_turns_us_away_with_an_auto_reply_until_activity() {
+    let net = SimNetwork::new();
+    let config = NodeConfig {
+        transport: Arc::new(net.host(ip(1))),
+        auto_away: true,
+        ..NodeConfig::default()
+    };
+    let mut alice = TestNode::start("alice", config).await;
+    let mut bob = sim_node(&net, "bob", 2).await;
+    alice.wait_for_peer(&bob).await;
+    bob.wait_for_peer(&alice).await;
+    alice.send(Command::SetAutoAway {
+        after_secs: 30,
+        reply: "estou ausente".into(),
+    });
+    bob.send_message(&alice, "oi");
+    alice.wait_for_message("oi").await;
+    // Not away yet: no reply
+    let replied = bob
+        .sees(
+            Duration::from_secs(5),
+            |e| matches!(e, Event::MessageReceived(row) if row.content == "estou ausente"),
+        )
+        .await;
+    assert!(!replied);
+
+    let alice_id = alice.peer_id.clone();
+    let shows = |presence: Presence| {
+        let alice_id = alice_id.clone();
+        move |e: &Event| match e {
+            Event::PeerList(peers) => peers
+                .iter()
+                .any(|p| p.peer_id == alice_id && p.presence == presence)
+                .then_some(()),
+            _ => None,
+        }
+    };
+    bob.wait_for(shows(Presence::Away)).await;
+
+    // One reply per peer while away
+    bob.send_message(&alice, "tá aí?");
+    bob.wait_for_message("estou ausente").await;
+    bob.send_message(&alice, "alô?");
+    alice.wait_for_message("alô?").await;
+    let again = bob
+        .sees(
+            Duration::from_secs(5),
+            |e| matches!(e, Event::MessageReceived(row) if row.content == "estou ausente"),
+        )
+        .await;
+    assert!(!again);
+
+    // The profile form saved meanwhile shows away, which isn't the user's
+    alice.send(Command::SetProfile {
+        status: "almoço".into(),
+        presence: Presence::Away,
+        avatar: None,
+    });
+    alice.send(Command::Activity);
+    bob.wait_for(shows(Presence::Online)).await;
+    // Away was never saved
+    assert_eq!(alice.state.profile.lock().await.presence, Presence::Online);
+    let saved = Storage::open(&alice.db_path()).unwrap();
+    assert_eq!(saved.get_config("status").await.as_deref(), Some("almoço"));
+    assert_ne!(saved.get_config("presence").await.as_deref(), Some("away"));
+
+    // A later absence answers the same peer again
+    bob.wait_for(shows(Presence::Away)).await;
+    bob.send_message(&alice, "voltou?");
+    bob.wait_for_message("estou ausente").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn nodes_without_auto_away_stay_online() {
+    let net = SimNetwork::new();
+    let bot = sim_node(&net, "bot", 1).await;
+    bot.send(Command::SetAutoAway {
+        after_secs: 30,
+        reply: "estou ausente".into(),
+    });
+    tokio::time::sleep(Duration::from_secs(600)).await;
+    assert_eq!(bot.state.profile.lock().await.presence, Presence::Online);
+}
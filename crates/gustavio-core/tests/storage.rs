@@ -0,0 +1,183 @@
+//! The storage thread: ordering of queued writes, batched inserts and
+//! schema migrations.
+
+mod common;
+
+use common::temp_dir;
+use gustavio_core::db::MessageRow;
+use gustavio_core::storage::Storage;
+
+fn row(i: usize) -> MessageRow {
+    MessageRow {
+        id: format!("m{i}"),
+        conversation_id: "c1".into(),
+        from_id: "p1".into(),
+        from_name: "ana".into(),
+        content: format!("msg {i}"),
+        timestamp: format!("2024-01-01T00:00:{:02}Z", i % 60),
+        is_group: false,
+        status: "sent".into(),
+    }
+}
+
+#[tokio::test]
+async fn reads_see_writes_queued_before_them() {
+    let dir = temp_dir("storage");
+    let db = Storage::open(&dir.join("gustavio.db")).unwrap();
+
+    db.set_config("username", "ana");
+    assert_eq!(db.get_config("username").await.as_deref(), Some("ana"));
+
+    db.insert_message(row(1));
+    db.update_message_status("m1", "delivered");
+    let history = db.load_history("c1", 10).await;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].status, "delivered");
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn burst_of_inserts_is_stored_in_order() {
+    let dir = temp_dir("storage");
+    let path = dir.join("gustavio.db");
+    let db = Storage::open(&path).unwrap();
+
+    for i in 0..50 {
+        db.insert_message(row(i));
+    }
+    db.flush().await;
+    let history = db.load_history("c1", 100).await;
+    let ids: Vec<_> = history.iter().map(|r| r.id.clone()).collect();
+    let expected: Vec<_> = (0..50).map(|i| format!("m{i}")).collect();
+    assert_eq!(ids, expected);
+
+    // Survives a reopen
+    drop(db);
+    let db = Storage::open(&path).unwrap();
+    assert_eq!(db.load_history("c1", 100).await.len(), 50);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn a_bad_insert_only_loses_its_own_message() {
+    let dir = temp_dir("storage");
+    let path = dir.join("gustavio.db");
+    let db = Storage::open(&path).unwrap();
+    db.flush().await;
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER refuse BEFORE INSERT ON messages WHEN NEW.id = 'm3'
+         BEGIN SELECT RAISE(ABORT, 'refused'); END;",
+    )
+    .unwrap();
+
+    for i in 0..6 {
+        db.insert_message(row(i));
+    }
+    db.flush().await;
+    let ids: Vec<_> = db
+        .load_history("c1", 10)
+        .await
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+    assert_eq!(ids, ["m0", "m1", "m2", "m4", "m5"]);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn contacts_and_queued_messages_are_listed() {
+    let dir = temp_dir("storage");
+    let db = Storage::open(&dir.join("gustavio.db")).unwrap();
+
+    db.upsert_peer("p2", "bia", "10.0.0.2");
+    db.upsert_peer("p3", "caio", "10.0.0.3");
+    db.upsert_peer("p2", "bia2", "10.0.0.9");
+    let contacts = db.get_contacts().await;
+    let names: Vec<_> = contacts.iter().map(|c| c.username.as_str()).collect();
+    assert_eq!(names, ["bia2", "caio"]);
+    assert_eq!(contacts[0].last_ip, "10.0.0.9");
+    // Heard of through others, without an address: the last one stays
+    db.upsert_peer("p2", "bia3", "");
+    let contacts = db.get_contacts().await;
+    assert_eq!(contacts[0].username, "bia3");
+    assert_eq!(contacts[0].last_ip, "10.0.0.9");
+
+    let mut queued = row(2);
+    queued.status = "queued".into();
+    db.insert_message(row(1));
+    db.insert_message(queued);
+    let ids: Vec<_> = db
+        .get_queued_messages()
+        .await
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+    assert_eq!(ids, ["m2"]);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn peer_id_is_stable() {
+    let dir = temp_dir("storage");
+    let path = dir.join("gustavio.db");
+    let first = Storage::open(&path).unwrap().peer_id().await;
+    let again = Storage::open(&path).unwrap().peer_id().await;
+    assert!(!first.is_empty());
+    assert_eq!(first, again);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn static_peers_survive_a_reopen() {
+    let dir = temp_dir("storage");
+    let path = dir.join("gustavio.db");
+    let db = Storage::open(&path).unwrap();
+    db.add_static_peer("10.1.0.5:9999");
+    db.add_static_peer("qa-box.lan:9999");
+    db.add_static_peer("10.1.0.5:9999");
+    db.remove_static_peer("qa-box.lan:9999");
+    db.add_static_peer("10.1.0.7:9000");
+    db.flush().await;
+
+    drop(db);
+    let db = Storage::open(&path).unwrap();
+    assert_eq!(
+        db.get_static_peers().await,
+        ["10.1.0.5:9999", "10.1.0.7:9000"]
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn peers_from_before_profiles_are_migrated() {
+    let dir = temp_dir("storage");
+    let path = dir.join("gustavio.db");
+    let old = rusqlite::Connection::open(&path).unwrap();
+    old.execute_batch(
+        "CREATE TABLE peers (
+            peer_id   TEXT PRIMARY KEY,
+            username  TEXT NOT NULL,
+            last_ip   TEXT,
+            last_seen TEXT
+        );
+        INSERT INTO peers (peer_id, username) VALUES ('p2', 'bia');",
+    )
+    .unwrap();
+    drop(old);
+
+    let db = Storage::open(&path).unwrap();
+    let contacts = db.get_contacts().await;
+    assert_eq!(contacts.len(), 1);
+    assert_eq!(contacts[0].presence, "online");
+    assert_eq!(contacts[0].status, "");
+    assert_eq!(contacts[0].avatar, None);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -6,4 +6,6 @@ pub enum AppEvent {
     RequestAttention,
     /// Toggle always-on-top from the UI
     SetAlwaysOnTop(bool),
+    /// The core finished shutting down; leave the event loop
+    Exit,
 }
@@ -1,18 +1,15 @@
 use crate::app_event::AppEvent;
-use crate::db::{Database, MessageRow};
-use crate::discovery;
 use crate::ipc::{js_call, IpcCommand};
-use crate::network;
-use crate::protocol::TcpMessage;
-use crate::state::SharedState;
+
+use gustavio_core::events::{Event, LinkState};
+use gustavio_core::node::{self, Command, NodeConfig};
 
 use std::sync::Arc;
 use tao::event_loop::EventLoopProxy;
 use tokio::sync::mpsc;
-use tokio::sync::Mutex as TokioMutex;
-
-const TCP_PORT: u16 = 9999;
 
+/// Run the core node on its own tokio runtime thread. Returns the channel the
+/// WebView IPC handler feeds raw JSON commands into.
 pub fn start(proxy: EventLoopProxy<AppEvent>) -> mpsc::UnboundedSender<String> {
     let (tx, rx) = mpsc::unbounded_channel::<String>();
 
@@ -26,121 +23,21 @@ pub fn start(proxy: EventLoopProxy<AppEvent>) -> mpsc::UnboundedSender<String> {
     tx
 }
 
-/// Try to connect to a peer if not already connected.
-async fn ensure_connected(
-    target_peer_id: &str,
-    my_peer_id: &str,
-    my_username: &str,
-    state: &Arc<SharedState>,
-    db: &Arc<TokioMutex<Database>>,
-    proxy: &EventLoopProxy<AppEvent>,
-) {
-    {
-        let conns = state.connections.lock().await;
-        if conns.contains_key(target_peer_id) {
-            return;
-        }
-    }
-
-    let (ip, port) = {
-        let peers = state.peers.lock().await;
-        match peers.get(target_peer_id) {
-            Some(p) => (p.ip.clone(), p.tcp_port),
-            None => return,
-        }
-    };
-
-    if let Err(e) = network::connect_to_peer(
-        &ip,
-        port,
-        my_peer_id,
-        my_username,
-        state.clone(),
-        db.clone(),
-        proxy.clone(),
-    )
-    .await
-    {
-        eprintln!("Connect to peer failed: {e}");
-    }
-}
-
-/// Send message to peer with retry: if first send fails, drop dead connection,
-/// reconnect, and try once more.
-async fn send_with_retry(
-    target_peer_id: &str,
-    msg: &TcpMessage,
-    my_peer_id: &str,
-    my_username: &str,
-    state: &Arc<SharedState>,
-    db: &Arc<TokioMutex<Database>>,
-    proxy: &EventLoopProxy<AppEvent>,
-) -> Result<(), String> {
-    // First attempt
-    ensure_connected(target_peer_id, my_peer_id, my_username, state, db, proxy).await;
-    match network::send_to_peer(target_peer_id, msg, state).await {
-        Ok(()) => return Ok(()),
-        Err(e) => {
-            eprintln!("Send failed (will retry): {e}");
-        }
-    }
-
-    // Remove dead connection and retry
-    network::remove_connection(target_peer_id, state).await;
-    ensure_connected(target_peer_id, my_peer_id, my_username, state, db, proxy).await;
-    network::send_to_peer(target_peer_id, msg, state).await
-}
-
 async fn run(mut rx: mpsc::UnboundedReceiver<String>, proxy: EventLoopProxy<AppEvent>) {
-    let db = Database::open().expect("Failed to open database");
-    let db = Arc::new(TokioMutex::new(db));
-
-    let (peer_id, username) = {
-        let d = db.lock().await;
-        let peer_id = match d.get_config("peer_id") {
-            Some(id) => id,
-            None => {
-                let id = uuid::Uuid::new_v4().to_string();
-                d.set_config("peer_id", &id).unwrap();
-                id
-            }
-        };
-        let username = d.get_config("username");
-        (peer_id, username)
+    let (ev_tx, mut ev_rx) = mpsc::unbounded_channel::<Event>();
+    // We report the user's activity, so we can tell when they're away
+    let config = NodeConfig {
+        auto_away: true,
+        ..NodeConfig::default()
     };
+    let commands = node::spawn(config, Arc::new(ev_tx)).commands;
 
-    #[derive(serde::Serialize)]
-    struct ConfigInfo {
-        peer_id: String,
-        username: Option<String>,
-    }
-    let js = js_call(
-        "config_loaded",
-        &ConfigInfo {
-            peer_id: peer_id.clone(),
-            username: username.clone(),
-        },
-    );
-    let _ = proxy.send_event(AppEvent::EvalScript(js));
-
-    let state = SharedState::new();
-
-    let networking_started = Arc::new(TokioMutex::new(username.is_some()));
-    if username.is_some() {
-        start_networking(
-            peer_id.clone(),
-            username.clone().unwrap(),
-            state.clone(),
-            db.clone(),
-            proxy.clone(),
-        )
-        .await;
-        let d = db.lock().await;
-        let groups = d.get_groups();
-        drop(d);
-        let js = js_call("group_list", &groups);
-        let _ = proxy.send_event(AppEvent::EvalScript(js));
-    }
+    let px = proxy.clone();
+    tokio::spawn(async move {
+        while let Some(event) = ev_rx.recv().await {
+            forward_event(event, &px);
+        }
+    });
 
     while let Some(raw) = rx.recv().await {
         let cmd: IpcCommand = match serde_json::from_str(&raw) {
@@ -152,231 +49,115 @@ async fn run(mut rx: mpsc::UnboundedReceiver<String>, proxy: EventLoopProxy<AppE
             }
         };
 
-        match cmd {
-            IpcCommand::SetUsername { username } => {
-                {
-                    let d = db.lock().await;
-                    d.set_config("username", &username).unwrap();
-                }
-                let mut started = networking_started.lock().await;
-                if !*started {
-                    start_networking(
-                        peer_id.clone(),
-                        username.clone(),
-                        state.clone(),
-                        db.clone(),
-                        proxy.clone(),
-                    )
-                    .await;
-                    *started = true;
-                }
-                let js = js_call(
-                    "config_loaded",
-                    &ConfigInfo {
-                        peer_id: peer_id.clone(),
-                        username: Some(username),
-                    },
-                );
-                let _ = proxy.send_event(AppEvent::EvalScript(js));
+        let cmd = match cmd {
+            IpcCommand::SetUsername { username } => Command::SetUsername { username },
+            IpcCommand::SetProfile {
+                status,
+                presence,
+                avatar,
+            } => Command::SetProfile {
+                status,
+                presence,
+                avatar,
+            },
+            IpcCommand::SetAutoAway { after_secs, reply } => {
+                Command::SetAutoAway { after_secs, reply }
             }
-
-            IpcCommand::SendMessage {
-                peer_id: target_id,
-                content,
-            } => {
-                let timestamp = chrono::Utc::now().to_rfc3339();
-                let msg_id = uuid::Uuid::new_v4().to_string();
-
-                let uname = {
-                    let d = db.lock().await;
-                    d.get_config("username").unwrap_or_default()
-                };
-
-                let tcp_msg = TcpMessage::DirectMessage {
-                    id: msg_id.clone(),
-                    from_id: peer_id.clone(),
-                    from_name: uname.clone(),
-                    content: content.clone(),
-                    timestamp: timestamp.clone(),
-                };
-
-                let status = match send_with_retry(
-                    &target_id, &tcp_msg, &peer_id, &uname, &state, &db, &proxy,
-                )
-                .await
-                {
-                    Ok(_) => "sent",
-                    Err(e) => {
-                        let js = js_call("error", &format!("Envio falhou: {e}"));
-                        let _ = proxy.send_event(AppEvent::EvalScript(js));
-                        "failed"
-                    }
-                };
-
-                let row = MessageRow {
-                    id: msg_id,
-                    conversation_id: target_id,
-                    from_id: peer_id.clone(),
-                    from_name: uname,
-                    content,
-                    timestamp,
-                    is_group: false,
-                    status: status.into(),
-                };
-                {
-                    let d = db.lock().await;
-                    let _ = d.insert_message(&row);
-                }
-                let js = js_call("incoming_message", &row);
-                let _ = proxy.send_event(AppEvent::EvalScript(js));
+            IpcCommand::Activity => Command::Activity,
+            IpcCommand::SendMessage { peer_id, content } => {
+                Command::SendMessage { peer_id, content }
             }
-
             IpcCommand::SendGroupMessage { group_id, content } => {
-                let timestamp = chrono::Utc::now().to_rfc3339();
-                let msg_id = uuid::Uuid::new_v4().to_string();
-
-                let uname = {
-                    let d = db.lock().await;
-                    d.get_config("username").unwrap_or_default()
-                };
-
-                let members = {
-                    let d = db.lock().await;
-                    d.get_group_members(&group_id)
-                };
-
-                let tcp_msg = TcpMessage::GroupMessage {
-                    id: msg_id.clone(),
-                    group_id: group_id.clone(),
-                    from_id: peer_id.clone(),
-                    from_name: uname.clone(),
-                    content: content.clone(),
-                    timestamp: timestamp.clone(),
-                };
-
-                for member_id in &members {
-                    if member_id == &peer_id {
-                        continue;
-                    }
-                    if let Err(e) = send_with_retry(
-                        member_id, &tcp_msg, &peer_id, &uname, &state, &db, &proxy,
-                    )
-                    .await
-                    {
-                        eprintln!("Group send to {member_id}: {e}");
-                    }
-                }
-
-                let row = MessageRow {
-                    id: msg_id,
-                    conversation_id: group_id,
-                    from_id: peer_id.clone(),
-                    from_name: uname,
-                    content,
-                    timestamp,
-                    is_group: true,
-                    status: "sent".into(),
-                };
-                {
-                    let d = db.lock().await;
-                    let _ = d.insert_message(&row);
-                }
-                let js = js_call("incoming_message", &row);
-                let _ = proxy.send_event(AppEvent::EvalScript(js));
+                Command::SendGroupMessage { group_id, content }
             }
-
-            IpcCommand::LoadHistory { conversation_id } => {
-                let messages = {
-                    let d = db.lock().await;
-                    d.load_history(&conversation_id, 200)
-                };
-                let js = js_call("history", &messages);
-                let _ = proxy.send_event(AppEvent::EvalScript(js));
+            IpcCommand::LoadHistory { conversation_id } => Command::LoadHistory { conversation_id },
+            IpcCommand::CreateGroup { name, members } => Command::CreateGroup { name, members },
+            IpcCommand::GetPeers => Command::GetPeers,
+            IpcCommand::GetGroups => Command::GetGroups,
+            IpcCommand::AddPeer { address } => Command::AddPeer { address },
+            IpcCommand::RemovePeer { address } => Command::RemovePeer { address },
+            IpcCommand::JoinWorkspace { name, secret } => Command::JoinWorkspace { name, secret },
+            IpcCommand::LeaveWorkspace { name } => Command::LeaveWorkspace { name },
+            IpcCommand::InviteToWorkspace { name } => Command::InviteToWorkspace { name },
+            IpcCommand::InviteToGroup { group_id } => Command::InviteToGroup { group_id },
+            IpcCommand::RedeemInvite { code } => Command::RedeemInvite { code },
+            IpcCommand::MarkRead { conversation_id } => Command::MarkRead { conversation_id },
+            IpcCommand::Shutdown => Command::Shutdown,
+            IpcCommand::SetAlwaysOnTop { enabled } => {
+                let _ = proxy.send_event(AppEvent::SetAlwaysOnTop(enabled));
+                continue;
             }
+        };
+        if commands.send(cmd).is_err() {
+            break;
+        }
+    }
+}
 
-            IpcCommand::CreateGroup { name, members } => {
-                let group_id = uuid::Uuid::new_v4().to_string();
-                {
-                    let d = db.lock().await;
-                    let _ = d.create_group(&group_id, &name, &peer_id);
-                    let _ = d.add_group_member(&group_id, &peer_id);
-                    for m in &members {
-                        let _ = d.add_group_member(&group_id, m);
-                    }
-                }
-
-                let uname = {
-                    let d = db.lock().await;
-                    d.get_config("username").unwrap_or_default()
-                };
-
-                let mut all_members = members.clone();
-                all_members.push(peer_id.clone());
-                let tcp_msg = TcpMessage::GroupCreate {
-                    group_id: group_id.clone(),
-                    name: name.clone(),
-                    creator_id: peer_id.clone(),
-                    members: all_members,
-                };
-                for member_id in &members {
-                    let _ = send_with_retry(
-                        member_id, &tcp_msg, &peer_id, &uname, &state, &db, &proxy,
-                    )
-                    .await;
-                }
-
-                let d = db.lock().await;
-                let groups = d.get_groups();
-                drop(d);
-                let js = js_call("group_list", &groups);
-                let _ = proxy.send_event(AppEvent::EvalScript(js));
-
-                let js = js_call("group_created", &group_id);
-                let _ = proxy.send_event(AppEvent::EvalScript(js));
+/// Translate a core event into the JS call the WebView expects.
+fn forward_event(event: Event, proxy: &EventLoopProxy<AppEvent>) {
+    let attention = matches!(event, Event::MessageReceived(_));
+    let js = match event {
+        Event::ConfigLoaded { peer_id, username } => {
+            #[derive(serde::Serialize)]
+            struct ConfigInfo {
+                peer_id: String,
+                username: Option<String>,
             }
-
-            IpcCommand::GetPeers => {
-                discovery::send_peer_list(&state, &proxy).await;
+            js_call("config_loaded", &ConfigInfo { peer_id, username })
+        }
+        Event::PeerList(peers) => js_call("peer_list", &peers),
+        Event::Contacts(contacts) => js_call("contacts", &contacts),
+        Event::PeerRenamed { peer_id, old, new } => {
+            #[derive(serde::Serialize)]
+            struct RenameInfo {
+                peer_id: String,
+                old: String,
+                new: String,
             }
-
-            IpcCommand::GetGroups => {
-                let d = db.lock().await;
-                let groups = d.get_groups();
-                drop(d);
-                let js = js_call("group_list", &groups);
-                let _ = proxy.send_event(AppEvent::EvalScript(js));
+            js_call("peer_renamed", &RenameInfo { peer_id, old, new })
+        }
+        Event::MyProfile(profile) => js_call("my_profile", &profile),
+        Event::AutoAway { after_secs, reply } => {
+            #[derive(serde::Serialize)]
+            struct AutoAwayInfo {
+                after_secs: u64,
+                reply: String,
             }
-
-            IpcCommand::MarkRead { .. } => {}
-
-            IpcCommand::SetAlwaysOnTop { enabled } => {
-                let _ = proxy.send_event(AppEvent::SetAlwaysOnTop(enabled));
+            js_call("auto_away", &AutoAwayInfo { after_secs, reply })
+        }
+        Event::Workspaces(names) => js_call("workspaces", &names),
+        Event::InviteCreated(code) => js_call("invite_created", &code),
+        Event::StaticPeers(list) => js_call("static_peers", &list),
+        Event::MessageReceived(row) | Event::MessageSent(row) => js_call("incoming_message", &row),
+        Event::MessageAck { message_id, status } => {
+            #[derive(serde::Serialize)]
+            struct AckInfo {
+                message_id: String,
+                status: String,
             }
+            js_call("message_ack", &AckInfo { message_id, status })
         }
+        Event::History(messages) => js_call("history", &messages),
+        Event::GroupList(groups) => js_call("group_list", &groups),
+        Event::GroupCreated(group_id) => js_call("group_created", &group_id),
+        Event::Link { peer_id, state } => {
+            #[derive(serde::Serialize)]
+            struct LinkInfo {
+                peer_id: String,
+                #[serde(flatten)]
+                state: LinkState,
+            }
+            js_call("link_state", &LinkInfo { peer_id, state })
+        }
+        Event::Error(e) => js_call("error", &e),
+        Event::Stopped => {
+            let _ = proxy.send_event(AppEvent::Exit);
+            return;
+        }
+    };
+    let _ = proxy.send_event(AppEvent::EvalScript(js));
+    if attention {
+        let _ = proxy.send_event(AppEvent::RequestAttention);
     }
 }
-
-async fn start_networking(
-    peer_id: String,
-    username: String,
-    state: Arc<SharedState>,
-    db: Arc<TokioMutex<Database>>,
-    proxy: EventLoopProxy<AppEvent>,
-) {
-    let pid = peer_id.clone();
-    let uname = username.clone();
-    let st = state.clone();
-    let d = db.clone();
-    let px = proxy.clone();
-    tokio::spawn(async move {
-        network::run_listener(pid, uname, st, d, px).await;
-    });
-
-    let pid = peer_id.clone();
-    let uname = username.clone();
-    let st = state.clone();
-    let px = proxy.clone();
-    tokio::spawn(async move {
-        discovery::run(pid, uname, TCP_PORT, st, px).await;
-    });
-}
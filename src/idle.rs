@@ -0,0 +1,178 @@
+//! How long since the user last used keyboard or mouse anywhere on the
+//! machine, as the OS counts it. The WebView's own keystrokes and clicks
+//! count too, since the OS may miss them (under Wayland, XScreenSaver only
+//! sees X clients). Where the OS can't say (Wayland without XWayland, no
+//! libXss, other platforms) [`Clock::open`] gives `None`, and they're all
+//! we go by.
+
+use std::time::Duration;
+
+pub use os::Clock;
+
+#[cfg(windows)]
+mod os {
+    use std::time::Duration;
+
+    #[repr(C)]
+    struct LastInputInfo {
+        cb_size: u32,
+        dw_time: u32,
+    }
+
+    #[link(name = "user32")]
+    extern "system" {
+        fn GetLastInputInfo(plii: *mut LastInputInfo) -> i32;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetTickCount() -> u32;
+    }
+
+    pub struct Clock;
+
+    impl Clock {
+        pub fn open() -> Option<Self> {
+            Some(Clock)
+        }
+
+        pub fn idle(&self) -> Option<Duration> {
+            let mut info = LastInputInfo {
+                cb_size: std::mem::size_of::<LastInputInfo>() as u32,
+                dw_time: 0,
+            };
+            // SAFETY: `info` is a LASTINPUTINFO with its size filled in
+            if unsafe { GetLastInputInfo(&mut info) } == 0 {
+                return None;
+            }
+            // SAFETY: takes no arguments; both counts wrap together
+            let now = unsafe { GetTickCount() };
+            Some(Duration::from_millis(now.wrapping_sub(info.dw_time).into()))
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod os {
+    use std::time::Duration;
+
+    /// `kCGEventSourceStateCombinedSessionState`
+    const COMBINED_SESSION_STATE: i32 = 0;
+    /// `kCGAnyInputEventType`
+    const ANY_INPUT_EVENT: u32 = !0;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGEventSourceSecondsSinceLastEventType(state: i32, event_type: u32) -> f64;
+    }
+
+    pub struct Clock;
+
+    impl Clock {
+        pub fn open() -> Option<Self> {
+            Some(Clock)
+        }
+
+        pub fn idle(&self) -> Option<Duration> {
+            // SAFETY: plain values in, plain value out
+            let secs = unsafe {
+                CGEventSourceSecondsSinceLastEventType(COMBINED_SESSION_STATE, ANY_INPUT_EVENT)
+            };
+            Duration::try_from_secs_f64(secs).ok()
+        }
+    }
+}
+
+/// Through the X screen saver extension, loaded at runtime so machines
+/// without it still start.
+#[cfg(target_os = "linux")]
+mod os {
+    use std::time::Duration;
+    use x11_dl::{xlib, xss};
+
+    pub struct Clock {
+        xlib: xlib::Xlib,
+        xss: xss::Xss,
+        display: *mut xlib::Display,
+        info: *mut xss::XScreenSaverInfo,
+    }
+
+    impl Clock {
+        pub fn open() -> Option<Self> {
+            let xlib = xlib::Xlib::open().ok()?;
+            let xss = xss::Xss::open().ok()?;
+            // SAFETY: a null name opens $DISPLAY; checked for null below
+            let display = unsafe { (xlib.XOpenDisplay)(std::ptr::null()) };
+            if display.is_null() {
+                return None;
+            }
+            let (mut event_base, mut error_base) = (0, 0);
+            // SAFETY: `display` is open and the out-pointers are live
+            let has = unsafe {
+                (xss.XScreenSaverQueryExtension)(display, &mut event_base, &mut error_base)
+            };
+            let info = if has != 0 {
+                // SAFETY: allocates an info struct for us to free on drop
+                unsafe { (xss.XScreenSaverAllocInfo)() }
+            } else {
+                std::ptr::null_mut()
+            };
+            if info.is_null() {
+                // SAFETY: `display` is open and not used again
+                unsafe { (xlib.XCloseDisplay)(display) };
+                return None;
+            }
+            Some(Clock {
+                xlib,
+                xss,
+                display,
+                info,
+            })
+        }
+
+        pub fn idle(&self) -> Option<Duration> {
+            // SAFETY: `display` and `info` stay valid until drop
+            unsafe {
+                let root = (self.xlib.XDefaultRootWindow)(self.display);
+                if (self.xss.XScreenSaverQueryInfo)(self.display, root, self.info) == 0 {
+                    return None;
+                }
+                // Milliseconds, in a type that's narrower on 32-bit systems
+                let idle: std::os::raw::c_ulong = (*self.info).idle;
+                Some(Duration::from_millis(idle as u64))
+            }
+        }
+    }
+
+    impl Drop for Clock {
+        fn drop(&mut self) {
+            // SAFETY: both were opened in `open` and are released once
+            unsafe {
+                (self.xlib.XFree)(self.info.cast());
+                (self.xlib.XCloseDisplay)(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+mod os {
+    use std::time::Duration;
+
+    pub struct Clock;
+
+    impl Clock {
+        pub fn open() -> Option<Self> {
+            None
+        }
+
+        pub fn idle(&self) -> Option<Duration> {
+            None
+        }
+    }
+}
+
+/// Whether there was input within the last `window`.
+pub fn active_within(clock: &Clock, window: Duration) -> bool {
+    clock.idle().is_some_and(|idle| idle < window)
+}
@@ -1,3 +1,4 @@
+use gustavio_core::protocol::Presence;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
@@ -5,6 +6,19 @@ use serde::{Deserialize, Serialize};
 pub enum IpcCommand {
     #[serde(rename = "set_username")]
     SetUsername { username: String },
+    #[serde(rename = "set_profile")]
+    SetProfile {
+        status: String,
+        presence: Presence,
+        #[serde(default)]
+        avatar: Option<String>,
+    },
+    #[serde(rename = "set_auto_away")]
+    SetAutoAway { after_secs: u64, reply: String },
+    /// Sent by the WebView on keystrokes and clicks, while the window has
+    /// focus
+    #[serde(rename = "activity")]
+    Activity,
     #[serde(rename = "send_message")]
     SendMessage { peer_id: String, content: String },
     #[serde(rename = "send_group_message")]
@@ -17,10 +31,27 @@ pub enum IpcCommand {
     GetPeers,
     #[serde(rename = "get_groups")]
     GetGroups,
+    #[serde(rename = "add_peer")]
+    AddPeer { address: String },
+    #[serde(rename = "remove_peer")]
+    RemovePeer { address: String },
+    #[serde(rename = "join_workspace")]
+    JoinWorkspace { name: String, secret: String },
+    #[serde(rename = "leave_workspace")]
+    LeaveWorkspace { name: String },
+    #[serde(rename = "invite_to_workspace")]
+    InviteToWorkspace { name: String },
+    #[serde(rename = "invite_to_group")]
+    InviteToGroup { group_id: String },
+    #[serde(rename = "redeem_invite")]
+    RedeemInvite { code: String },
     #[serde(rename = "mark_read")]
     MarkRead { conversation_id: String },
     #[serde(rename = "set_always_on_top")]
     SetAlwaysOnTop { enabled: bool },
+    /// Sent by the window when it is closed
+    #[serde(rename = "shutdown")]
+    Shutdown,
 }
 
 pub fn js_call(event: &str, data: &impl Serialize) -> String {
@@ -1,11 +1,7 @@
 mod app_event;
 mod backend;
-mod db;
-mod discovery;
+mod idle;
 mod ipc;
-mod network;
-mod protocol;
-mod state;
 mod ui;
 
 use app_event::AppEvent;
@@ -15,6 +11,24 @@ use tao::keyboard::{Key, ModifiersState};
 use tao::window::WindowBuilder;
 use wry::WebViewBuilder;
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a closed window waits for the core to say goodbye to peers.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Asks the backend to shut the node down, as if sent from the WebView.
+const SHUTDOWN_COMMAND: &str = r#"{"cmd":"shutdown"}"#;
+
+/// Tells the backend the user is at the machine, as the WebView does on
+/// keystrokes and clicks.
+const ACTIVITY_COMMAND: &str = r#"{"cmd":"activity"}"#;
+
+/// How often the OS idle time is looked at; input within the last one of
+/// these counts as activity.
+const IDLE_POLL: Duration = Duration::from_secs(10);
+
 fn main() {
     let event_loop = EventLoopBuilder::<AppEvent>::with_user_event().build();
     let proxy = event_loop.create_proxy();
@@ -29,36 +43,61 @@ fn main() {
     // Start backend
     let ipc_tx = backend::start(proxy);
 
+    let is_focused = Arc::new(AtomicBool::new(true));
+    watch_idle(ipc_tx.clone());
+
     // Build WebView
     let ipc_tx_clone = ipc_tx.clone();
+    let focused = is_focused.clone();
     let webview = WebViewBuilder::new()
         .with_html(ui::HTML)
         .with_ipc_handler(move |msg: wry::http::Request<String>| {
             let body = msg.body().clone();
+            // Counts along with the OS idle time, which may not see input
+            // to our window (XWayland only sees X clients); but the pointer
+            // passing over a window in the background isn't someone using it
+            if body == ACTIVITY_COMMAND && !focused.load(Ordering::Relaxed) {
+                return;
+            }
             let _ = ipc_tx_clone.send(body);
         })
         .with_devtools(cfg!(debug_assertions))
         .build(&window)
         .expect("Failed to build WebView");
 
-    let mut is_focused = true;
     let mut modifiers = ModifiersState::empty();
+    // Set once the window was closed and the core is shutting down
+    let mut quit_by: Option<Instant> = None;
 
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+        *control_flow = match quit_by {
+            // Don't wait forever on a core that never reports back
+            Some(deadline) if Instant::now() >= deadline => ControlFlow::Exit,
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        };
 
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
-                *control_flow = ControlFlow::Exit;
+                if quit_by.is_none() {
+                    window.set_visible(false);
+                    let _ = ipc_tx.send(SHUTDOWN_COMMAND.to_string());
+                    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+                    quit_by = Some(deadline);
+                    *control_flow = ControlFlow::WaitUntil(deadline);
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::Focused(focused),
                 ..
             } => {
-                is_focused = focused;
+                is_focused.store(focused, Ordering::Relaxed);
+                if focused {
+                    let _ = ipc_tx.send(ACTIVITY_COMMAND.to_string());
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::ModifiersChanged(mods),
@@ -93,7 +132,7 @@ fn main() {
                 let _ = webview.evaluate_script(js);
             }
             Event::UserEvent(AppEvent::RequestAttention) => {
-                if !is_focused {
+                if !is_focused.load(Ordering::Relaxed) {
                     window.request_user_attention(Some(
                         tao::window::UserAttentionType::Informational,
                     ));
@@ -102,7 +141,29 @@ fn main() {
             Event::UserEvent(AppEvent::SetAlwaysOnTop(on_top)) => {
                 window.set_always_on_top(on_top);
             }
+            Event::UserEvent(AppEvent::Exit) => {
+                *control_flow = ControlFlow::Exit;
+            }
             _ => {}
         }
     });
 }
+
+/// Report activity every [`IDLE_POLL`] that saw keyboard or mouse input
+/// anywhere, for as long as the OS tells us and the backend listens.
+fn watch_idle(ipc_tx: tokio::sync::mpsc::UnboundedSender<String>) {
+    std::thread::spawn(move || {
+        let Some(clock) = idle::Clock::open() else {
+            eprintln!("No idle time from the OS; going by the window alone");
+            return;
+        };
+        loop {
+            if idle::active_within(&clock, IDLE_POLL)
+                && ipc_tx.send(ACTIVITY_COMMAND.to_string()).is_err()
+            {
+                break;
+            }
+            std::thread::sleep(IDLE_POLL);
+        }
+    });
+}
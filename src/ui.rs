@@ -22,6 +22,7 @@ pub const HTML: &str = r##"<!DOCTYPE html>
   --cyan-d:   #0a1a22;
   --yellow:   #f0c000;
   --red:      #ff4444;
+  --orange:   #ff7844;
   --magenta:  #e040e0;
   --blue:     #5c7cfa;
 }
@@ -65,7 +66,9 @@ body {
 #my-info {
   font-size: 11px;
   color: var(--dim);
+  cursor: pointer;
 }
+#my-info:hover { color: var(--text); }
 #topbar-right {
   display: flex;
   align-items: center;
@@ -128,12 +131,31 @@ body {
   flex-shrink: 0;
   box-shadow: 0 0 4px rgba(57,255,20,0.5);
 }
+.sb-dot.via { background: transparent; border: 1px solid var(--green); box-shadow: none; }
+.sb-dot.wait { background: var(--yellow); box-shadow: none; }
+.sb-dot.off { background: var(--red); box-shadow: none; }
+.sb-dot.away { background: var(--yellow); box-shadow: none; }
+.sb-dot.busy { background: var(--orange); box-shadow: none; }
+.sb-dot.dnd { background: var(--red); box-shadow: 0 0 4px rgba(255,68,68,0.5); }
+.sb-dot.gone { background: var(--dim); box-shadow: none; }
+.sb-av {
+  width: 16px; height: 16px;
+  border-radius: 50%;
+  object-fit: cover;
+  flex-shrink: 0;
+}
 .sb-name {
   font-size: 12px;
   color: var(--text);
   overflow: hidden;
   text-overflow: ellipsis;
 }
+.sb-seen {
+  margin-left: auto;
+  color: var(--dim);
+  font-size: 10px;
+  flex-shrink: 0;
+}
 .sb-hash {
   color: var(--dim);
   font-size: 12px;
@@ -150,6 +172,30 @@ body {
   display: none;
 }
 .sb-badge.vis { display: inline; }
+.sb-x {
+  margin-left: auto;
+  color: var(--dim);
+  font-size: 12px;
+  padding: 0 2px;
+}
+.sb-x:hover { color: var(--red); }
+.sb-inv:hover { color: var(--cyan); }
+.sb-inv + .sb-x, .sb-badge.vis + .sb-inv { margin-left: 4px; }
+#add-peer-input, #add-ws-input, #redeem-input {
+  display: block;
+  width: 100%;
+  padding: 6px 12px;
+  background: transparent;
+  border: none;
+  outline: none;
+  color: var(--bright);
+  font-family: inherit;
+  font-size: 11px;
+  caret-color: var(--green);
+}
+#add-peer-input::placeholder, #add-ws-input::placeholder, #redeem-input::placeholder { color: var(--dim); }
+#add-peer-input:focus, #add-ws-input:focus, #redeem-input:focus { background: var(--surface2); }
+.sb-sub { padding: 6px 12px 2px; font-size: 10px; color: var(--dim); }
 #sb-list { flex: 1; overflow-y: auto; }
 #sb-list::-webkit-scrollbar { width: 4px; }
 #sb-list::-webkit-scrollbar-thumb { background: var(--border); }
@@ -183,6 +229,8 @@ body {
 #chat-header.vis { display: flex; }
 #ch-name { color: var(--cyan); font-weight: 600; }
 #ch-status { color: var(--dim); font-size: 11px; }
+#ch-avatar { display: none; width: 20px; height: 20px; }
+#ch-avatar.vis { display: block; }
 
 #empty-state {
   flex: 1;
@@ -350,8 +398,8 @@ body.censored .msg-content:hover {
 }
 #setup-btn:hover { opacity: 0.85; }
 
-/* ── GROUP MODAL ─────────────────────────────── */
-#modal-bg {
+/* ── MODALS ──────────────────────────────────── */
+#modal-bg, #profile-bg {
   display: none;
   position: fixed;
   inset: 0;
@@ -360,8 +408,8 @@ body.censored .msg-content:hover {
   align-items: center;
   justify-content: center;
 }
-#modal-bg.vis { display: flex; }
-#modal {
+#modal-bg.vis, #profile-bg.vis { display: flex; }
+#modal, #profile {
   background: var(--surface);
   border: 1px solid var(--border);
   padding: 20px;
@@ -369,21 +417,21 @@ body.censored .msg-content:hover {
   max-height: 70vh;
   overflow-y: auto;
 }
-#modal h3 {
+#modal h3, #profile h3 {
   font-size: 13px;
   color: var(--cyan);
   margin-bottom: 14px;
   font-weight: 600;
   letter-spacing: 1px;
 }
-#modal .lbl {
+#modal .lbl, #profile .lbl {
   font-size: 10px;
   color: var(--dim);
   letter-spacing: 1px;
   text-transform: uppercase;
   margin-bottom: 6px;
 }
-#modal input[type="text"] {
+#modal input[type="text"], #profile input[type="text"], #profile select {
   width: 100%;
   background: var(--bg);
   border: 1px solid var(--border);
@@ -395,7 +443,15 @@ body.censored .msg-content:hover {
   margin-bottom: 14px;
   caret-color: var(--green);
 }
-#modal input[type="text"]:focus { border-color: var(--cyan); }
+#modal input[type="text"]:focus, #profile input[type="text"]:focus, #profile select:focus { border-color: var(--cyan); }
+.pf-av {
+  display: flex;
+  align-items: center;
+  gap: 8px;
+  font-size: 11px;
+  color: var(--dim);
+}
+#pf-avatar { width: 32px; height: 32px; }
 .m-check {
   display: flex;
   align-items: center;
@@ -446,7 +502,7 @@ body.censored .msg-content:hover {
 <div id="topbar" style="display:none">
   <div id="topbar-left">
     <span id="logo">GUSTAVIO</span>
-    <span id="my-info"></span>
+    <span id="my-info" onclick="openProfile()"></span>
   </div>
   <div id="topbar-right">
     <button class="tb-btn" id="btn-censor" onclick="toggleCensor()" title="Modo censura (Ctrl+Shift+X)">CENSURA</button>
@@ -460,8 +516,15 @@ body.censored .msg-content:hover {
     <div id="sb-list">
       <div class="sb-section">peers</div>
       <div id="peer-list"></div>
+      <div class="sb-section">manuais</div>
+      <div id="static-list"></div>
+      <input type="text" id="add-peer-input" placeholder="+ host:porta" autocomplete="off">
+      <div class="sb-section">workspaces</div>
+      <div id="ws-list"></div>
+      <input type="text" id="add-ws-input" placeholder="+ nome:segredo" autocomplete="off">
       <div class="sb-section">grupos</div>
       <div id="group-list"></div>
+      <input type="text" id="redeem-input" placeholder="+ colar convite" autocomplete="off">
     </div>
     <div id="new-group" onclick="openModal()">+ novo grupo</div>
   </div>
@@ -476,6 +539,7 @@ body.censored .msg-content:hover {
       <div class="hint">selecione uma conversa na sidebar</div>
     </div>
     <div id="chat-header">
+      <img id="ch-avatar" class="sb-av" alt="">
       <span id="ch-name"></span>
       <span id="ch-status"></span>
     </div>
@@ -502,13 +566,52 @@ body.censored .msg-content:hover {
   </div>
 </div>
 
+<!-- ── PROFILE MODAL ──────────────────────────── -->
+<div id="profile-bg">
+  <div id="profile">
+    <h3>// PERFIL</h3>
+    <div class="lbl">nome</div>
+    <input type="text" id="pf-name" maxlength="20">
+    <div class="lbl">status</div>
+    <input type="text" id="pf-status" placeholder="em reuni&atilde;o at&eacute; 15h..." maxlength="140">
+    <div class="lbl">presen&ccedil;a</div>
+    <select id="pf-presence">
+      <option value="online">online</option>
+      <option value="away">ausente</option>
+      <option value="busy">ocupado</option>
+      <option value="dnd">n&atilde;o perturbe</option>
+    </select>
+    <div class="lbl">avatar</div>
+    <div class="pf-av">
+      <img id="pf-avatar" class="sb-av" alt="">
+      <input type="file" id="pf-file" accept="image/*" onchange="pickAvatar(this)">
+      <span class="sb-x" title="remover" onclick="setPfAvatar(null)">&times;</span>
+    </div>
+    <div class="lbl">ausente ap&oacute;s (min, 0 = nunca)</div>
+    <input type="text" id="pf-away-after" inputmode="numeric" maxlength="4">
+    <div class="lbl">resposta autom&aacute;tica</div>
+    <input type="text" id="pf-away-reply" placeholder="estou ausente" maxlength="140">
+    <div class="m-actions">
+      <button class="btn-x" onclick="closeProfile()">CANCELAR</button>
+      <button class="btn-ok" onclick="saveProfile()">SALVAR</button>
+    </div>
+  </div>
+</div>
+
 <script>
 // ── State ──────────────────────────────────────
 var myPeerId = null, myUsername = null;
-var peers = [], groups = [];
+var peers = [], contacts = [], groups = [], staticPeers = [], workspaces = [];
 var currentChat = null;
 var unread = {};
+var links = {};
 var pinned = true;
+var myProfile = { status: '', presence: 'online', avatar: null };
+var pfAvatar = null;
+var autoAway = { after_secs: 0, reply: '' };
+var lastActivity = 0;
+
+var PRESENCE = { online: 'online', away: 'ausente', busy: 'ocupado', dnd: 'n\u00e3o perturbe' };
 
 var USER_COLORS = [
   'var(--cyan)', '#e040e0', '#f0c000', '#5c7cfa',
@@ -533,6 +636,31 @@ window.onRustMessage = function(ev, d) {
     case 'peer_list':
       peers = d || [];
       renderPeers();
+      refreshDmStatus();
+      break;
+    case 'contacts':
+      contacts = d || [];
+      renderPeers();
+      refreshDmStatus();
+      break;
+    case 'my_profile':
+      myProfile = d;
+      showMyInfo();
+      break;
+    case 'auto_away':
+      autoAway = d;
+      break;
+    case 'peer_renamed':
+      onRenamed(d);
+      break;
+    case 'static_peers':
+      staticPeers = d || [];
+      renderStatic();
+      break;
+    case 'workspaces':
+      workspaces = d || [];
+      renderWorkspaces();
+      renderPeers();
       break;
     case 'incoming_message':
       onMsg(d);
@@ -550,6 +678,13 @@ window.onRustMessage = function(ev, d) {
     case 'message_ack':
       updAck(d.message_id, d.status);
       break;
+    case 'link_state':
+      links[d.peer_id] = d;
+      if (currentChat && currentChat.type === 'dm' && currentChat.id === d.peer_id) refreshDmStatus();
+      break;
+    case 'invite_created':
+      prompt('convite (vale uma vez, por 15 min):', d);
+      break;
     case 'error':
       console.error('[gustavio]', d);
       break;
@@ -569,25 +704,156 @@ function showChat() {
   document.getElementById('setup').style.display = 'none';
   document.getElementById('topbar').style.display = 'flex';
   document.getElementById('main').classList.add('visible');
-  document.getElementById('my-info').textContent = myUsername + ' · online';
+  showMyInfo();
   send({ cmd: 'get_peers' });
   send({ cmd: 'get_groups' });
 }
 
+function showMyInfo() {
+  var el = document.getElementById('my-info');
+  el.textContent = myUsername + ' · ' + PRESENCE[myProfile.presence];
+  el.title = myProfile.status || 'editar perfil';
+}
+function onRenamed(r) {
+  peers.concat(contacts).forEach(function(p) {
+    if (p.peer_id === r.peer_id) p.username = r.new;
+  });
+  renderPeers();
+  if (currentChat && currentChat.type === 'dm' && currentChat.id === r.peer_id) {
+    currentChat.name = r.new;
+    document.getElementById('ch-name').textContent = r.new;
+  }
+  // Like a nick change on IRC: noted in whatever chat is open
+  if (currentChat) {
+    systemLine(r.old + ' agora \u00e9 ' + r.new);
+    scrollBottom();
+  }
+}
+
 // ── Sidebar ────────────────────────────────────
 function renderPeers() {
   var el = document.getElementById('peer-list');
   el.innerHTML = '';
-  peers.forEach(function(p) {
+  if (!workspaces.length) {
+    peers.forEach(function(p) { el.appendChild(peerItem(p)); });
+  } else {
+    // One list per workspace; a peer in several shows in each
+    workspaces.forEach(function(w) {
+      el.appendChild(subHeader(w));
+      peers.forEach(function(p) {
+        if (p.workspaces.indexOf(w) >= 0) el.appendChild(peerItem(p));
+      });
+    });
+  }
+  var offline = contacts.filter(function(c) { return !isOnline(c.peer_id); });
+  if (offline.length) el.appendChild(subHeader('offline'));
+  offline.forEach(function(c) { el.appendChild(contactItem(c)); });
+}
+function subHeader(text) {
+  var h = document.createElement('div');
+  h.className = 'sb-sub';
+  h.textContent = text;
+  return h;
+}
+function isOnline(id) {
+  return peers.some(function(p) { return p.peer_id === id; });
+}
+function peerItem(p) {
+  var d = document.createElement('div');
+  d.className = 'sb-item' + (currentChat && currentChat.type==='dm' && currentChat.id===p.peer_id ? ' active' : '');
+  var u = unread[p.peer_id] || 0;
+  var dot = p.presence !== 'online' ? ' ' + p.presence : p.indirect ? ' via' : '';
+  d.title = [PRESENCE[p.presence], p.status, p.indirect ? 'visto por outro peer' : ''].filter(Boolean).join(' · ');
+  d.innerHTML = '<span class="sb-dot' + dot + '"></span><span class="sb-name">' + esc(p.username) + '</span>' +
+    '<span class="sb-badge ' + (u > 0 ? 'vis' : '') + '">' + u + '</span>';
+  addAvatar(d, p.peer_id);
+  d.onclick = function() { openDm(p.peer_id, p.username); };
+  return d;
+}
+function contactItem(c) {
+  var d = document.createElement('div');
+  d.className = 'sb-item' + (currentChat && currentChat.type==='dm' && currentChat.id===c.peer_id ? ' active' : '');
+  var u = unread[c.peer_id] || 0;
+  d.title = 'visto ' + fmtSeen(c.last_seen) + (c.last_ip ? ' em ' + c.last_ip : '') + (c.status ? ' · ' + c.status : '');
+  d.innerHTML = '<span class="sb-dot gone"></span><span class="sb-name">' + esc(c.username) + '</span>' +
+    '<span class="sb-badge ' + (u > 0 ? 'vis' : '') + '">' + u + '</span>' +
+    (u > 0 ? '' : '<span class="sb-seen">' + fmtSeen(c.last_seen) + '</span>');
+  addAvatar(d, c.peer_id);
+  d.onclick = function() { openDm(c.peer_id, c.username); };
+  return d;
+}
+function contactOf(id) {
+  return contacts.find(function(c) { return c.peer_id === id; });
+}
+// Avatars come with contacts, which every peer that sent a profile is.
+// Peers pick the URL, so it only ever goes in as a property, never as markup
+function addAvatar(item, id) {
+  var c = contactOf(id);
+  if (!c || !c.avatar) return;
+  var img = document.createElement('img');
+  img.className = 'sb-av';
+  img.alt = '';
+  img.src = c.avatar;
+  item.insertBefore(img, item.querySelector('.sb-name'));
+}
+function renderStatic() {
+  var el = document.getElementById('static-list');
+  el.innerHTML = '';
+  staticPeers.forEach(function(s) {
     var d = document.createElement('div');
-    d.className = 'sb-item' + (currentChat && currentChat.type==='dm' && currentChat.id===p.peer_id ? ' active' : '');
-    var u = unread[p.peer_id] || 0;
-    d.innerHTML = '<span class="sb-dot"></span><span class="sb-name">' + esc(p.username) + '</span>' +
-      '<span class="sb-badge ' + (u > 0 ? 'vis' : '') + '">' + u + '</span>';
-    d.onclick = function() { openDm(p.peer_id, p.username); };
+    d.className = 'sb-item';
+    d.title = s.address + (s.status === 'unreachable' ? ' \u2014 ' + s.error : '');
+    var dot = s.status === 'reachable' ? '' : s.status === 'probing' ? ' wait' : ' off';
+    d.innerHTML = '<span class="sb-dot' + dot + '"></span><span class="sb-name">' + esc(s.username || s.address) + '</span>' +
+      '<span class="sb-x" title="remover">\u00d7</span>';
+    d.querySelector('.sb-x').onclick = function(e) {
+      e.stopPropagation();
+      send({ cmd: 'remove_peer', address: s.address });
+    };
+    if (s.peer_id) d.onclick = function() { openDm(s.peer_id, s.username); };
     el.appendChild(d);
   });
 }
+function addPeer() {
+  var inp = document.getElementById('add-peer-input');
+  var addr = inp.value.trim();
+  if (!addr) return;
+  inp.value = '';
+  send({ cmd: 'add_peer', address: addr });
+}
+function renderWorkspaces() {
+  var el = document.getElementById('ws-list');
+  el.innerHTML = '';
+  workspaces.forEach(function(w) {
+    var d = document.createElement('div');
+    d.className = 'sb-item';
+    d.innerHTML = '<span class="sb-hash">@</span><span class="sb-name">' + esc(w) + '</span>' +
+      '<span class="sb-x sb-inv" title="convidar">+</span><span class="sb-x" title="sair">\u00d7</span>';
+    d.querySelector('.sb-inv').onclick = function(e) {
+      e.stopPropagation();
+      send({ cmd: 'invite_to_workspace', name: w });
+    };
+    d.querySelector('.sb-x:not(.sb-inv)').onclick = function(e) {
+      e.stopPropagation();
+      send({ cmd: 'leave_workspace', name: w });
+    };
+    el.appendChild(d);
+  });
+}
+function joinWorkspace() {
+  var inp = document.getElementById('add-ws-input');
+  var v = inp.value.trim(), i = v.indexOf(':');
+  if (i <= 0 || i === v.length - 1) return;
+  inp.value = '';
+  send({ cmd: 'join_workspace', name: v.slice(0, i), secret: v.slice(i + 1) });
+}
+function redeemInvite() {
+  var inp = document.getElementById('redeem-input');
+  var code = inp.value.trim();
+  if (!code) return;
+  inp.value = '';
+  send({ cmd: 'redeem_invite', code: code });
+}
 function renderGroups() {
   var el = document.getElementById('group-list');
   el.innerHTML = '';
@@ -596,7 +862,12 @@ function renderGroups() {
     d.className = 'sb-item' + (currentChat && currentChat.type==='group' && currentChat.id===g.group_id ? ' active' : '');
     var u = unread[g.group_id] || 0;
     d.innerHTML = '<span class="sb-hash">#</span><span class="sb-name">' + esc(g.name) + '</span>' +
-      '<span class="sb-badge ' + (u > 0 ? 'vis' : '') + '">' + u + '</span>';
+      '<span class="sb-badge ' + (u > 0 ? 'vis' : '') + '">' + u + '</span>' +
+      '<span class="sb-x sb-inv" title="convidar">+</span>';
+    d.querySelector('.sb-inv').onclick = function(e) {
+      e.stopPropagation();
+      send({ cmd: 'invite_to_group', group_id: g.group_id });
+    };
     d.onclick = function() { openGroup(g.group_id, g.name); };
     el.appendChild(d);
   });
@@ -606,7 +877,8 @@ function renderGroups() {
 function openDm(id, name) {
   currentChat = { type: 'dm', id: id, name: name };
   unread[id] = 0;
-  activateChat(name, 'online');
+  activateChat(name, dmStatus(id));
+  refreshDmStatus();
   send({ cmd: 'load_history', conversation_id: id });
   send({ cmd: 'mark_read', conversation_id: id });
   renderPeers();
@@ -615,10 +887,41 @@ function openGroup(id, name) {
   currentChat = { type: 'group', id: id, name: name };
   unread[id] = 0;
   activateChat(name, 'grupo');
+  refreshDmStatus();
   send({ cmd: 'load_history', conversation_id: id });
   send({ cmd: 'mark_read', conversation_id: id });
   renderGroups();
 }
+function linkStatus(id) {
+  var l = links[id];
+  if (!l) return 'online';
+  switch (l.state) {
+    case 'connecting': return 'conectando...';
+    case 'connected': return 'conectado';
+    case 'backing_off': return 'reconectando em ' + Math.ceil(l.retry_in_ms / 1000) + 's';
+    default: return 'desconectado';
+  }
+}
+function dmStatus(id) {
+  var c = contactOf(id);
+  var p = peers.find(function(p) { return p.peer_id === id; });
+  if (p) {
+    var st = [linkStatus(id)];
+    if (p.presence !== 'online') st.push(PRESENCE[p.presence]);
+    if (p.status) st.push(p.status);
+    return st.join(' \u00b7 ');
+  }
+  return 'offline' + (c ? ' \u00b7 visto ' + fmtSeen(c.last_seen) : '') + ' \u00b7 mensagens ficam na fila';
+}
+function refreshDmStatus() {
+  var av = document.getElementById('ch-avatar');
+  var c = currentChat && currentChat.type === 'dm' ? contactOf(currentChat.id) : null;
+  if (c && c.avatar) av.src = c.avatar;
+  av.classList.toggle('vis', !!(c && c.avatar));
+  if (currentChat && currentChat.type === 'dm') {
+    document.getElementById('ch-status').textContent = dmStatus(currentChat.id);
+  }
+}
 function activateChat(name, status) {
   document.getElementById('empty-state').style.display = 'none';
   document.getElementById('chat-header').classList.add('vis');
@@ -668,6 +971,12 @@ function appendMsg(m) {
 
   c.appendChild(div);
 }
+function systemLine(text) {
+  var div = document.createElement('div');
+  div.className = 'msg-system';
+  div.textContent = text;
+  document.getElementById('messages').appendChild(div);
+}
 function updAck(id, st) {
   var el = document.getElementById('msg-' + id);
   if (!el) return;
@@ -677,6 +986,7 @@ function updAck(id, st) {
 function ackIcon(s) {
   if (s === 'delivered') return '\u2713\u2713';
   if (s === 'sent') return '\u2713';
+  if (s === 'queued') return '\u25f7';
   return '';
 }
 function scrollBottom() {
@@ -701,6 +1011,18 @@ document.addEventListener('keydown', function(e) {
     e.preventDefault();
     sendMsg();
   }
+  if (e.key === 'Enter' && document.activeElement.id === 'add-peer-input') {
+    e.preventDefault();
+    addPeer();
+  }
+  if (e.key === 'Enter' && document.activeElement.id === 'add-ws-input') {
+    e.preventDefault();
+    joinWorkspace();
+  }
+  if (e.key === 'Enter' && document.activeElement.id === 'redeem-input') {
+    e.preventDefault();
+    redeemInvite();
+  }
   // Ctrl+Shift+X = toggle censorship
   if (e.key === 'X' && e.ctrlKey && e.shiftKey) {
     e.preventDefault();
@@ -708,6 +1030,19 @@ document.addEventListener('keydown', function(e) {
   }
 });
 
+// ── Activity ───────────────────────────────────
+// Keeps us from turning away where the OS can't tell the app's idle time;
+// once in a while is enough
+function activity() {
+  var now = Date.now();
+  if (now - lastActivity < 15000) return;
+  lastActivity = now;
+  send({ cmd: 'activity' });
+}
+['keydown', 'mousedown', 'mousemove', 'wheel'].forEach(function(t) {
+  document.addEventListener(t, activity, true);
+});
+
 // ── Censorship ─────────────────────────────────
 function toggleCensor() {
   document.body.classList.toggle('censored');
@@ -730,8 +1065,8 @@ function openModal() {
   peers.forEach(function(p) {
     var d = document.createElement('div');
     d.className = 'm-check';
-    d.innerHTML = '<input type="checkbox" id="gm-' + p.peer_id + '" value="' + p.peer_id + '">' +
-      '<label for="gm-' + p.peer_id + '">' + esc(p.username) + '</label>';
+    d.innerHTML = '<input type="checkbox" id="gm-' + esc(p.peer_id) + '" value="' + esc(p.peer_id) + '">' +
+      '<label for="gm-' + esc(p.peer_id) + '">' + esc(p.username) + '</label>';
     mm.appendChild(d);
   });
   document.getElementById('grp-name').value = '';
@@ -749,11 +1084,67 @@ function createGroup() {
   send({ cmd: 'create_group', name: name, members: mem });
 }
 
+// ── Profile Modal ──────────────────────────────
+function openProfile() {
+  document.getElementById('pf-name').value = myUsername;
+  document.getElementById('pf-status').value = myProfile.status;
+  document.getElementById('pf-presence').value = myProfile.presence;
+  document.getElementById('pf-file').value = '';
+  setPfAvatar(myProfile.avatar);
+  document.getElementById('pf-away-after').value = Math.round(autoAway.after_secs / 60);
+  document.getElementById('pf-away-reply').value = autoAway.reply;
+  document.getElementById('profile-bg').classList.add('vis');
+  document.getElementById('pf-status').focus();
+}
+function closeProfile() { document.getElementById('profile-bg').classList.remove('vis'); }
+function setPfAvatar(url) {
+  pfAvatar = url;
+  var img = document.getElementById('pf-avatar');
+  img.style.visibility = url ? 'visible' : 'hidden';
+  if (url) img.src = url;
+}
+// Cropped to a 64px square JPEG, well under what peers accept
+function pickAvatar(inp) {
+  var f = inp.files[0];
+  if (!f) return;
+  var r = new FileReader();
+  r.onload = function() {
+    var img = new Image();
+    img.onload = function() {
+      var c = document.createElement('canvas');
+      c.width = c.height = 64;
+      var side = Math.min(img.width, img.height);
+      c.getContext('2d').drawImage(img, (img.width - side) / 2, (img.height - side) / 2, side, side, 0, 0, 64, 64);
+      setPfAvatar(c.toDataURL('image/jpeg', 0.8));
+    };
+    img.src = r.result;
+  };
+  r.readAsDataURL(f);
+}
+function saveProfile() {
+  var n = document.getElementById('pf-name').value.trim();
+  if (n && n !== myUsername) send({ cmd: 'set_username', username: n });
+  send({
+    cmd: 'set_profile',
+    status: document.getElementById('pf-status').value.trim(),
+    presence: document.getElementById('pf-presence').value,
+    avatar: pfAvatar
+  });
+  var mins = parseInt(document.getElementById('pf-away-after').value, 10);
+  var secs = isNaN(mins) || mins < 0 ? autoAway.after_secs : mins * 60;
+  var reply = document.getElementById('pf-away-reply').value.trim();
+  if (secs !== autoAway.after_secs || reply !== autoAway.reply) {
+    send({ cmd: 'set_auto_away', after_secs: secs, reply: reply });
+  }
+  closeProfile();
+}
+
 // ── Helpers ────────────────────────────────────
+// Safe in text and in quoted attributes
 function esc(s) {
   var d = document.createElement('span');
   d.textContent = s;
-  return d.innerHTML;
+  return d.innerHTML.replace(/"/g, '&quot;').replace(/'/g, '&#39;');
 }
 function fmtTime(ts) {
   try {
@@ -763,6 +1154,13 @@ function fmtTime(ts) {
     return h + ':' + m;
   } catch(e) { return '--:--'; }
 }
+// Time today, date before that
+function fmtSeen(ts) {
+  var d = new Date(ts);
+  if (isNaN(d)) return '?';
+  if (d.toDateString() === new Date().toDateString()) return fmtTime(ts);
+  return ('0'+d.getDate()).slice(-2) + '/' + ('0'+(d.getMonth()+1)).slice(-2);
+}
 </script>
 </body>
 </html>